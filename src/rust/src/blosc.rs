@@ -0,0 +1,330 @@
+use anyhow::Result;
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+
+const HEADER_SIZE: usize = 16;
+
+const FLAG_SHUFFLE: u8 = 0x01;
+const FLAG_MEMCPYED: u8 = 0x02;
+const FLAG_BITSHUFFLE: u8 = 0x04;
+const FLAG_DONT_SPLIT: u8 = 0x10;
+
+// BloscLZ distances past this take two extra bytes
+const BLOSCLZ_MAX_DISTANCE: usize = 8191;
+
+fn read_u32(data: &[u8], offset: usize) -> Result<usize> {
+    offset
+        .checked_add(4)
+        .and_then(|end| data.get(offset..end))
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .ok_or_else(|| anyhow::anyhow!("Truncated blosc buffer"))
+}
+
+// Reads at most limit bytes, so a small stream cannot inflate without bound
+pub(crate) fn decompress_zstd(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let decoder = ruzstd::StreamingDecoder::new(data)
+        .map_err(|e| anyhow::anyhow!("Invalid zstd stream: {}", e))?;
+    let mut out = Vec::new();
+    decoder.take(limit as u64).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// BloscLZ (c-blosc's default codec): a literal run of ctrl + 1 bytes when the top three
+// bits of ctrl are clear, otherwise a match whose length and distance continue in the
+// following bytes. Returns the number of bytes written.
+fn decompress_blosclz(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let truncated = || anyhow::anyhow!("Truncated blosc blosclz block");
+    let byte = |ip: &mut usize| -> Result<usize> {
+        let value = *input.get(*ip).ok_or_else(truncated)?;
+        *ip += 1;
+        Ok(value as usize)
+    };
+
+    let mut ip = 0;
+    let mut op = 0;
+    let mut ctrl = byte(&mut ip)? & 31;
+    loop {
+        if ctrl >= 32 {
+            let mut len = (ctrl >> 5) - 1;
+            let mut distance = (ctrl & 31) << 8;
+            if len == 6 {
+                loop {
+                    let code = byte(&mut ip)?;
+                    len += code;
+                    if code != 255 {
+                        break;
+                    }
+                }
+            }
+            let code = byte(&mut ip)?;
+            len += 3;
+            distance += code;
+            if code == 255 && distance == (31 << 8) + 255 {
+                distance = (byte(&mut ip)? << 8) + byte(&mut ip)? + BLOSCLZ_MAX_DISTANCE;
+            }
+            distance += 1;
+
+            if distance > op || op + len > output.len() {
+                return Err(anyhow::anyhow!("Invalid blosc blosclz block"));
+            }
+            // Byte by byte, as the match may overlap what it is producing
+            for i in op..op + len {
+                output[i] = output[i - distance];
+            }
+            op += len;
+        } else {
+            let len = ctrl + 1;
+            let literal = input.get(ip..ip + len).ok_or_else(truncated)?;
+            output
+                .get_mut(op..op + len)
+                .ok_or_else(|| anyhow::anyhow!("Invalid blosc blosclz block"))?
+                .copy_from_slice(literal);
+            ip += len;
+            op += len;
+        }
+
+        if ip >= input.len() {
+            return Ok(op);
+        }
+        ctrl = byte(&mut ip)?;
+    }
+}
+
+fn decompress_stream(compressor: u8, input: &[u8], output: &mut [u8]) -> Result<()> {
+    // One byte more than fits is enough to tell an oversized stream apart
+    let limit = output.len() as u64 + 1;
+    let written = match compressor {
+        0 => decompress_blosclz(input, output)?,
+        // LZ4 and LZ4HC share the block format
+        1 => lz4_flex::block::decompress_into(input, output)
+            .map_err(|e| anyhow::anyhow!("Invalid blosc LZ4 block: {}", e))?,
+        3 => {
+            let mut buf = Vec::with_capacity(output.len());
+            ZlibDecoder::new(input).take(limit).read_to_end(&mut buf)?;
+            copy_exact(&buf, output)?
+        },
+        4 => copy_exact(&decompress_zstd(input, limit as usize)?, output)?,
+        2 => return Err(anyhow::anyhow!("Blosc snappy compression is not supported")),
+        _ => return Err(anyhow::anyhow!("Unknown blosc compressor: {}", compressor)),
+    };
+
+    if written != output.len() {
+        return Err(anyhow::anyhow!("Blosc block decompressed to the wrong size"));
+    }
+    Ok(())
+}
+
+fn copy_exact(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    if src.len() != dst.len() {
+        return Err(anyhow::anyhow!("Blosc block decompressed to the wrong size"));
+    }
+    dst.copy_from_slice(src);
+    Ok(src.len())
+}
+
+fn unshuffle(block: &[u8], typesize: usize) -> Vec<u8> {
+    // Trailing bytes that do not fill an element are stored unshuffled
+    let elements = block.len() / typesize;
+    let mut out = block.to_vec();
+    for j in 0..elements {
+        for i in 0..typesize {
+            out[j * typesize + i] = block[i * elements + j];
+        }
+    }
+    out
+}
+
+// Inverse of bitshuffle's bit transpose: bit k of byte j of element i is bit i of
+// row j * 8 + k, and each row holds one bit of every element, least significant first
+fn bit_unshuffle(block: &[u8], typesize: usize, elements: usize) -> Vec<u8> {
+    let mut out = block.to_vec();
+    let row_len = elements / 8;
+    out[..elements * typesize].fill(0);
+    for i in 0..elements {
+        for j in 0..typesize {
+            let mut value = 0u8;
+            for k in 0..8 {
+                let bit = (block[(j * 8 + k) * row_len + i / 8] >> (i % 8)) & 1;
+                value |= bit << k;
+            }
+            out[i * typesize + j] = value;
+        }
+    }
+    out
+}
+
+// Blosc format version 2 only bitshuffles blocks of a whole number of bytes of bits;
+// later versions shuffle as many elements as they can and leave the rest as stored
+fn bit_unshuffle_block(block: &[u8], typesize: usize, version: u8) -> Vec<u8> {
+    let elements = block.len() / typesize;
+    match (version, elements % 8) {
+        (2, 0) => bit_unshuffle(block, typesize, elements),
+        (2, _) => block.to_vec(),
+        _ => bit_unshuffle(block, typesize, elements - elements % 8),
+    }
+}
+
+// Buffers that claim to decompress to more than max_len bytes are rejected up front
+pub fn decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    if data.len() < HEADER_SIZE {
+        return Err(anyhow::anyhow!("Truncated blosc buffer"));
+    }
+
+    let version = data[0];
+    let flags = data[2];
+    let typesize = data[3].max(1) as usize;
+    let nbytes = read_u32(data, 4)?;
+    let blocksize = read_u32(data, 8)?;
+    let cbytes = read_u32(data, 12)?;
+
+    if cbytes > data.len() {
+        return Err(anyhow::anyhow!("Truncated blosc buffer"));
+    }
+    if nbytes > max_len {
+        return Err(anyhow::anyhow!("Blosc buffer holds {} bytes, more than the {} expected", nbytes, max_len));
+    }
+
+    if flags & FLAG_MEMCPYED != 0 {
+        return data
+            .get(HEADER_SIZE..)
+            .and_then(|rest| rest.get(..nbytes))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow::anyhow!("Truncated blosc buffer"));
+    }
+    if blocksize == 0 {
+        return Err(anyhow::anyhow!("Invalid blosc block size"));
+    }
+
+    let compressor = flags >> 5;
+    let shuffled = flags & FLAG_SHUFFLE != 0 && typesize > 1;
+    let bit_shuffled = flags & FLAG_BITSHUFFLE != 0 && !shuffled;
+    let blocks = nbytes.div_ceil(blocksize);
+
+    let mut out = vec![0u8; nbytes];
+
+    for block in 0..blocks {
+        let offset = block.checked_mul(4).and_then(|o| o.checked_add(HEADER_SIZE));
+        let start = read_u32(data, offset.ok_or_else(|| anyhow::anyhow!("Truncated blosc buffer"))?)?;
+        let block_len = blocksize.min(nbytes - block * blocksize);
+        let leftover = block_len < blocksize;
+
+        let streams = if flags & FLAG_DONT_SPLIT == 0 && !leftover && block_len >= typesize { typesize } else { 1 };
+        let stream_len = block_len / streams;
+
+        let mut decoded = vec![0u8; block_len];
+        let mut pos = start;
+        for stream in decoded.chunks_exact_mut(stream_len).take(streams) {
+            let compressed_len = read_u32(data, pos)?;
+            let input = pos
+                .checked_add(4)
+                .and_then(|begin| Some(begin..begin.checked_add(compressed_len)?))
+                .and_then(|range| data.get(range))
+                .ok_or_else(|| anyhow::anyhow!("Truncated blosc buffer"))?;
+            pos += 4 + compressed_len;
+
+            if compressed_len == stream_len {
+                stream.copy_from_slice(input);
+            } else {
+                decompress_stream(compressor, input, stream)?;
+            }
+        }
+
+        if shuffled {
+            decoded = unshuffle(&decoded, typesize);
+        } else if bit_shuffled && block_len >= typesize {
+            decoded = bit_unshuffle_block(&decoded, typesize, version);
+        }
+
+        out[block * blocksize..block * blocksize + block_len].copy_from_slice(&decoded);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags: u8, typesize: u8, nbytes: usize, blocksize: usize, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![2, 1, flags, typesize];
+        out.extend_from_slice(&(nbytes as u32).to_le_bytes());
+        out.extend_from_slice(&(blocksize as u32).to_le_bytes());
+        out.extend_from_slice(&((HEADER_SIZE + body.len()) as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    // Byte-shuffles each block and stores its streams LZ4-compressed, as c-blosc does
+    fn lz4_shuffled(raw: &[u8], typesize: usize, blocksize: usize) -> Vec<u8> {
+        let blocks = raw.len().div_ceil(blocksize);
+        let mut starts = Vec::new();
+        let mut body = Vec::new();
+        for block in raw.chunks(blocksize) {
+            let n = block.len() / typesize;
+            let mut shuffled = block.to_vec();
+            for j in 0..n {
+                for i in 0..typesize {
+                    shuffled[i * n + j] = block[j * typesize + i];
+                }
+            }
+            starts.push((HEADER_SIZE + 4 * blocks + body.len()) as u32);
+            let streams = if block.len() < blocksize { 1 } else { typesize };
+            for stream in shuffled.chunks(shuffled.len() / streams) {
+                let compressed = lz4_flex::block::compress(stream);
+                body.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+                body.extend_from_slice(&compressed);
+            }
+        }
+        let offsets: Vec<u8> = starts.iter().flat_map(|s| s.to_le_bytes()).collect();
+        header(FLAG_SHUFFLE | (1 << 5), typesize as u8, raw.len(), blocksize, &[offsets, body].concat())
+    }
+
+    #[test]
+    fn lz4_with_shuffle_and_leftover_block() {
+        let raw: Vec<u8> = (0u16..20).flat_map(|v| (v * 300).to_le_bytes()).collect();
+        assert_eq!(decompress(&lz4_shuffled(&raw, 2, 16), raw.len()).unwrap(), raw);
+    }
+
+    #[test]
+    fn blosclz_literals_and_overlapping_match() {
+        // A 3-byte literal, a 6-byte match at distance 3, then one more literal
+        let stream = [2, b'a', b'b', b'c', 128, 2, 0, b'x'];
+        let mut body = (HEADER_SIZE as u32 + 4).to_le_bytes().to_vec();
+        body.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+        body.extend_from_slice(&stream);
+        let data = header(0, 1, 10, 10, &body);
+        assert_eq!(decompress(&data, 10).unwrap(), b"abcabcabcx");
+    }
+
+    #[test]
+    fn bitshuffle_leaves_trailing_elements() {
+        // 20 two-byte elements: the first 16 are bit-transposed, the last 4 stored as is
+        let raw: Vec<u8> = (0u16..20).flat_map(|v| (v * 3001).to_le_bytes()).collect();
+        let mut shuffled = raw.clone();
+        shuffled[..32].fill(0);
+        for i in 0..16 {
+            for j in 0..2 {
+                for k in 0..8 {
+                    shuffled[(j * 8 + k) * 2 + i / 8] |= ((raw[i * 2 + j] >> k) & 1) << (i % 8);
+                }
+            }
+        }
+        let mut body = (HEADER_SIZE as u32 + 4).to_le_bytes().to_vec();
+        body.extend_from_slice(&(shuffled.len() as u32).to_le_bytes());
+        body.extend_from_slice(&shuffled);
+        let mut data = header(FLAG_BITSHUFFLE | FLAG_DONT_SPLIT, 2, 40, 40, &body);
+        data[0] = 3;
+        assert_eq!(decompress(&data, 40).unwrap(), raw);
+    }
+
+    #[test]
+    fn bad_lengths_are_errors() {
+        let raw = vec![5u8; 64];
+        let data = lz4_shuffled(&raw, 1, 64);
+        assert!(decompress(&data, 32).is_err());
+
+        let mut data = data;
+        data[HEADER_SIZE + 4..HEADER_SIZE + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress(&data, 64).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+// Source of Zarr metadata documents and chunks, addressed by their key within the store
+pub trait ChunkStore {
+    // Missing keys are not an error: absent chunks take the array's fill value
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    entries: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: &str, value: Vec<u8>) {
+        self.entries.insert(key.trim_start_matches('/').to_string(), value);
+    }
+}
+
+impl ChunkStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries.get(key).cloned())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileSystemStore {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSystemStore {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ChunkStore for FileSystemStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.root.join(key);
+        match std::fs::read(&path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+}
+
+// Calls back into JavaScript with the key; the callback returns a Uint8Array, or null/undefined when missing
+pub struct JsChunkStore {
+    callback: js_sys::Function,
+}
+
+impl JsChunkStore {
+    pub fn new(callback: js_sys::Function) -> Self {
+        Self { callback }
+    }
+}
+
+impl ChunkStore for JsChunkStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = self.callback
+            .call1(&JsValue::NULL, &JsValue::from_str(key))
+            .map_err(|e| anyhow::anyhow!("Chunk callback failed for {}: {:?}", key, e))?;
+
+        if value.is_null() || value.is_undefined() {
+            return Ok(None);
+        }
+
+        Ok(Some(js_sys::Uint8Array::new(&value).to_vec()))
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::Read;
+use flate2::read::DeflateDecoder;
+use log::{debug, info};
+
+// (group, element)
+type Tag = (u16, u16);
+
+const IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+const EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+const DEFLATED_EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1.99";
+
+const TRANSFER_SYNTAX: Tag = (0x0002, 0x0010);
+const SAMPLES_PER_PIXEL: Tag = (0x0028, 0x0002);
+const NUMBER_OF_FRAMES: Tag = (0x0028, 0x0008);
+const ROWS: Tag = (0x0028, 0x0010);
+const COLUMNS: Tag = (0x0028, 0x0011);
+const PIXEL_SPACING: Tag = (0x0028, 0x0030);
+const BITS_ALLOCATED: Tag = (0x0028, 0x0100);
+const BITS_STORED: Tag = (0x0028, 0x0101);
+const PIXEL_REPRESENTATION: Tag = (0x0028, 0x0103);
+const RESCALE_INTERCEPT: Tag = (0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = (0x0028, 0x1053);
+const SLICE_THICKNESS: Tag = (0x0018, 0x0050);
+const INSTANCE_NUMBER: Tag = (0x0020, 0x0013);
+const IMAGE_POSITION_PATIENT: Tag = (0x0020, 0x0032);
+const IMAGE_ORIENTATION_PATIENT: Tag = (0x0020, 0x0037);
+const PIXEL_DATA: Tag = (0x7FE0, 0x0010);
+
+const ITEM: Tag = (0xFFFE, 0xE000);
+const ITEM_DELIMITATION: Tag = (0xFFFE, 0xE00D);
+const SEQUENCE_DELIMITATION: Tag = (0xFFFE, 0xE0DD);
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+
+// A deflated data set inflates to at most the largest pixel data plus room for its other elements
+const MAX_INFLATED: usize = crate::MAX_SIZE * 4 + (1 << 20);
+
+pub struct DicomSeries {
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    pub directions: [[f32; 3]; 3],
+}
+
+struct DicomSlice {
+    pixels: Vec<f32>,
+    width: usize,
+    height: usize,
+    frames: usize,
+    position: Option<[f32; 3]>,
+    orientation: Option<[f32; 6]>,
+    instance_number: Option<i32>,
+    pixel_spacing: Option<[f32; 2]>,
+    slice_thickness: Option<f32>,
+}
+
+struct DataSet<'a> {
+    elements: HashMap<Tag, &'a [u8]>,
+}
+
+impl DataSet<'_> {
+    fn u16(&self, tag: Tag) -> Option<u16> {
+        self.elements
+            .get(&tag)
+            .filter(|v| v.len() >= 2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+    }
+
+    fn string(&self, tag: Tag) -> Option<String> {
+        self.elements.get(&tag).map(|v| {
+            String::from_utf8_lossy(v)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string()
+        })
+    }
+
+    fn numbers(&self, tag: Tag) -> Option<Vec<f32>> {
+        let text = self.string(tag)?;
+        text.split('\\')
+            .map(|v| v.trim().parse::<f32>().ok())
+            .collect()
+    }
+
+    fn number(&self, tag: Tag) -> Option<f32> {
+        self.numbers(tag).and_then(|v| v.first().copied())
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    explicit_vr: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| anyhow::anyhow!("Unexpected end of DICOM data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    // Returns the tag and its value; undefined-length values and items are skipped and yield None
+    fn next_element(&mut self) -> Result<(Tag, Option<&'a [u8]>)> {
+        let tag = (self.read_u16()?, self.read_u16()?);
+
+        // Item and delimitation tags never carry a VR
+        if tag.0 == 0xFFFE {
+            self.read_u32()?;
+            return Ok((tag, None));
+        }
+
+        let explicit = self.explicit_vr || tag.0 == 0x0002;
+        let len = if explicit {
+            let vr = self.take(2)?;
+            match vr {
+                b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"UC" | b"UN" | b"UR" | b"UT"
+                | b"SV" | b"UV" => {
+                    self.take(2)?;
+                    self.read_u32()?
+                },
+                _ => self.read_u16()? as u32,
+            }
+        } else {
+            self.read_u32()?
+        };
+
+        if len == UNDEFINED_LENGTH {
+            if tag == PIXEL_DATA {
+                return Err(anyhow::anyhow!("Encapsulated (compressed) DICOM pixel data is not supported"));
+            }
+            self.skip_sequence()?;
+            return Ok((tag, None));
+        }
+
+        Ok((tag, Some(self.take(len as usize)?)))
+    }
+
+    fn skip_sequence(&mut self) -> Result<()> {
+        loop {
+            let tag = (self.read_u16()?, self.read_u16()?);
+            let len = self.read_u32()?;
+
+            if tag == SEQUENCE_DELIMITATION {
+                return Ok(());
+            }
+            if tag != ITEM {
+                return Err(anyhow::anyhow!("Malformed DICOM sequence"));
+            }
+
+            if len == UNDEFINED_LENGTH {
+                loop {
+                    let (tag, _) = self.next_element()?;
+                    if tag == ITEM_DELIMITATION {
+                        break;
+                    }
+                }
+            } else {
+                self.take(len as usize)?;
+            }
+        }
+    }
+}
+
+pub fn is_dicom(data: &[u8]) -> bool {
+    data.len() >= 132 && &data[128..132] == b"DICM"
+}
+
+// Reads the file meta group and returns the transfer syntax and where the data set starts
+fn parse_meta(data: &[u8]) -> Result<(String, usize)> {
+    if !is_dicom(data) {
+        return Err(anyhow::anyhow!("Not a DICOM Part 10 file"));
+    }
+
+    let mut elements = HashMap::new();
+    let mut parser = Parser { data, pos: 132, explicit_vr: true };
+
+    // File meta information is always explicit VR little endian
+    while parser.pos + 4 <= data.len() {
+        let group = u16::from_le_bytes([data[parser.pos], data[parser.pos + 1]]);
+        if group != 0x0002 {
+            break;
+        }
+        let (tag, value) = parser.next_element()?;
+        if let Some(value) = value {
+            elements.insert(tag, value);
+        }
+    }
+
+    let transfer_syntax = DataSet { elements }
+        .string(TRANSFER_SYNTAX)
+        .unwrap_or_else(|| IMPLICIT_VR_LE.to_string());
+
+    Ok((transfer_syntax, parser.pos))
+}
+
+fn parse_dataset(body: &[u8], explicit_vr: bool) -> Result<DataSet<'_>> {
+    let mut elements = HashMap::new();
+    let mut parser = Parser { data: body, pos: 0, explicit_vr };
+
+    while !parser.at_end() {
+        let (tag, value) = parser.next_element()?;
+        if let Some(value) = value {
+            elements.insert(tag, value);
+        }
+        if tag == PIXEL_DATA {
+            break;
+        }
+    }
+
+    Ok(DataSet { elements })
+}
+
+fn decode_pixels(dataset: &DataSet, count: usize) -> Result<Vec<f32>> {
+    let pixel_data = dataset.elements
+        .get(&PIXEL_DATA)
+        .ok_or_else(|| anyhow::anyhow!("DICOM file has no pixel data"))?;
+
+    let bits_allocated = dataset.u16(BITS_ALLOCATED).unwrap_or(16) as u32;
+    let bits_stored = dataset.u16(BITS_STORED).map(u32::from).unwrap_or(bits_allocated);
+    let signed = dataset.u16(PIXEL_REPRESENTATION) == Some(1);
+
+    let bytes_per_sample = match bits_allocated {
+        8 => 1,
+        16 => 2,
+        32 => 4,
+        _ => return Err(anyhow::anyhow!("Unsupported DICOM BitsAllocated: {}", bits_allocated)),
+    };
+
+    if pixel_data.len() < count * bytes_per_sample {
+        return Err(anyhow::anyhow!("DICOM pixel data is shorter than Rows x Columns"));
+    }
+
+    let bits_stored = bits_stored.clamp(1, bits_allocated);
+    let mask = if bits_stored == 32 { u32::MAX } else { (1u32 << bits_stored) - 1 };
+    let sign_bit = 1u32 << (bits_stored - 1);
+
+    let pixels = pixel_data
+        .chunks_exact(bytes_per_sample)
+        .take(count)
+        .map(|chunk| {
+            let raw = match bytes_per_sample {
+                1 => chunk[0] as u32,
+                2 => u16::from_le_bytes([chunk[0], chunk[1]]) as u32,
+                _ => u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+            } & mask;
+
+            if signed && raw & sign_bit != 0 {
+                (raw as i64 - (mask as i64 + 1)) as f32
+            } else {
+                raw as f32
+            }
+        })
+        .collect();
+
+    Ok(pixels)
+}
+
+fn load_slice(data: &[u8], max_inflated: usize) -> Result<DicomSlice> {
+    let (transfer_syntax, body_start) = parse_meta(data)?;
+
+    let inflated;
+    let mut capped = false;
+    let (explicit_vr, body) = match transfer_syntax.as_str() {
+        IMPLICIT_VR_LE => (false, &data[body_start..]),
+        EXPLICIT_VR_LE => (true, &data[body_start..]),
+        DEFLATED_EXPLICIT_VR_LE => {
+            let mut buf = Vec::new();
+            DeflateDecoder::new(&data[body_start..]).take(max_inflated as u64).read_to_end(&mut buf)?;
+            capped = buf.len() == max_inflated;
+            inflated = buf;
+            (true, &inflated[..])
+        },
+        other => return Err(anyhow::anyhow!("Unsupported DICOM transfer syntax: {}", other)),
+    };
+    // A data set cut off at the cap is only an error if the pixel data did not fit
+    let too_large = |e: anyhow::Error| {
+        if capped {
+            anyhow::anyhow!("Deflated DICOM data set inflates past {} bytes", max_inflated)
+        } else {
+            e
+        }
+    };
+
+    let dataset = parse_dataset(body, explicit_vr).map_err(too_large)?;
+
+    let height = dataset.u16(ROWS).ok_or_else(|| anyhow::anyhow!("DICOM file missing Rows"))? as usize;
+    let width = dataset.u16(COLUMNS).ok_or_else(|| anyhow::anyhow!("DICOM file missing Columns"))? as usize;
+
+    if dataset.u16(SAMPLES_PER_PIXEL).unwrap_or(1) != 1 {
+        return Err(anyhow::anyhow!("Only single-sample (grayscale) DICOM images are supported"));
+    }
+
+    let frames = dataset.number(NUMBER_OF_FRAMES).map(|n| n.max(1.0) as usize).unwrap_or(1);
+    let count = width
+        .checked_mul(height)
+        .and_then(|wh| wh.checked_mul(frames))
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+
+    let mut pixels = decode_pixels(&dataset, count).map_err(too_large)?;
+
+    let slope = dataset.number(RESCALE_SLOPE).unwrap_or(1.0);
+    let intercept = dataset.number(RESCALE_INTERCEPT).unwrap_or(0.0);
+    if slope != 1.0 || intercept != 0.0 {
+        for v in pixels.iter_mut() {
+            *v = *v * slope + intercept;
+        }
+    }
+
+    let position = dataset.numbers(IMAGE_POSITION_PATIENT)
+        .filter(|v| v.len() == 3)
+        .map(|v| [v[0], v[1], v[2]]);
+    let orientation = dataset.numbers(IMAGE_ORIENTATION_PATIENT)
+        .filter(|v| v.len() == 6)
+        .map(|v| [v[0], v[1], v[2], v[3], v[4], v[5]]);
+    let pixel_spacing = dataset.numbers(PIXEL_SPACING)
+        .filter(|v| v.len() == 2)
+        .map(|v| [v[0], v[1]]);
+
+    Ok(DicomSlice {
+        pixels,
+        width,
+        height,
+        frames,
+        position,
+        orientation,
+        instance_number: dataset.number(INSTANCE_NUMBER).map(|n| n as i32),
+        pixel_spacing,
+        slice_thickness: dataset.number(SLICE_THICKNESS),
+    })
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn load_dicom_series_from_memory(files: &[&[u8]]) -> Result<DicomSeries> {
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No DICOM files provided"));
+    }
+
+    let mut slices = files
+        .iter()
+        .map(|data| load_slice(data, MAX_INFLATED))
+        .collect::<Result<Vec<_>>>()?;
+
+    let width = slices[0].width;
+    let height = slices[0].height;
+    for slice in &slices[1..] {
+        if slice.width != width || slice.height != height {
+            return Err(anyhow::anyhow!("Inconsistent slice dimensions"));
+        }
+    }
+
+    let orientation = slices[0].orientation.unwrap_or([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    let row = [orientation[0], orientation[1], orientation[2]];
+    let column = [orientation[3], orientation[4], orientation[5]];
+    let normal = cross(row, column);
+
+    // Prefer geometric ordering along the slice normal, fall back to InstanceNumber
+    let mut slice_positions = None;
+    if slices.iter().all(|s| s.position.is_some()) {
+        slices.sort_by(|a, b| {
+            let da = dot(a.position.unwrap_or_default(), normal);
+            let db = dot(b.position.unwrap_or_default(), normal);
+            da.total_cmp(&db)
+        });
+        slice_positions = Some(slices
+            .iter()
+            .map(|s| dot(s.position.unwrap_or_default(), normal))
+            .collect::<Vec<_>>());
+        debug!("Sorted DICOM slices by ImagePositionPatient");
+    } else if slices.iter().all(|s| s.instance_number.is_some()) {
+        slices.sort_by_key(|s| s.instance_number);
+        debug!("Sorted DICOM slices by InstanceNumber");
+    }
+
+    let slice_spacing = slice_positions
+        .filter(|p| p.len() > 1)
+        .map(|p| {
+            let mut gaps: Vec<f32> = p.windows(2).map(|w| w[1] - w[0]).collect();
+            gaps.sort_by(f32::total_cmp);
+            gaps[gaps.len() / 2]
+        })
+        .filter(|&gap| gap > 0.0)
+        .or(slices[0].slice_thickness.filter(|&t| t > 0.0))
+        .unwrap_or(1.0);
+
+    let [row_spacing, column_spacing] = slices[0].pixel_spacing.unwrap_or([1.0, 1.0]);
+    let depth: usize = slices.iter().map(|s| s.frames).sum();
+
+    let mut data = Vec::with_capacity(width * height * depth);
+    for slice in &slices {
+        data.extend_from_slice(&slice.pixels);
+    }
+
+    info!("Loaded DICOM series: {}x{}x{} from {} files", width, height, depth, files.len());
+    debug!("DICOM spacing: {} x {} x {}", column_spacing, row_spacing, slice_spacing);
+
+    Ok(DicomSeries {
+        data,
+        dimensions: (width, height, depth),
+        spacing: [column_spacing, row_spacing, slice_spacing],
+        origin: slices[0].position.unwrap_or_default(),
+        directions: [row, column, normal],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(explicit: bool, group: u16, tag: u16, vr: &[u8; 2], value: &[u8]) -> Vec<u8> {
+        let mut value = value.to_vec();
+        if value.len() % 2 == 1 {
+            value.push(b' ');
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&group.to_le_bytes());
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        if !explicit {
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        } else if [b"OB", b"OW", b"SQ", b"UN"].contains(&vr) {
+            bytes.extend_from_slice(vr);
+            bytes.extend_from_slice(&[0, 0]);
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        } else {
+            bytes.extend_from_slice(vr);
+            bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        }
+        bytes.extend_from_slice(&value);
+        bytes
+    }
+
+    // 3x2 CT slice at height z with 12-bit signed pixels base, base + 1, ...
+    fn slice(explicit: bool, z: f32, instance: i32, base: i16) -> Vec<u8> {
+        let mut file = vec![0u8; 128];
+        file.extend_from_slice(b"DICM");
+        let syntax: &[u8] = if explicit { b"1.2.840.10008.1.2.1\0" } else { b"1.2.840.10008.1.2\0" };
+        file.extend(element(true, 0x0002, 0x0010, b"UI", syntax));
+
+        // An undefined-length sequence with one undefined-length item, which must be skipped
+        file.extend_from_slice(&0x0008u16.to_le_bytes());
+        file.extend_from_slice(&0x1140u16.to_le_bytes());
+        if explicit {
+            file.extend_from_slice(b"SQ\0\0");
+        }
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        file.extend_from_slice(&[0xFE, 0xFF, 0x00, 0xE0]);
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        file.extend(element(explicit, 0x0008, 0x1150, b"UI", b"1.2.3"));
+        file.extend_from_slice(&[0xFE, 0xFF, 0x0D, 0xE0, 0, 0, 0, 0]);
+        file.extend_from_slice(&[0xFE, 0xFF, 0xDD, 0xE0, 0, 0, 0, 0]);
+
+        file.extend(element(explicit, 0x0020, 0x0013, b"IS", instance.to_string().as_bytes()));
+        file.extend(element(explicit, 0x0020, 0x0032, b"DS", format!("0\\0\\{}", z).as_bytes()));
+        file.extend(element(explicit, 0x0020, 0x0037, b"DS", b"1\\0\\0\\0\\1\\0"));
+        file.extend(element(explicit, 0x0028, 0x0002, b"US", &1u16.to_le_bytes()));
+        file.extend(element(explicit, 0x0028, 0x0010, b"US", &2u16.to_le_bytes()));
+        file.extend(element(explicit, 0x0028, 0x0011, b"US", &3u16.to_le_bytes()));
+        file.extend(element(explicit, 0x0028, 0x0030, b"DS", b"0.5\\0.25"));
+        file.extend(element(explicit, 0x0028, 0x0100, b"US", &16u16.to_le_bytes()));
+        file.extend(element(explicit, 0x0028, 0x0101, b"US", &12u16.to_le_bytes()));
+        file.extend(element(explicit, 0x0028, 0x0103, b"US", &1u16.to_le_bytes()));
+        file.extend(element(explicit, 0x0028, 0x1052, b"DS", b"-1024"));
+        file.extend(element(explicit, 0x0028, 0x1053, b"DS", b"2"));
+
+        let pixels: Vec<u8> = (0..6).flat_map(|i| ((base + i) as u16 & 0x0FFF).to_le_bytes()).collect();
+        file.extend(element(explicit, 0x7FE0, 0x0010, b"OW", &pixels));
+        file
+    }
+
+    #[test]
+    fn series_is_sorted_by_position() {
+        let top = slice(true, 5.0, 1, -3);
+        let middle = slice(false, 2.5, 2, 10);
+        let bottom = slice(true, 0.0, 3, 100);
+        let series = load_dicom_series_from_memory(&[&top, &middle, &bottom]).unwrap();
+
+        assert_eq!(series.dimensions, (3, 2, 3));
+        assert_eq!(series.spacing, [0.25, 0.5, 2.5]);
+        assert_eq!(series.origin, [0.0, 0.0, 0.0]);
+        // Rescaled, with the 12-bit values sign extended
+        assert_eq!(series.data[0], 100.0 * 2.0 - 1024.0);
+        assert_eq!(series.data[6], 10.0 * 2.0 - 1024.0);
+        assert_eq!(series.data[12], -3.0 * 2.0 - 1024.0);
+    }
+
+    #[test]
+    fn mismatched_slices_are_rejected() {
+        let mut other = slice(true, 2.5, 2, 0);
+        let width = other.windows(4).position(|w| w == [0x28, 0x00, 0x11, 0x00]).unwrap();
+        other[width + 8] = 4;
+        assert!(load_dicom_series_from_memory(&[&slice(true, 0.0, 1, 0), &other]).is_err());
+        assert!(load_dicom_series_from_memory(&[]).is_err());
+    }
+
+    #[test]
+    fn deflated_data_sets_are_inflated_up_to_a_cap() {
+        use std::io::Write;
+        use flate2::write::DeflateEncoder;
+        use flate2::Compression;
+
+        // The plain slice's meta group is the 160 bytes up to the end of its transfer syntax
+        let plain = slice(true, 0.0, 1, 0);
+        let deflated = |body: &[u8]| {
+            let mut file = plain[..132].to_vec();
+            file.extend(element(true, 0x0002, 0x0010, b"UI", b"1.2.840.10008.1.2.1.99"));
+            let mut encoder = DeflateEncoder::new(file, Compression::default());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        };
+        let body = &plain[160..];
+
+        let series = load_dicom_series_from_memory(&[&deflated(body)]).unwrap();
+        assert_eq!(series.data, load_dicom_series_from_memory(&[&plain]).unwrap().data);
+
+        // Anything past the pixel data is cut off without harm, but the pixel data itself must fit
+        let mut padded = body.to_vec();
+        padded.extend(vec![0; 1 << 20]);
+        assert!(load_slice(&deflated(&padded), body.len()).is_ok());
+        let error = load_slice(&deflated(&padded), body.len() - 2).err().unwrap();
+        assert!(error.to_string().contains("inflates past"), "{}", error);
+    }
+}
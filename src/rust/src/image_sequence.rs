@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::cmp::Ordering;
+use image::DynamicImage;
+use log::{debug, info};
+use crate::sample::SampleType;
+use crate::tiff_loader::{self, ImageInfo};
+use crate::voxels::Voxels;
+
+// Compares digit runs by numeric value so "slice2" sorts before "slice10"
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut left = String::new();
+                while let Some(c) = a.next_if(char::is_ascii_digit) {
+                    left.push(c);
+                }
+                let mut right = String::new();
+                while let Some(c) = b.next_if(char::is_ascii_digit) {
+                    right.push(c);
+                }
+
+                let left_trimmed = left.trim_start_matches('0');
+                let right_trimmed = right.trim_start_matches('0');
+                let ordering = left_trimmed
+                    .len()
+                    .cmp(&right_trimmed.len())
+                    .then_with(|| left_trimmed.cmp(right_trimmed))
+                    .then_with(|| left.len().cmp(&right.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            },
+        }
+    }
+}
+
+pub fn is_image(data: &[u8]) -> bool {
+    image::guess_format(data).is_ok()
+}
+
+fn decode_image(image: DynamicImage) -> Result<ImageInfo> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+
+    let (values, sample_type, samples) = match image {
+        DynamicImage::ImageLuma8(img) => (Voxels::U8(img.into_raw()), SampleType::U8, 1),
+        DynamicImage::ImageLumaA8(img) => (Voxels::U8(img.into_raw()), SampleType::U8, 2),
+        DynamicImage::ImageRgb8(img) => (Voxels::U8(img.into_raw()), SampleType::U8, 3),
+        DynamicImage::ImageRgba8(img) => (Voxels::U8(img.into_raw()), SampleType::U8, 4),
+        DynamicImage::ImageLuma16(img) => (Voxels::U16(img.into_raw()), SampleType::U16, 1),
+        DynamicImage::ImageLumaA16(img) => (Voxels::U16(img.into_raw()), SampleType::U16, 2),
+        DynamicImage::ImageRgb16(img) => (Voxels::U16(img.into_raw()), SampleType::U16, 3),
+        DynamicImage::ImageRgba16(img) => (Voxels::U16(img.into_raw()), SampleType::U16, 4),
+        DynamicImage::ImageRgb32F(img) => (Voxels::F32(img.into_raw()), SampleType::F32, 3),
+        DynamicImage::ImageRgba32F(img) => (Voxels::F32(img.into_raw()), SampleType::F32, 4),
+        other => return Err(anyhow::anyhow!("Unsupported image color type: {:?}", other.color())),
+    };
+
+    tiff_loader::split_samples(values, sample_type, samples, width, height)
+}
+
+pub fn load_image_sequence(files: &[(&str, &[u8])]) -> Result<Vec<ImageInfo>> {
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No images provided"));
+    }
+
+    let mut files = files.to_vec();
+    files.sort_by(|a, b| natural_cmp(a.0, b.0));
+
+    let mut slices: Vec<ImageInfo> = Vec::with_capacity(files.len());
+
+    for (name, data) in &files {
+        let image = image::load_from_memory(data)
+            .map_err(|e| anyhow::anyhow!("Failed to decode {}: {}", name, e))?;
+        let slice = decode_image(image)
+            .map_err(|e| anyhow::anyhow!("{}: {}", name, e))?;
+
+        if let Some(first) = slices.first() {
+            if slice.width != first.width || slice.height != first.height {
+                return Err(anyhow::anyhow!(
+                    "{} is {}x{}, expected {}x{}",
+                    name, slice.width, slice.height, first.width, first.height
+                ));
+            }
+            if slice.sample_type != first.sample_type || slice.channels != first.channels {
+                return Err(anyhow::anyhow!(
+                    "{} has {} {:?} channels, expected {} {:?} channels",
+                    name, slice.channels, slice.sample_type, first.channels, first.sample_type
+                ));
+            }
+        }
+
+        debug!("Decoded {} ({}x{})", name, slice.width, slice.height);
+        slices.push(slice);
+    }
+
+    info!("Loaded image sequence of {} slices", slices.len());
+    Ok(slices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use image::{ImageBuffer, ImageOutputFormat, Luma};
+    use crate::voxels::Voxels;
+
+    fn png(value: u16, width: u32) -> Vec<u8> {
+        let pixels = ImageBuffer::<Luma<u16>, _>::from_raw(width, 2, vec![value; width as usize * 2]).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::ImageLuma16(pixels).write_to(&mut out, ImageOutputFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn slices_are_natural_sorted() {
+        let (first, second, third) = (png(1000, 2), png(2000, 2), png(3000, 2));
+        let files = [("slice10.png", &third[..]), ("slice2.png", &second[..]), ("slice1.png", &first[..])];
+
+        let slices = load_image_sequence(&files).unwrap();
+        let values: Vec<Voxels> = slices.into_iter().map(|slice| slice.data).collect();
+        assert_eq!(values, vec![Voxels::U16(vec![1000; 4]), Voxels::U16(vec![2000; 4]), Voxels::U16(vec![3000; 4])]);
+        assert_eq!(natural_cmp("img007", "img10"), Ordering::Less);
+    }
+
+    #[test]
+    fn mismatched_slice_is_named() {
+        let (first, other) = (png(1, 2), png(1, 3));
+        let error = load_image_sequence(&[("a1.png", &first), ("a2.png", &other)]).err().unwrap();
+        assert!(error.to_string().contains("a2.png"), "{}", error);
+    }
+}
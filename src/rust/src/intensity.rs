@@ -0,0 +1,144 @@
+use wasm_bindgen::prelude::*;
+use crate::voxels::VoxelSlice;
+
+// Percentiles used for the robust range, which ignores hot pixels and empty background
+pub const LOW_PERCENTILE: f32 = 0.5;
+pub const HIGH_PERCENTILE: f32 = 99.5;
+
+// Float data is binned this finely to find its percentiles
+const FLOAT_BINS: usize = 4096;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangeMode {
+    // Smallest to largest value in the channel
+    Data,
+    // LOW_PERCENTILE to HIGH_PERCENTILE of the channel
+    Percentile,
+    // Full range of the sample type (the data range for floats)
+    Nominal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IntensityStats {
+    pub data_range: (f32, f32),
+    pub percentile_range: (f32, f32),
+}
+
+impl Default for IntensityStats {
+    fn default() -> Self {
+        Self {
+            data_range: (0.0, 1.0),
+            percentile_range: (0.0, 1.0),
+        }
+    }
+}
+
+// Value at the given percentile of a histogram whose bin i starts at value(i)
+fn percentile(histogram: &[u64], total: u64, percent: f32, value: impl Fn(usize) -> f32) -> f32 {
+    let rank = ((percent / 100.0) as f64 * (total - 1) as f64).round() as u64;
+    let mut seen = 0;
+    for (bin, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen > rank {
+            return value(bin);
+        }
+    }
+    value(histogram.len() - 1)
+}
+
+// Exact statistics for integer samples, counted in one bin per value
+fn integer_stats(values: impl Iterator<Item = usize>, bins: usize, offset: f32) -> IntensityStats {
+    let mut histogram = vec![0u64; bins];
+    for v in values {
+        histogram[v] += 1;
+    }
+
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return IntensityStats::default();
+    }
+
+    let value = |bin: usize| bin as f32 + offset;
+    let min = histogram.iter().position(|&c| c > 0).map_or(0.0, value);
+    let max = histogram.iter().rposition(|&c| c > 0).map_or(0.0, value);
+    IntensityStats {
+        data_range: (min, max),
+        percentile_range: (
+            percentile(&histogram, total, LOW_PERCENTILE, value),
+            percentile(&histogram, total, HIGH_PERCENTILE, value),
+        ),
+    }
+}
+
+// Float percentiles come from a FLOAT_BINS histogram, so they are within one bin of exact
+fn float_stats(data: &[f32]) -> IntensityStats {
+    let (min, max) = data
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    if min > max {
+        return IntensityStats::default();
+    }
+    if min == max {
+        return IntensityStats { data_range: (min, max), percentile_range: (min, max) };
+    }
+
+    // In f64 so that neither a denormal-wide nor a full-f32-wide range overflows the scale
+    let scale = (FLOAT_BINS - 1) as f64 / (max as f64 - min as f64);
+    let mut histogram = vec![0u64; FLOAT_BINS];
+    for &v in data.iter().filter(|v| v.is_finite()) {
+        let bin = ((v as f64 - min as f64) * scale) as usize;
+        histogram[bin.min(FLOAT_BINS - 1)] += 1;
+    }
+
+    let total: u64 = histogram.iter().sum();
+    let value = |bin: usize| ((min as f64 + bin as f64 / scale) as f32).min(max);
+    IntensityStats {
+        data_range: (min, max),
+        percentile_range: (
+            percentile(&histogram, total, LOW_PERCENTILE, value),
+            percentile(&histogram, total, HIGH_PERCENTILE, value),
+        ),
+    }
+}
+
+pub fn channel_stats(data: VoxelSlice<'_>) -> IntensityStats {
+    match data {
+        VoxelSlice::U8(data) => integer_stats(data.iter().map(|&v| v as usize), 1 << 8, 0.0),
+        VoxelSlice::U16(data) => integer_stats(data.iter().map(|&v| v as usize), 1 << 16, 0.0),
+        VoxelSlice::I16(data) => {
+            integer_stats(data.iter().map(|&v| (v as i32 - i16::MIN as i32) as usize), 1 << 16, i16::MIN as f32)
+        },
+        VoxelSlice::F32(data) => float_stats(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_percentiles_skip_outliers() {
+        let mut data = vec![100u16; 1000];
+        data[0] = 0;
+        data[1] = 60000;
+        let stats = channel_stats(VoxelSlice::U16(&data));
+        assert_eq!(stats.data_range, (0.0, 60000.0));
+        assert_eq!(stats.percentile_range, (100.0, 100.0));
+    }
+
+    #[test]
+    fn tiny_and_huge_float_ranges() {
+        let stats = channel_stats(VoxelSlice::F32(&[0.0, 1e-37]));
+        assert_eq!(stats.data_range, (0.0, 1e-37));
+        assert_eq!(stats.percentile_range, (0.0, 1e-37));
+
+        // The smallest denormal step still gets a finite scale
+        let stats = channel_stats(VoxelSlice::F32(&[0.0, f32::from_bits(1), f32::NAN]));
+        assert_eq!(stats.data_range, (0.0, f32::from_bits(1)));
+
+        let stats = channel_stats(VoxelSlice::F32(&[-3e38, 3e38]));
+        assert_eq!(stats.percentile_range, (-3e38, 3e38));
+    }
+}
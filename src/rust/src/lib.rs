@@ -7,21 +7,134 @@ pub mod camera;
 pub mod renderer;
 pub mod transfer_function;
 pub mod tiff_loader;
+pub mod tiff_stream;
+pub mod tiff_inspect;
+pub mod tiff_writer;
+pub mod nrrd_writer;
+pub mod nifti_writer;
+pub mod png_export;
+pub mod nrrd_loader;
+pub mod nifti_loader;
+pub mod dicom_loader;
+pub mod metaimage_loader;
+pub mod raw_loader;
+pub mod ome_metadata;
+pub mod image_sequence;
+pub mod chunk_store;
+pub mod blosc;
+pub mod zarr_loader;
+pub mod vtk_loader;
+pub mod sample;
+pub mod intensity;
+pub mod voxels;
+pub mod loader;
 
 use camera::Camera;
+use chunk_store::{ChunkStore, JsChunkStore};
+use raw_loader::RawDescriptor;
+use png_export::PngExportOptions;
+use tiff_loader::{LoadReport, PageStatus, TiffLoadOptions, TiffStack};
+use tiff_stream::TiffStream;
 use renderer::VolumeRenderer;
 use transfer_function::TransferFunction;
+use intensity::{IntensityStats, RangeMode};
+use voxels::{Voxel, VoxelSlice, Voxels};
+
+// Voxels for loaders that still decode through an f32 buffer
+pub(crate) const MAX_SIZE: usize = 256 * 1024 * 1024 / 4; // 256MB limit
+// Stored voxel data, leaving room in the 4 GB wasm32 address space for the
+// source file and the decoded pages while the volume is assembled
+pub(crate) const MAX_BYTES: usize = 1536 * 1024 * 1024;
+
+fn voxel_count(width: usize, height: usize, depth: usize) -> Result<usize> {
+    voxel_count_within(width, height, depth, MAX_SIZE)
+}
+
+fn voxel_count_within(width: usize, height: usize, depth: usize, max_voxels: usize) -> Result<usize> {
+    let total_size = width
+        .checked_mul(height)
+        .and_then(|wh| wh.checked_mul(depth))
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+
+    if total_size > max_voxels {
+        return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+    }
+
+    Ok(total_size)
+}
 
-#[derive(Default)]
 pub struct VolumeData {
-    pub raw_data: Vec<f32>,
+    // Timepoint-major, then channel-major: each channel of each timepoint is a full
+    // width x height x depth block
+    pub raw_data: Voxels,
     pub dimensions: (usize, usize, usize),
+    pub channels: usize,
+    pub active_channel: usize,
+    // 1 unless the source file is a time series
+    pub timepoints: usize,
+    pub active_timepoint: usize,
+    // Sample format of the source file; raw_data keeps u8, u16 and i16 at their own
+    // width (i8 as i16) and holds everything else as f32
+    pub sample_type: sample::SampleType,
+    // Empty when the source file does not name its channels
+    pub channel_names: Vec<String>,
+    // Range of the active channel that maps onto 0..1, picked by range_mode
     pub value_range: (f32, f32),
+    pub range_mode: RangeMode,
+    // Per channel of each timepoint (t * channels + c), computed at load time
+    pub intensity: Vec<IntensityStats>,
+    // Physical size of a voxel along x, y and z, in unit
+    pub spacing: [f32; 3],
+    // World position of the first voxel, in LPS (DICOM and NRRD's frame) like the directions
+    pub origin: [f32; 3],
+    // Row i is the world direction of image axis i
+    pub directions: [[f32; 3]; 3],
+    // None when the source file does not say
+    pub unit: Option<String>,
+    // Axes whose spacing the source file or the user gave; the others hold stand-ins
+    pub spacing_known: [bool; 3],
+    pub load_report: LoadReport,
+}
+
+const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+impl Default for VolumeData {
+    fn default() -> Self {
+        Self {
+            raw_data: Voxels::default(),
+            dimensions: (0, 0, 0),
+            channels: 1,
+            active_channel: 0,
+            timepoints: 1,
+            active_timepoint: 0,
+            sample_type: sample::SampleType::F32,
+            channel_names: Vec::new(),
+            value_range: (0.0, 0.0),
+            range_mode: RangeMode::Data,
+            intensity: Vec::new(),
+            spacing: [1.0; 3],
+            origin: [0.0; 3],
+            directions: IDENTITY,
+            unit: None,
+            spacing_known: [false; 3],
+            load_report: LoadReport::default(),
+        }
+    }
 }
 
 impl VolumeData {
     pub fn load_tiff_from_memory(&mut self, data: &[u8]) -> Result<()> {
-        let slices = tiff_loader::load_tiff_from_memory(data)?;
+        self.load_tiff_with_options(data, &TiffLoadOptions::default())
+    }
+
+    pub fn load_tiff_with_options(&mut self, data: &[u8], options: &TiffLoadOptions) -> Result<()> {
+        let stack = tiff_loader::load_tiff_with_options(data, options)?;
+        self.load_tiff_stack(&stack, options.max_voxels)
+    }
+
+    // Also used for partial volumes while a TIFF is still streaming in
+    pub fn load_tiff_stack(&mut self, stack: &TiffStack, max_voxels: usize) -> Result<()> {
+        let slices = &stack.slices;
 
         if slices.is_empty() {
             return Err(anyhow::anyhow!("No valid slices found in TIFF"));
@@ -29,52 +142,471 @@ impl VolumeData {
 
         let width = slices[0].width;
         let height = slices[0].height;
-        let depth = slices.len();
-
-        let total_size = width
-            .checked_mul(height)
-            .and_then(|wh| wh.checked_mul(depth))
-            .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
 
-        const MAX_SIZE: usize = 256 * 1024 * 1024 / 4; // 256MB limit
-        if total_size > MAX_SIZE {
-            return Err(anyhow::anyhow!("Image data too large to fit in memory"));
-        }
+        let samples = slices[0].channels;
 
         // Check slice compatibility
         for slice in &slices[1..] {
             if slice.width != width || slice.height != height {
                 return Err(anyhow::anyhow!("Inconsistent slice dimensions"));
             }
+            if slice.channels != samples {
+                return Err(anyhow::anyhow!("Inconsistent samples per pixel across slices"));
+            }
+            if slice.sample_type != slices[0].sample_type {
+                return Err(anyhow::anyhow!("Inconsistent sample formats across slices"));
+            }
+        }
+
+        // Without OME-XML every page is a Z slice of a single channel plane
+        let (depth, planes, timepoints, ordered) = match (&stack.ome, stack.ome_layout()) {
+            (Some(ome), Some(layout)) => {
+                if layout.timepoints == 0 {
+                    return Err(match stack.report.truncated {
+                        true => anyhow::anyhow!("OME-TIFF planes for the first timepoint exceed the load limits"),
+                        false => anyhow::anyhow!("OME-TIFF is missing planes for the first timepoint"),
+                    });
+                }
+
+                info!(
+                    "OME-TIFF: {} channels, {} of {} timepoints, physical size {:?} {}",
+                    ome.size_c, layout.timepoints, ome.size_t, ome.physical_size, ome.physical_size_unit
+                );
+                let ordered: Vec<_> = layout.pages.iter().map(|&page| &slices[page]).collect();
+                (layout.depth, ome.size_c, layout.timepoints, ordered)
+            },
+            _ => (slices.len(), 1, 1, slices.iter().collect()),
+        };
+
+        self.stack_slices(&ordered, depth, planes, timepoints, max_voxels)?;
+        self.apply_tiff_metadata(stack);
+
+        Ok(())
+    }
+
+    // Brings a volume loaded from an earlier state of the same stream up to date, copying
+    // only what was decoded since. Plain stacks grow in Z and OME-TIFFs by whole timepoints.
+    pub fn extend_tiff_stack(&mut self, stack: &TiffStack, max_voxels: usize) -> Result<()> {
+        let (width, height, depth) = self.dimensions;
+        let compatible = !self.raw_data.is_empty()
+            && stack.slices.iter().all(|slice| {
+                slice.width == width && slice.height == height && slice.sample_type == self.sample_type
+            });
+        if !compatible {
+            return self.load_tiff_stack(stack, max_voxels);
         }
 
-        let is_16bit = slices[0].data.iter().any(|&x| x > 255_u16);
-        let max_possible = if is_16bit { 65535.0 } else { 255.0 };
+        match stack.ome_layout() {
+            Some(layout) => {
+                let frame = layout.pages.len() / layout.timepoints.max(1);
+                let loaded = frame * self.timepoints;
+                if layout.depth != depth || layout.timepoints < self.timepoints {
+                    return self.load_tiff_stack(stack, max_voxels);
+                }
+                let total = depth * self.channels * layout.timepoints;
+                self.check_stack_size(total, max_voxels)?;
+
+                // Timepoints are stored one after another, so new ones go at the end
+                let samples = stack.slices[0].channels;
+                for plane in layout.pages[loaded..].chunks(depth) {
+                    for sample in 0..samples {
+                        for &page in plane {
+                            self.raw_data.extend_from(stack.slices[page].channel(sample));
+                        }
+                    }
+                }
+                self.timepoints = layout.timepoints;
+            },
+            None => {
+                let reload = self.timepoints != 1
+                    || stack.slices.len() < depth
+                    || stack.slices.iter().any(|slice| slice.channels != self.channels);
+                if reload {
+                    return self.load_tiff_stack(stack, max_voxels);
+                }
+                let added = &stack.slices[depth..];
+                let new_depth = depth + added.len();
+                self.check_stack_size(new_depth * self.channels, max_voxels)?;
 
-        let mut combined_data = Vec::with_capacity(total_size);
+                // Each channel is a block of Z slices, so slices are inserted from the last channel back
+                let plane = width * height;
+                for channel in (0..self.channels).rev() {
+                    let mut block = Voxels::with_capacity(self.sample_type, added.len() * plane);
+                    for slice in added {
+                        block.extend_from(slice.channel(channel));
+                    }
+                    self.raw_data.insert_from((channel + 1) * depth * plane, block.as_slice());
+                }
+                self.dimensions.2 = new_depth;
+            },
+        }
+
+        self.update_intensity();
+        self.apply_tiff_metadata(stack);
+        Ok(())
+    }
 
-        for slice in &slices {
-            combined_data.extend(slice.data.iter().map(|&v| v as f32));
+    fn check_stack_size(&self, depth: usize, max_voxels: usize) -> Result<()> {
+        let (width, height, _) = self.dimensions;
+        let total_size = voxel_count_within(width, height, depth, max_voxels)?;
+        if total_size * self.raw_data.bytes_per_voxel() > MAX_BYTES {
+            return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+        }
+        Ok(())
+    }
+
+    // Report, channel names and spacing of a volume just stacked from TIFF pages
+    fn apply_tiff_metadata(&mut self, stack: &TiffStack) {
+        let timepoints = self.timepoints;
+        self.load_report = stack.report.clone();
+
+        if let Some(ome) = &stack.ome {
+            if timepoints < ome.size_t {
+                self.load_report.warnings.push(format!("Loaded {} of {} timepoints", timepoints, ome.size_t));
+            }
+
+            // OME names channel planes, which only map onto channels one to one for single-sample pages
+            if self.channels == ome.channel_names.len() {
+                self.channel_names = ome.channel_names.clone();
+            }
+        }
+
+        let physical = match &stack.ome {
+            Some(ome) if ome.physical_size.iter().any(Option::is_some) => {
+                Some((ome.physical_size, ome.physical_size_unit.clone()))
+            }
+            _ => stack.spacing.as_ref().map(|page| (page.spacing, page.unit.clone())),
+        };
+        if let Some((mut sizes, unit)) = physical {
+            // Binned pixels and skipped Z slices cover more of the sample
+            let factors = [stack.binning, stack.binning, stack.slice_range.step];
+            for (size, factor) in sizes.iter_mut().zip(factors) {
+                *size = size.map(|size| size * factor as f32);
+            }
+            self.set_geometry(fill_spacing(sizes), [0.0; 3], IDENTITY, Some(unit));
+            self.spacing_known = sizes.map(|size| size.is_some());
+        }
+    }
+
+    pub fn load_image_sequence(&mut self, files: &[(&str, &[u8])]) -> Result<()> {
+        let slices = image_sequence::load_image_sequence(files)?;
+        let ordered: Vec<&tiff_loader::ImageInfo> = slices.iter().collect();
+
+        self.stack_slices(&ordered, ordered.len(), 1, 1, MAX_SIZE)?;
+        self.load_report = LoadReport::default();
+
+        Ok(())
+    }
+
+    // Slices are ordered plane-major (all Z of the first channel plane first),
+    // one timepoint after another
+    fn stack_slices(
+        &mut self,
+        ordered: &[&tiff_loader::ImageInfo],
+        depth: usize,
+        planes: usize,
+        timepoints: usize,
+        max_voxels: usize,
+    ) -> Result<()> {
+        let width = ordered[0].width;
+        let height = ordered[0].height;
+        let samples = ordered[0].channels;
+
+        let channels = planes * samples;
+        let total_size = voxel_count_within(width, height, depth * channels * timepoints, max_voxels)?;
+
+        let sample_type = ordered[0].sample_type;
+        if total_size * voxels::stored_size(sample_type) > MAX_BYTES {
+            return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+        }
+
+        let mut combined_data = Voxels::with_capacity(sample_type, total_size);
+
+        // Each sample of each channel plane becomes its own channel
+        for plane in ordered.chunks(depth) {
+            for sample in 0..samples {
+                for slice in plane {
+                    combined_data.extend_from(slice.channel(sample));
+                }
+            }
         }
 
         self.raw_data = combined_data;
         self.dimensions = (width, height, depth);
-        self.value_range = (0.0, max_possible);
+        self.channels = channels;
+        self.active_channel = 0;
+        self.timepoints = timepoints;
+        self.active_timepoint = 0;
+        self.sample_type = sample_type;
+        self.channel_names = Vec::new();
+        self.set_geometry([1.0; 3], [0.0; 3], IDENTITY, None);
+        self.update_intensity();
+
+        info!("Loaded volume: {}x{}x{} ({:?})", width, height, depth, sample_type);
+        info!("Value range: {} to {}", self.value_range.0, self.value_range.1);
+
+        Ok(())
+    }
+
+    // Replaces the contents with channel-major samples; loaders outside this crate use this too
+    pub fn set_samples(
+        &mut self,
+        data: Vec<f32>,
+        dimensions: (usize, usize, usize),
+        channels: usize,
+        sample_type: sample::SampleType,
+    ) -> Result<()> {
+        let (width, height, depth) = dimensions;
+        let planes = depth
+            .checked_mul(channels.max(1))
+            .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+        voxel_count(width, height, planes)?;
+        self.set_voxels(Voxels::from_f32(data, sample_type), dimensions, channels, sample_type)
+    }
+
+    // As set_samples, for data already stored at its native width
+    pub fn set_voxels(
+        &mut self,
+        data: Voxels,
+        dimensions: (usize, usize, usize),
+        channels: usize,
+        sample_type: sample::SampleType,
+    ) -> Result<()> {
+        self.set_time_series(data, dimensions, channels, 1, sample_type)
+    }
+
+    // As set_voxels, with the channels of each timepoint one after another
+    pub fn set_time_series(
+        &mut self,
+        data: Voxels,
+        dimensions: (usize, usize, usize),
+        channels: usize,
+        timepoints: usize,
+        sample_type: sample::SampleType,
+    ) -> Result<()> {
+        let (width, height, depth) = dimensions;
+        let planes = channels
+            .max(1)
+            .checked_mul(timepoints.max(1))
+            .and_then(|frames| frames.checked_mul(depth))
+            .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+        let total_size = voxel_count_within(width, height, planes, MAX_BYTES)?;
+        if data.len() != total_size {
+            return Err(anyhow::anyhow!(
+                "Sample count {} does not match {}x{}x{} with {} channels and {} timepoints",
+                data.len(), width, height, depth, channels, timepoints
+            ));
+        }
+        if total_size.checked_mul(data.bytes_per_voxel()).is_none_or(|bytes| bytes > MAX_BYTES) {
+            return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+        }
+
+        self.raw_data = data;
+        self.dimensions = dimensions;
+        self.channels = channels.max(1);
+        self.active_channel = 0;
+        self.timepoints = timepoints.max(1);
+        self.active_timepoint = 0;
+        self.sample_type = sample_type;
+        self.channel_names = Vec::new();
+        self.set_geometry([1.0; 3], [0.0; 3], IDENTITY, None);
+        self.update_intensity();
+        self.load_report = LoadReport::default();
 
         info!("Loaded volume: {}x{}x{}", width, height, depth);
-        info!("Value range: {} to {}", 0.0, max_possible);
+        info!("Value range: {} to {}", self.value_range.0, self.value_range.1);
+
+        Ok(())
+    }
+
+    fn update_intensity(&mut self) {
+        self.intensity = (0..self.timepoints * self.channels)
+            .map(|i| {
+                self.frame_channel(i / self.channels, i % self.channels)
+                    .map(intensity::channel_stats)
+                    .unwrap_or_default()
+            })
+            .collect();
+        self.set_range_mode(self.range_mode);
+    }
+
+    // Statistics of the active channel at the active timepoint
+    pub fn active_stats(&self) -> IntensityStats {
+        let index = self.active_timepoint * self.channels + self.active_channel;
+        self.intensity.get(index).copied().unwrap_or_default()
+    }
 
+    // Full range of the sample type, or the data range for floats
+    pub fn nominal_range(&self) -> (f32, f32) {
+        self.sample_type.nominal_range().unwrap_or(self.active_stats().data_range)
+    }
+
+    // Picks the range of the active channel that get_normalized_value maps onto 0..1
+    pub fn set_range_mode(&mut self, mode: RangeMode) {
+        let stats = self.active_stats();
+        self.range_mode = mode;
+        self.value_range = match mode {
+            RangeMode::Data => stats.data_range,
+            RangeMode::Percentile => stats.percentile_range,
+            RangeMode::Nominal => self.nominal_range(),
+        };
+    }
+
+    pub fn set_active_channel(&mut self, channel: usize) -> Result<()> {
+        if channel >= self.channels {
+            return Err(anyhow::anyhow!("Channel index out of range"));
+        }
+
+        self.active_channel = channel;
+        self.set_range_mode(self.range_mode);
+        Ok(())
+    }
+
+    // Every timepoint is already in memory, so this only changes which one is shown
+    pub fn set_timepoint(&mut self, timepoint: usize) -> Result<()> {
+        if timepoint >= self.timepoints {
+            return Err(anyhow::anyhow!("Timepoint index out of range"));
+        }
+
+        self.active_timepoint = timepoint;
+        self.set_range_mode(self.range_mode);
         Ok(())
     }
 
+    // Spacings that are not positive and finite fall back to 1. Spacing is taken to be
+    // known when it has a unit or differs from the default.
+    pub fn set_geometry(
+        &mut self,
+        spacing: [f32; 3],
+        origin: [f32; 3],
+        directions: [[f32; 3]; 3],
+        unit: Option<String>,
+    ) {
+        self.spacing = spacing.map(|s| if s.is_finite() && s > 0.0 { s } else { 1.0 });
+        self.origin = origin;
+        self.directions = directions;
+        self.spacing_known = [unit.is_some() || self.spacing != [1.0; 3]; 3];
+        self.unit = unit;
+    }
+
+    // Voxel spacing of each axis the source file or the user gave
+    pub fn known_spacing(&self) -> [Option<f32>; 3] {
+        std::array::from_fn(|axis| self.spacing_known[axis].then_some(self.spacing[axis]))
+    }
+
+    // The spacing unit, for writers that can only label all three axes at once
+    pub fn physical_unit(&self) -> Option<&str> {
+        self.unit.as_deref().filter(|_| self.spacing_known == [true; 3])
+    }
+
+    pub fn load_nrrd_from_memory(&mut self, data: &[u8]) -> Result<()> {
+        let nrrd = nrrd_loader::load_nrrd_from_memory(data)?;
+        let voxels = Voxels::from_f32(nrrd.data, nrrd.sample_type);
+        self.set_time_series(voxels, nrrd.dimensions, nrrd.channels, nrrd.timepoints, nrrd.sample_type)?;
+        self.set_geometry(nrrd.spacing, nrrd.origin, nrrd.directions, nrrd.unit);
+        Ok(())
+    }
+
+    pub fn load_nifti_from_memory(&mut self, data: &[u8]) -> Result<()> {
+        let nifti = nifti_loader::load_nifti_from_memory(data)?;
+        let voxels = Voxels::from_f32(nifti.data, nifti.sample_type);
+        self.set_time_series(voxels, nifti.dimensions, nifti.channels, nifti.timepoints, nifti.sample_type)?;
+        self.set_geometry(nifti.spacing, nifti.origin, nifti.directions, nifti.unit);
+        self.report_timepoints(nifti.timepoints, nifti.total_timepoints);
+        Ok(())
+    }
+
+    pub fn load_dicom_series_from_memory(&mut self, files: &[&[u8]]) -> Result<()> {
+        let series = dicom_loader::load_dicom_series_from_memory(files)?;
+        self.set_samples(series.data, series.dimensions, 1, sample::SampleType::F32)?;
+        // DICOM patient coordinates are always in millimetres
+        self.set_geometry(series.spacing, series.origin, series.directions, Some("mm".to_string()));
+        Ok(())
+    }
+
+    pub fn load_metaimage_from_memory(&mut self, header: &[u8], raw: Option<&[u8]>) -> Result<()> {
+        let image = metaimage_loader::load_metaimage_from_memory(header, raw)?;
+        self.set_samples(image.data, image.dimensions, 1, image.sample_type)?;
+        self.set_geometry(image.spacing, image.origin, image.directions, None);
+        Ok(())
+    }
+
+    pub fn load_raw_from_memory(&mut self, data: &[u8], descriptor: &RawDescriptor) -> Result<()> {
+        let (width, height, depth) = descriptor.dimensions;
+        let frames = descriptor.timepoints.max(1);
+        voxel_count(width, height, depth.saturating_mul(frames))?;
+
+        let raw = raw_loader::load_raw_from_memory(data, descriptor)?;
+        let voxels = Voxels::from_f32(raw.data, raw.sample_type);
+        self.set_time_series(voxels, raw.dimensions, 1, raw.timepoints, raw.sample_type)
+    }
+
+    pub fn load_vtk_from_memory(&mut self, data: &[u8]) -> Result<()> {
+        let vtk = if vtk_loader::is_vtk_legacy(data) {
+            vtk_loader::load_vtk_legacy_from_memory(data)?
+        } else {
+            vtk_loader::load_vti_from_memory(data)?
+        };
+        self.set_samples(vtk.data, vtk.dimensions, vtk.channels, vtk.sample_type)?;
+        self.set_geometry(vtk.spacing, vtk.origin, vtk.directions, None);
+        Ok(())
+    }
+
+    pub fn load_zarr(&mut self, store: &dyn ChunkStore, level: usize) -> Result<()> {
+        let zarr = zarr_loader::load_zarr(store, level)?;
+        let voxels = Voxels::from_f32(zarr.data, zarr.sample_type);
+        self.set_time_series(voxels, zarr.dimensions, zarr.channels, zarr.timepoints, zarr.sample_type)?;
+        self.set_geometry(zarr.spacing, zarr.origin, IDENTITY, zarr.unit);
+        self.report_timepoints(zarr.timepoints, zarr.total_timepoints);
+        Ok(())
+    }
+
+    // Marks the load truncated when only the first timepoints fit; set_time_series has
+    // just reset the report
+    fn report_timepoints(&mut self, loaded: usize, total: usize) {
+        if loaded < total {
+            self.load_report.truncate(format!("Loaded {} of {} timepoints", loaded, total));
+        }
+    }
+
     pub fn sample(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        self.voxel_index(x, y, z).and_then(|index| self.raw_data.get(index))
+    }
+
+    // The stored value at (x, y, z), if the volume is held as T (see raw_data)
+    pub fn sample_as<T: Voxel>(&self, x: usize, y: usize, z: usize) -> Option<T> {
+        let data = self.raw_data.as_slice().typed::<T>()?;
+        self.voxel_index(x, y, z).and_then(|index| data.get(index).copied())
+    }
+
+    fn voxel_index(&self, x: usize, y: usize, z: usize) -> Option<usize> {
         let (width, height, depth) = self.dimensions;
         if x >= width || y >= height || z >= depth {
             return None;
         }
 
-        let index = z * width * height + y * width + x;
-        self.raw_data.get(index).copied()
+        let frame = self.active_timepoint * self.channels + self.active_channel;
+        Some(frame * width * height * depth + z * width * height + y * width + x)
+    }
+
+    // All width x height x depth samples of one channel at the active timepoint
+    pub fn channel_data(&self, channel: usize) -> Option<VoxelSlice<'_>> {
+        self.frame_channel(self.active_timepoint, channel)
+    }
+
+    // All width x height x depth samples of one channel at the given timepoint
+    pub fn frame_channel(&self, timepoint: usize, channel: usize) -> Option<VoxelSlice<'_>> {
+        let (width, height, depth) = self.dimensions;
+        let size = width * height * depth;
+        if size == 0 || channel >= self.channels {
+            return None;
+        }
+
+        let start = (timepoint * self.channels + channel) * size;
+        if start + size > self.raw_data.len() {
+            return None;
+        }
+        Some(self.raw_data.as_slice().range(start, start + size))
     }
 
     pub fn get_normalized_value(&self, value: f32) -> f32 {
@@ -86,12 +618,41 @@ impl VolumeData {
     }
 }
 
+fn page_status_name(status: PageStatus) -> &'static str {
+    match status {
+        PageStatus::Used => "used",
+        PageStatus::Skipped => "skipped",
+        PageStatus::Dropped => "dropped",
+    }
+}
+
+// Stand-ins for axes without a physical size: each takes X's size (or Y's, or Z's),
+// so the voxels render as cubes rather than stretched along the missing axis
+fn fill_spacing(sizes: [Option<f32>; 3]) -> [f32; 3] {
+    let fallback = sizes[0].or(sizes[1]).or(sizes[2]).unwrap_or(1.0);
+    sizes.map(|size| size.unwrap_or(fallback))
+}
+
+fn dimensions_array(volume: &VolumeData) -> js_sys::Array {
+    let (width, height, depth) = volume.dimensions;
+    let result = js_sys::Array::new();
+    result.push(&JsValue::from_f64(width as f64));
+    result.push(&JsValue::from_f64(height as f64));
+    result.push(&JsValue::from_f64(depth as f64));
+    result
+}
+
 #[wasm_bindgen]
 pub struct VolumeViewer {
     volume_data: Option<VolumeData>,
     camera: Camera,
     renderer: VolumeRenderer,
     transfer_func: TransferFunction,
+    // Applied to every volume loaded from now on
+    range_mode: RangeMode,
+    stream: Option<TiffStream>,
+    // Whether the volume on screen was built from the stream in progress
+    stream_shown: bool,
 }
 
 #[wasm_bindgen]
@@ -114,22 +675,410 @@ impl VolumeViewer {
             camera: Camera::default(),
             renderer: VolumeRenderer::new(width, height),
             transfer_func: TransferFunction::default(),
+            range_mode: RangeMode::Data,
+            stream: None,
+            stream_shown: false,
         })
     }
 
     #[wasm_bindgen]
     pub fn load_volume(&mut self, data: &[u8]) -> Result<js_sys::Array, JsValue> {
+        self.load_volume_with_options(data, &TiffLoadOptions::default())
+    }
+
+    #[wasm_bindgen]
+    pub fn load_volume_with_options(&mut self, data: &[u8], options: &TiffLoadOptions) -> Result<js_sys::Array, JsValue> {
         let mut volume = VolumeData::default();
-        volume.load_tiff_from_memory(data)
+        loader::load_volume(data, &loader::LoadOptions::from(*options), &mut volume)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
-        let dimensions = volume.dimensions;
-        let result = js_sys::Array::new();
-        result.push(&JsValue::from_f64(dimensions.0 as f64));
-        result.push(&JsValue::from_f64(dimensions.1 as f64));
-        result.push(&JsValue::from_f64(dimensions.2 as f64));
+        Ok(self.set_volume(volume))
+    }
+
+    // Name of the loader that would handle data, if any
+    #[wasm_bindgen]
+    pub fn detect_format(&self, data: &[u8]) -> Option<String> {
+        loader::find_loader(data).map(|loader| loader.name().to_string())
+    }
 
+    // Header-only summary of a TIFF for showing before it is loaded
+    #[wasm_bindgen]
+    pub fn inspect_tiff(&self, data: &[u8], options: &TiffLoadOptions) -> Result<JsValue, JsValue> {
+        let summary = tiff_inspect::inspect_tiff(data, options)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let pages = js_sys::Array::new();
+        for page in &summary.pages {
+            let entry = js_sys::Object::new();
+            js_sys::Reflect::set(&entry, &"index".into(), &JsValue::from(page.index as u32))?;
+            js_sys::Reflect::set(&entry, &"width".into(), &JsValue::from(page.width as u32))?;
+            js_sys::Reflect::set(&entry, &"height".into(), &JsValue::from(page.height as u32))?;
+            js_sys::Reflect::set(&entry, &"samplesPerPixel".into(), &JsValue::from(page.samples_per_pixel as u32))?;
+            js_sys::Reflect::set(&entry, &"bitsPerSample".into(), &JsValue::from(page.bits_per_sample))?;
+            let sample_type = page.sample_type.map_or(JsValue::NULL, |t| JsValue::from_str(&format!("{:?}", t)));
+            js_sys::Reflect::set(&entry, &"sampleType".into(), &sample_type)?;
+            js_sys::Reflect::set(&entry, &"compression".into(), &JsValue::from_str(&page.compression))?;
+            js_sys::Reflect::set(&entry, &"orientation".into(), &JsValue::from(page.orientation))?;
+            let description = page.description.as_deref().map_or(JsValue::NULL, JsValue::from_str);
+            js_sys::Reflect::set(&entry, &"description".into(), &description)?;
+            let resolution = match &page.resolution {
+                Some(resolution) => {
+                    let value = js_sys::Object::new();
+                    js_sys::Reflect::set(&value, &"x".into(), &JsValue::from_f64(resolution.x))?;
+                    js_sys::Reflect::set(&value, &"y".into(), &JsValue::from_f64(resolution.y))?;
+                    js_sys::Reflect::set(&value, &"unit".into(), &JsValue::from_str(resolution.unit))?;
+                    value.into()
+                },
+                None => JsValue::NULL,
+            };
+            js_sys::Reflect::set(&entry, &"resolution".into(), &resolution)?;
+            js_sys::Reflect::set(&entry, &"status".into(), &JsValue::from_str(page_status_name(page.status)))?;
+            let reason = page.reason.as_deref().map_or(JsValue::NULL, JsValue::from_str);
+            js_sys::Reflect::set(&entry, &"reason".into(), &reason)?;
+            pages.push(&entry);
+        }
+
+        let dimensions = js_sys::Array::new();
+        dimensions.push(&JsValue::from_f64(summary.dimensions.0 as f64));
+        dimensions.push(&JsValue::from_f64(summary.dimensions.1 as f64));
+        dimensions.push(&JsValue::from_f64(summary.dimensions.2 as f64));
+
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"bigTiff".into(), &JsValue::from_bool(summary.big_tiff))?;
+        js_sys::Reflect::set(&result, &"ome".into(), &JsValue::from_bool(summary.ome.is_some()))?;
+        js_sys::Reflect::set(&result, &"pages".into(), &pages)?;
+        js_sys::Reflect::set(&result, &"dimensions".into(), &dimensions)?;
+        js_sys::Reflect::set(&result, &"channels".into(), &JsValue::from(summary.channels as u32))?;
+        js_sys::Reflect::set(&result, &"timepoints".into(), &JsValue::from(summary.timepoints as u32))?;
+        js_sys::Reflect::set(&result, &"estimatedBytes".into(), &JsValue::from_f64(summary.estimated_bytes as f64))?;
+        js_sys::Reflect::set(&result, &"truncated".into(), &JsValue::from_bool(summary.truncated))?;
+        let error = summary.error.as_deref().map_or(JsValue::NULL, JsValue::from_str);
+        js_sys::Reflect::set(&result, &"error".into(), &error)?;
+        Ok(result.into())
+    }
+
+    #[wasm_bindgen]
+    pub fn load_dicom_series(&mut self, files: js_sys::Array) -> Result<js_sys::Array, JsValue> {
+        let buffers: Vec<Vec<u8>> = files
+            .iter()
+            .map(|file| js_sys::Uint8Array::new(&file).to_vec())
+            .collect();
+        let slices: Vec<&[u8]> = buffers.iter().map(|b| b.as_slice()).collect();
+
+        let mut volume = VolumeData::default();
+        volume.load_dicom_series_from_memory(&slices)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        Ok(self.set_volume(volume))
+    }
+
+    #[wasm_bindgen]
+    pub fn load_metaimage(&mut self, header: &[u8], raw: &[u8]) -> Result<js_sys::Array, JsValue> {
+        let mut volume = VolumeData::default();
+        volume.load_metaimage_from_memory(header, Some(raw))
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        Ok(self.set_volume(volume))
+    }
+
+    #[wasm_bindgen]
+    pub fn load_raw_volume(&mut self, data: &[u8], descriptor: &RawDescriptor) -> Result<js_sys::Array, JsValue> {
+        let mut volume = VolumeData::default();
+        volume.load_raw_from_memory(data, descriptor)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        Ok(self.set_volume(volume))
+    }
+
+    #[wasm_bindgen]
+    pub fn load_image_sequence(&mut self, names: Vec<String>, files: js_sys::Array) -> Result<js_sys::Array, JsValue> {
+        if names.len() != files.length() as usize {
+            return Err(JsValue::from_str("Each image needs a file name"));
+        }
+
+        let buffers: Vec<Vec<u8>> = files
+            .iter()
+            .map(|file| js_sys::Uint8Array::new(&file).to_vec())
+            .collect();
+        let named: Vec<(&str, &[u8])> = names
+            .iter()
+            .map(String::as_str)
+            .zip(buffers.iter().map(Vec::as_slice))
+            .collect();
+
+        let mut volume = VolumeData::default();
+        volume.load_image_sequence(&named)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        Ok(self.set_volume(volume))
+    }
+
+    // get_chunk(key) returns the bytes stored under key as a Uint8Array, or null if absent
+    #[wasm_bindgen]
+    pub fn load_zarr(&mut self, get_chunk: js_sys::Function, level: usize) -> Result<js_sys::Array, JsValue> {
+        let store = JsChunkStore::new(get_chunk);
+
+        let mut volume = VolumeData::default();
+        volume.load_zarr(&store, level)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        Ok(self.set_volume(volume))
+    }
+
+    #[wasm_bindgen]
+    pub fn begin_stream(&mut self, options: &TiffLoadOptions) {
+        self.stream = Some(TiffStream::new(*options));
+        self.stream_shown = false;
+    }
+
+    // Returns the number of slices decoded so far
+    #[wasm_bindgen]
+    pub fn feed_stream(&mut self, chunk: &[u8]) -> Result<usize, JsValue> {
+        let stream = self.stream.as_mut()
+            .ok_or_else(|| JsValue::from_str("No stream in progress"))?;
+
+        stream.feed(chunk).map_err(|e| {
+            self.stream = None;
+            JsValue::from_str(&e.to_string())
+        })
+    }
+
+    // Shows the slices decoded so far without ending the stream. Only the slices
+    // decoded since the last call are added to the volume already on screen.
+    #[wasm_bindgen]
+    pub fn show_stream_progress(&mut self) -> Result<js_sys::Array, JsValue> {
+        // Held aside so the volume can be updated while the stack is borrowed
+        let stream = self.stream.take()
+            .ok_or_else(|| JsValue::from_str("No stream in progress"))?;
+
+        let result = self.show_stack(stream.stack(), stream.options().max_voxels);
+        self.stream = Some(stream);
+        self.stream_shown = result.is_ok();
+        result
+    }
+
+    #[wasm_bindgen]
+    pub fn finish_stream(&mut self) -> Result<js_sys::Array, JsValue> {
+        let stream = self.stream.take()
+            .ok_or_else(|| JsValue::from_str("No stream in progress"))?;
+
+        let max_voxels = stream.options().max_voxels;
+        let stack = stream.finish()
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let result = self.show_stack(&stack, max_voxels);
+        self.stream_shown = false;
+        result
+    }
+
+    fn show_stack(&mut self, stack: &TiffStack, max_voxels: usize) -> Result<js_sys::Array, JsValue> {
+        match self.volume_data.as_mut().filter(|_| self.stream_shown) {
+            Some(volume) => {
+                volume.extend_tiff_stack(stack, max_voxels)
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+                Ok(dimensions_array(volume))
+            },
+            None => {
+                let mut volume = VolumeData::default();
+                volume.load_tiff_stack(stack, max_voxels)
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+                Ok(self.set_volume(volume))
+            },
+        }
+    }
+
+    fn set_volume(&mut self, mut volume: VolumeData) -> js_sys::Array {
+        volume.set_range_mode(self.range_mode);
+
+        let result = dimensions_array(&volume);
+        self.stream_shown = false;
         self.volume_data = Some(volume);
+        result
+    }
+
+    #[wasm_bindgen]
+    pub fn load_report(&self) -> Result<JsValue, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+        let report = &volume.load_report;
+
+        let warnings = js_sys::Array::new();
+        for warning in &report.warnings {
+            warnings.push(&JsValue::from_str(warning));
+        }
+
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"truncated".into(), &JsValue::from_bool(report.truncated))?;
+        js_sys::Reflect::set(&result, &"warnings".into(), &warnings)?;
+
+        let pages = js_sys::Array::new();
+        for page in &report.pages {
+            let entry = js_sys::Object::new();
+            js_sys::Reflect::set(&entry, &"index".into(), &JsValue::from(page.index as u32))?;
+            js_sys::Reflect::set(&entry, &"width".into(), &JsValue::from(page.width as u32))?;
+            js_sys::Reflect::set(&entry, &"height".into(), &JsValue::from(page.height as u32))?;
+            js_sys::Reflect::set(&entry, &"status".into(), &JsValue::from_str(page_status_name(page.status)))?;
+            let reason = page.reason.as_deref().map_or(JsValue::NULL, JsValue::from_str);
+            js_sys::Reflect::set(&entry, &"reason".into(), &reason)?;
+            pages.push(&entry);
+        }
+        js_sys::Reflect::set(&result, &"pages".into(), &pages)?;
+        Ok(result.into())
+    }
+
+    #[wasm_bindgen]
+    pub fn channel_count(&self) -> usize {
+        self.volume_data.as_ref().map_or(0, |v| v.channels)
+    }
+
+    #[wasm_bindgen]
+    pub fn timepoint_count(&self) -> usize {
+        self.volume_data.as_ref().map_or(0, |v| v.timepoints)
+    }
+
+    // Switches to another timepoint of a time series without reloading the file
+    #[wasm_bindgen]
+    pub fn set_timepoint(&mut self, timepoint: usize) -> Result<(), JsValue> {
+        let volume = self.volume_data.as_mut()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        volume.set_timepoint(timepoint).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_channel(&mut self, channel: usize) -> Result<(), JsValue> {
+        let volume = self.volume_data.as_mut()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        volume.set_active_channel(channel).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    // Which range of the active channel maps onto the transfer function
+    #[wasm_bindgen]
+    pub fn set_range_mode(&mut self, mode: RangeMode) {
+        self.range_mode = mode;
+        if let Some(volume) = self.volume_data.as_mut() {
+            volume.set_range_mode(mode);
+        }
+    }
+
+    // { data: [min, max], percentile: [low, high], nominal: [min, max], active: [min, max] }
+    // for the active channel and timepoint
+    #[wasm_bindgen]
+    pub fn intensity_range(&self) -> Result<JsValue, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+        let stats = volume.active_stats();
+
+        let pair = |(lo, hi): (f32, f32)| js_sys::Array::of2(&JsValue::from_f64(lo as f64), &JsValue::from_f64(hi as f64));
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"data".into(), &pair(stats.data_range))?;
+        js_sys::Reflect::set(&result, &"percentile".into(), &pair(stats.percentile_range))?;
+        js_sys::Reflect::set(&result, &"nominal".into(), &pair(volume.nominal_range()))?;
+        js_sys::Reflect::set(&result, &"active".into(), &pair(volume.value_range))?;
+        Ok(result.into())
+    }
+
+    // For files without spacing metadata; the origin and axis directions are kept
+    #[wasm_bindgen]
+    pub fn set_voxel_spacing(&mut self, x: f32, y: f32, z: f32, unit: Option<String>) -> Result<(), JsValue> {
+        let volume = self.volume_data.as_mut()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        let spacing = [x, y, z];
+        if spacing.iter().any(|s| !s.is_finite() || *s <= 0.0) {
+            return Err(JsValue::from_str("Voxel spacing must be positive"));
+        }
+
+        let unit = unit.or_else(|| volume.unit.clone());
+        volume.set_geometry(spacing, volume.origin, volume.directions, unit);
+        Ok(())
+    }
+
+    // { spacing: [x, y, z], origin: [x, y, z], directions: [[...], [...], [...]], unit }
+    #[wasm_bindgen]
+    pub fn geometry(&self) -> Result<JsValue, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        let vector = |v: [f32; 3]| v.iter().map(|&c| JsValue::from_f64(c as f64)).collect::<js_sys::Array>();
+        let directions: js_sys::Array = volume.directions.iter().map(|&d| JsValue::from(vector(d))).collect();
+        let unit = volume.unit.as_deref().map_or(JsValue::NULL, JsValue::from_str);
+
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"spacing".into(), &vector(volume.spacing))?;
+        js_sys::Reflect::set(&result, &"origin".into(), &vector(volume.origin))?;
+        js_sys::Reflect::set(&result, &"directions".into(), &directions)?;
+        js_sys::Reflect::set(&result, &"unit".into(), &unit)?;
+        Ok(result.into())
+    }
+
+    // Active channel as a multipage grayscale TIFF at the source bit depth
+    #[wasm_bindgen]
+    pub fn export_tiff(&self) -> Result<Vec<u8>, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        tiff_writer::write_tiff(volume).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    // All channels as OME-TIFF; sizes left undefined come from the volume's spacing when it
+    // is known and are omitted otherwise, and the unit defaults to the volume's or µm
+    #[wasm_bindgen]
+    pub fn export_ome_tiff(
+        &self,
+        physical_size_x: Option<f32>,
+        physical_size_y: Option<f32>,
+        physical_size_z: Option<f32>,
+        unit: Option<String>,
+    ) -> Result<Vec<u8>, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        let known = volume.known_spacing();
+        let physical_size = [physical_size_x, physical_size_y, physical_size_z];
+        let physical_size: [Option<f32>; 3] = std::array::from_fn(|axis| {
+            physical_size[axis].or(known[axis])
+        });
+        let unit = unit.or_else(|| volume.unit.clone());
+        tiff_writer::write_ome_tiff(volume, physical_size, unit.as_deref().unwrap_or("µm"))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    // Active channel as .nrrd, gzip-compressed if requested
+    #[wasm_bindgen]
+    pub fn export_nrrd(&self, gzip: bool) -> Result<Vec<u8>, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        nrrd_writer::write_nrrd(volume, gzip).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    // Active channel as .nii, or .nii.gz if gzip is set
+    #[wasm_bindgen]
+    pub fn export_nifti(&self, gzip: bool) -> Result<Vec<u8>, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        nifti_writer::write_nifti(volume, gzip).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    // Every Z slice of the active channel as PNG, returned as [{ name, data }]
+    #[wasm_bindgen]
+    pub fn export_png_slices(&self, options: &PngExportOptions) -> Result<js_sys::Array, JsValue> {
+        let volume = self.volume_data.as_ref()
+            .ok_or_else(|| JsValue::from_str("No volume data loaded"))?;
+
+        let files = png_export::export_png_slices(volume, options, &self.transfer_func)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let result = js_sys::Array::new();
+        for (name, data) in files {
+            let entry = js_sys::Object::new();
+            js_sys::Reflect::set(&entry, &"name".into(), &JsValue::from_str(&name))?;
+            js_sys::Reflect::set(&entry, &"data".into(), &js_sys::Uint8Array::from(data.as_slice()))?;
+            result.push(&entry);
+        }
         Ok(result)
     }
 
@@ -227,3 +1176,60 @@ impl VolumeViewer {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sample::SampleType;
+
+    #[test]
+    fn huge_channel_and_timepoint_counts_are_errors() {
+        let mut volume = VolumeData::default();
+        volume.set_samples(vec![1.0; 4], (2, 2, 1), 1, SampleType::U8).unwrap();
+
+        let error = volume.set_time_series(Voxels::U8(vec![0; 4]), (2, 2, 1), usize::MAX, 2, SampleType::U8);
+        assert!(error.unwrap_err().to_string().contains("overflow"));
+        let error = volume.set_time_series(Voxels::U8(vec![0; 4]), (2, 2, 2), usize::MAX / 2 + 1, 1, SampleType::U8);
+        assert!(error.unwrap_err().to_string().contains("overflow"));
+        let error = volume.set_samples(vec![0.0; 4], (2, 2, 2), usize::MAX, SampleType::U8);
+        assert!(error.unwrap_err().to_string().contains("overflow"));
+
+        // The volume is left as it was
+        assert_eq!(volume.raw_data, Voxels::U8(vec![1; 4]));
+        assert_eq!((volume.channels, volume.timepoints), (1, 1));
+    }
+
+    #[test]
+    fn timepoints_cut_off_by_the_end_of_a_nifti_are_reported() {
+        let mut volume = VolumeData::default();
+        volume.set_time_series(Voxels::U8((0..24).collect()), (2, 2, 2), 1, 3, SampleType::U8).unwrap();
+        let mut file = nifti_writer::write_nifti(&volume, false).unwrap();
+        file.truncate(file.len() - 4);
+
+        let mut loaded = VolumeData::default();
+        loaded.load_nifti_from_memory(&file).unwrap();
+        assert_eq!(loaded.timepoints, 2);
+        assert_eq!(loaded.raw_data, Voxels::U8((0..16).collect()));
+        assert!(loaded.load_report.truncated);
+        assert_eq!(loaded.load_report.warnings, ["Loaded 2 of 3 timepoints"]);
+    }
+
+    #[test]
+    fn missing_physical_sizes_are_filled_in_but_not_exported() {
+        let mut volume = VolumeData::default();
+        volume.set_samples((0..12).map(|v| v as f32).collect(), (3, 2, 2), 1, SampleType::U8).unwrap();
+        let file = tiff_writer::write_ome_tiff(&volume, [Some(0.1), Some(0.1), None], "µm").unwrap();
+
+        let mut loaded = VolumeData::default();
+        loaded.load_tiff_from_memory(&file).unwrap();
+        assert_eq!(loaded.spacing, [0.1, 0.1, 0.1]);
+        assert_eq!(loaded.known_spacing(), [Some(0.1), Some(0.1), None]);
+        assert_eq!(loaded.physical_unit(), None);
+
+        let ome = tiff_writer::write_ome_tiff(&loaded, loaded.known_spacing(), "µm").unwrap();
+        let description = String::from_utf8_lossy(&ome).into_owned();
+        assert!(description.contains(r#"PhysicalSizeX="0.1""#));
+        assert!(!description.contains("PhysicalSizeZ"));
+        let nrrd = String::from_utf8_lossy(&nrrd_writer::write_nrrd(&loaded, false).unwrap()).into_owned();
+        assert!(!nrrd.contains("space units"));
+    }
+}
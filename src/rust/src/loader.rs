@@ -0,0 +1,261 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex, OnceLock};
+use log::{debug, info};
+use crate::tiff_loader::TiffLoadOptions;
+use crate::{dicom_loader, image_sequence, metaimage_loader, nifti_loader, nrrd_loader, tiff_loader, vtk_loader};
+use crate::VolumeData;
+
+// Settings for load_volume. Each loader reads the part that applies to its format
+// and ignores the rest, so formats can gain options without changing the trait.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    pub tiff: TiffLoadOptions,
+}
+
+impl From<TiffLoadOptions> for LoadOptions {
+    fn from(tiff: TiffLoadOptions) -> Self {
+        Self { tiff }
+    }
+}
+
+// A decoder for one file format. Implement this in another crate and pass it
+// to register_loader to make VolumeViewer::load_volume recognise the format.
+pub trait VolumeLoader: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Cheap check on the leading bytes of the file
+    fn sniff(&self, data: &[u8]) -> bool;
+
+    fn load(&self, data: &[u8], options: &LoadOptions, volume: &mut VolumeData) -> Result<()>;
+}
+
+struct NrrdLoader;
+
+impl VolumeLoader for NrrdLoader {
+    fn name(&self) -> &str {
+        "nrrd"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        nrrd_loader::is_nrrd(data)
+    }
+
+    fn load(&self, data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_nrrd_from_memory(data)
+    }
+}
+
+struct NiftiLoader;
+
+impl VolumeLoader for NiftiLoader {
+    fn name(&self) -> &str {
+        "nifti"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        nifti_loader::is_nifti(data) || nifti_loader::is_gzipped_nifti(data)
+    }
+
+    fn load(&self, data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_nifti_from_memory(data)
+    }
+}
+
+struct MetaImageLoader;
+
+impl VolumeLoader for MetaImageLoader {
+    fn name(&self) -> &str {
+        "metaimage"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        metaimage_loader::is_metaimage(data)
+    }
+
+    fn load(&self, data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_metaimage_from_memory(data, None)
+    }
+}
+
+struct VtkLoader;
+
+impl VolumeLoader for VtkLoader {
+    fn name(&self) -> &str {
+        "vtk"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        vtk_loader::is_vtk_legacy(data) || vtk_loader::is_vti(data)
+    }
+
+    fn load(&self, data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_vtk_from_memory(data)
+    }
+}
+
+// A single DICOM file is loaded as a series of one slice
+struct DicomLoader;
+
+impl VolumeLoader for DicomLoader {
+    fn name(&self) -> &str {
+        "dicom"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        dicom_loader::is_dicom(data)
+    }
+
+    fn load(&self, data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_dicom_series_from_memory(&[data])
+    }
+}
+
+struct TiffLoader;
+
+impl VolumeLoader for TiffLoader {
+    fn name(&self) -> &str {
+        "tiff"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        tiff_loader::is_tiff(data)
+    }
+
+    fn load(&self, data: &[u8], options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_tiff_with_options(data, &options.tiff)
+    }
+}
+
+// PNG, JPEG and the other 2D formats the image crate decodes, as a single slice
+struct ImageLoader;
+
+impl VolumeLoader for ImageLoader {
+    fn name(&self) -> &str {
+        "image"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        image_sequence::is_image(data)
+    }
+
+    fn load(&self, data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+        volume.load_image_sequence(&[("image", data)])
+    }
+}
+
+fn registry() -> &'static Mutex<Vec<Arc<dyn VolumeLoader>>> {
+    static REGISTRY: OnceLock<Mutex<Vec<Arc<dyn VolumeLoader>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        Mutex::new(vec![
+            Arc::new(NrrdLoader),
+            Arc::new(NiftiLoader),
+            Arc::new(MetaImageLoader),
+            Arc::new(VtkLoader),
+            Arc::new(DicomLoader),
+            Arc::new(TiffLoader),
+            Arc::new(ImageLoader),
+        ])
+    })
+}
+
+fn loaders() -> Vec<Arc<dyn VolumeLoader>> {
+    registry().lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// Registered loaders are tried before the built-in ones, most recent first
+pub fn register_loader<L: VolumeLoader + 'static>(loader: L) {
+    info!("Registering volume loader: {}", loader.name());
+    registry().lock().unwrap_or_else(|e| e.into_inner()).insert(0, Arc::new(loader));
+}
+
+pub fn loader_names() -> Vec<String> {
+    loaders().iter().map(|loader| loader.name().to_string()).collect()
+}
+
+pub fn find_loader(data: &[u8]) -> Option<Arc<dyn VolumeLoader>> {
+    loaders().into_iter().find(|loader| loader.sniff(data))
+}
+
+pub fn load_volume(data: &[u8], options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+    let loader = find_loader(data).ok_or_else(|| {
+        anyhow::anyhow!("Unrecognized volume format (supported: {})", loader_names().join(", "))
+    })?;
+
+    debug!("Detected {} data", loader.name());
+    loader.load(data, options, volume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    struct CustomLoader;
+
+    impl VolumeLoader for CustomLoader {
+        fn name(&self) -> &str {
+            "custom"
+        }
+
+        fn sniff(&self, data: &[u8]) -> bool {
+            data.starts_with(b"CUSTOM")
+        }
+
+        fn load(&self, _data: &[u8], _options: &LoadOptions, volume: &mut VolumeData) -> Result<()> {
+            volume.set_samples(vec![1.0, 2.0, 3.0, 4.0], (2, 2, 1), 1, crate::sample::SampleType::F32)
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn detect(data: &[u8]) -> Option<String> {
+        find_loader(data).map(|loader| loader.name().to_string())
+    }
+
+    #[test]
+    fn formats_are_told_apart_by_their_leading_bytes() {
+        let mut tiff = Cursor::new(Vec::new());
+        tiff::encoder::TiffEncoder::new(&mut tiff).unwrap()
+            .write_image::<tiff::encoder::colortype::Gray8>(2, 2, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(detect(tiff.get_ref()).as_deref(), Some("tiff"));
+
+        let mut png = Cursor::new(Vec::new());
+        image::DynamicImage::ImageLuma8(image::ImageBuffer::from_raw(2, 2, vec![1u8, 2, 3, 4]).unwrap())
+            .write_to(&mut png, image::ImageOutputFormat::Png).unwrap();
+        assert_eq!(detect(png.get_ref()).as_deref(), Some("image"));
+
+        assert_eq!(detect(b"NRRD0004\ntype: uint8\n").as_deref(), Some("nrrd"));
+        assert_eq!(detect(b"# vtk DataFile Version 3.0\n").as_deref(), Some("vtk"));
+
+        let mut nifti = vec![0u8; 352];
+        nifti[..4].copy_from_slice(&348i32.to_le_bytes());
+        nifti[344..348].copy_from_slice(b"n+1\0");
+        assert_eq!(detect(&nifti).as_deref(), Some("nifti"));
+        assert_eq!(detect(&gzip(&nifti)).as_deref(), Some("nifti"));
+
+        // A gzip stream is only NIfTI when the header inside says so
+        assert_eq!(detect(&gzip(b"just some text")), None);
+        nifti[..4].copy_from_slice(&100i32.to_le_bytes());
+        assert_eq!(detect(&nifti), None);
+
+        let err = load_volume(b"garbage", &LoadOptions::default(), &mut VolumeData::default()).err().unwrap();
+        assert!(err.to_string().contains("Unrecognized volume format"), "{}", err);
+    }
+
+    #[test]
+    fn registered_loaders_are_tried_first() {
+        register_loader(CustomLoader);
+        assert_eq!(loader_names()[0], "custom");
+
+        let mut volume = VolumeData::default();
+        load_volume(b"CUSTOM data", &LoadOptions::default(), &mut volume).unwrap();
+        assert_eq!(volume.dimensions, (2, 2, 1));
+        assert_eq!(volume.value_range, (1.0, 4.0));
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::Result;
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use log::{debug, info};
+use crate::sample::{self, Endian, SampleType};
+
+pub struct MetaImageVolume {
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    pub sample_type: SampleType,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    pub directions: [[f32; 3]; 3],
+}
+
+type Field = (String, String);
+
+pub fn is_metaimage(data: &[u8]) -> bool {
+    let start = &data[..data.len().min(64)];
+    start.starts_with(b"ObjectType") || start.starts_with(b"NDims")
+}
+
+fn parse_sample_type(value: &str) -> Result<SampleType> {
+    let sample_type = match value {
+        "MET_UCHAR" => SampleType::U8,
+        "MET_CHAR" => SampleType::I8,
+        "MET_USHORT" => SampleType::U16,
+        "MET_SHORT" => SampleType::I16,
+        "MET_UINT" | "MET_ULONG" => SampleType::U32,
+        "MET_INT" | "MET_LONG" => SampleType::I32,
+        "MET_ULONG_LONG" => SampleType::U64,
+        "MET_LONG_LONG" => SampleType::I64,
+        "MET_FLOAT" => SampleType::F32,
+        "MET_DOUBLE" => SampleType::F64,
+        _ => return Err(anyhow::anyhow!("Unsupported MetaImage ElementType: {}", value)),
+    };
+    Ok(sample_type)
+}
+
+fn parse_numbers(key: &str, value: &str) -> Result<Vec<f32>> {
+    value
+        .split_whitespace()
+        .map(|v| v.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow::anyhow!("Invalid MetaImage {}: {}", key, value))
+}
+
+fn parse_bool(value: &str) -> bool {
+    value.eq_ignore_ascii_case("true") || value == "1"
+}
+
+// Splits the header from the bytes that follow ElementDataFile
+fn split_header(data: &[u8]) -> Result<(Vec<Field>, &[u8])> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let line_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| pos + i)
+            .unwrap_or(data.len());
+
+        let line = std::str::from_utf8(&data[pos..line_end])
+            .map_err(|_| anyhow::anyhow!("MetaImage header is not valid text"))?
+            .trim();
+        pos = (line_end + 1).min(data.len());
+
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim().to_string();
+            let is_last = key == "ElementDataFile";
+            fields.push((key, value.trim().to_string()));
+            if is_last {
+                return Ok((fields, &data[pos..]));
+            }
+        }
+    }
+
+    Err(anyhow::anyhow!("MetaImage header missing ElementDataFile"))
+}
+
+pub fn load_metaimage_from_memory(header: &[u8], raw: Option<&[u8]>) -> Result<MetaImageVolume> {
+    let (fields, local_data) = split_header(header)?;
+
+    let mut ndims = None;
+    let mut sizes: Vec<usize> = Vec::new();
+    let mut sample_type = None;
+    let mut channels = 1;
+    let mut endian = Endian::Little;
+    let mut compressed = false;
+    let mut header_size: i64 = 0;
+    let mut spacing_values = None;
+    let mut origin_values = None;
+    let mut matrix_values = None;
+    let mut data_file = String::new();
+
+    for (key, value) in &fields {
+        match key.as_str() {
+            "NDims" => {
+                ndims = Some(value.parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Invalid MetaImage NDims: {}", value))?);
+            },
+            "DimSize" => {
+                sizes = value
+                    .split_whitespace()
+                    .map(|v| v.parse::<usize>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| anyhow::anyhow!("Invalid MetaImage DimSize: {}", value))?;
+            },
+            "ElementType" => sample_type = Some(parse_sample_type(value)?),
+            "ElementNumberOfChannels" => {
+                channels = value.parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Invalid MetaImage ElementNumberOfChannels: {}", value))?;
+            },
+            "BinaryDataByteOrderMSB" | "ElementByteOrderMSB" => {
+                endian = if parse_bool(value) { Endian::Big } else { Endian::Little };
+            },
+            "CompressedData" => compressed = parse_bool(value),
+            "HeaderSize" => {
+                header_size = value.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid MetaImage HeaderSize: {}", value))?;
+            },
+            "ElementSpacing" => spacing_values = Some(parse_numbers(key, value)?),
+            "ElementSize" if spacing_values.is_none() => spacing_values = Some(parse_numbers(key, value)?),
+            "Offset" | "Origin" | "Position" => origin_values = Some(parse_numbers(key, value)?),
+            "TransformMatrix" | "Rotation" | "Orientation" => matrix_values = Some(parse_numbers(key, value)?),
+            "ElementDataFile" => data_file = value.clone(),
+            _ => debug!("Ignoring MetaImage field: {}", key),
+        }
+    }
+
+    let ndims = ndims.ok_or_else(|| anyhow::anyhow!("MetaImage header missing NDims"))?;
+    let sample_type = sample_type.ok_or_else(|| anyhow::anyhow!("MetaImage header missing ElementType"))?;
+
+    if sizes.len() != ndims {
+        return Err(anyhow::anyhow!("MetaImage DimSize does not match NDims"));
+    }
+    if !(2..=3).contains(&ndims) {
+        return Err(anyhow::anyhow!("Unsupported MetaImage NDims: {}", ndims));
+    }
+    if channels != 1 {
+        return Err(anyhow::anyhow!("Multi-channel MetaImage files are not supported"));
+    }
+
+    let payload = if data_file == "LOCAL" {
+        local_data
+    } else if data_file == "LIST" || data_file.contains('%') {
+        return Err(anyhow::anyhow!("MetaImage file lists are not supported"));
+    } else {
+        raw.ok_or_else(|| anyhow::anyhow!("MetaImage data file {} was not provided", data_file))?
+    };
+
+    let width = sizes[0];
+    let height = sizes[1];
+    let depth = sizes.get(2).copied().unwrap_or(1);
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|wh| wh.checked_mul(depth))
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+    if count > crate::MAX_SIZE {
+        return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+    }
+    let needed = count * sample_type.size();
+
+    let inflated;
+    let mut bytes = if compressed {
+        // Nothing past the samples is read, so a small stream cannot inflate without bound
+        let limit = needed + header_size.max(0) as usize;
+        let mut buf = Vec::new();
+        ZlibDecoder::new(payload).take(limit as u64).read_to_end(&mut buf)?;
+        inflated = buf;
+        &inflated[..]
+    } else {
+        payload
+    };
+
+    if header_size == -1 {
+        if bytes.len() < needed {
+            return Err(anyhow::anyhow!("Not enough data in MetaImage file"));
+        }
+        bytes = &bytes[bytes.len() - needed..];
+    } else if header_size > 0 {
+        let skip = header_size as usize;
+        if skip > bytes.len() {
+            return Err(anyhow::anyhow!("MetaImage HeaderSize past end of data"));
+        }
+        bytes = &bytes[skip..];
+    }
+
+    let data = sample::decode_samples(bytes, sample_type, endian, count)?;
+
+    let mut spacing = [1.0f32; 3];
+    if let Some(values) = spacing_values {
+        for (axis, &value) in values.iter().take(3).enumerate() {
+            if value > 0.0 {
+                spacing[axis] = value;
+            }
+        }
+    }
+
+    let mut origin = [0.0f32; 3];
+    if let Some(values) = origin_values {
+        for (axis, &value) in values.iter().take(3).enumerate() {
+            origin[axis] = value;
+        }
+    }
+
+    // Each row of TransformMatrix is the direction of one image axis
+    let mut directions = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    if let Some(values) = matrix_values {
+        if values.len() == ndims * ndims {
+            for (axis, direction) in directions.iter_mut().enumerate().take(ndims) {
+                for (i, component) in direction.iter_mut().enumerate().take(ndims) {
+                    *component = values[axis * ndims + i];
+                }
+            }
+        }
+    }
+
+    info!("Loaded MetaImage volume: {}x{}x{} ({:?})", width, height, depth, sample_type);
+    debug!("MetaImage spacing: {:?}, origin: {:?}", spacing, origin);
+
+    Ok(MetaImageVolume {
+        data,
+        dimensions: (width, height, depth),
+        sample_type,
+        spacing,
+        origin,
+        directions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn compressed_local_data() {
+        let samples: Vec<u8> = (0i16..24).flat_map(|v| (v - 5).to_be_bytes()).collect();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&samples).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut file = format!(
+            "ObjectType = Image\nNDims = 3\nBinaryData = True\nBinaryDataByteOrderMSB = True\n\
+CompressedData = True\nCompressedDataSize = {}\nTransformMatrix = 0 1 0 1 0 0 0 0 1\nOffset = 1 2 3\n\
+ElementSpacing = 0.5 0.5 2\nDimSize = 4 3 2\nElementType = MET_SHORT\nElementDataFile = LOCAL\n",
+            compressed.len()
+        )
+        .into_bytes();
+        file.extend_from_slice(&compressed);
+
+        let image = load_metaimage_from_memory(&file, None).unwrap();
+        assert_eq!(image.dimensions, (4, 3, 2));
+        assert_eq!(image.sample_type, SampleType::I16);
+        assert_eq!(image.data[0], -5.0);
+        assert_eq!(image.data[23], 18.0);
+        assert_eq!(image.spacing, [0.5, 0.5, 2.0]);
+        assert_eq!(image.origin, [1.0, 2.0, 3.0]);
+        assert_eq!(image.directions[0], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn detached_data_file() {
+        let header = b"NDims = 2\nDimSize = 2 2\nElementType = MET_UCHAR\nHeaderSize = -1\nElementDataFile = foo.raw\n";
+        assert!(is_metaimage(header));
+        assert!(load_metaimage_from_memory(header, None).is_err());
+
+        // HeaderSize -1 takes the samples from the end of the file
+        let image = load_metaimage_from_memory(header, Some(&[9, 9, 1, 2, 3, 4])).unwrap();
+        assert_eq!(image.data, vec![1.0, 2.0, 3.0, 4.0]);
+    }
+}
@@ -0,0 +1,507 @@
+use anyhow::Result;
+use std::io::{self, Read};
+use flate2::read::MultiGzDecoder;
+use log::{debug, info};
+use crate::sample::{self, Endian, SampleType};
+
+pub struct NiftiVolume {
+    // One width x height x depth block per channel of each timepoint, like VolumeData
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    // Components of a vector-valued image, from the fifth dimension
+    pub channels: usize,
+    // Timepoints held in data, which can be fewer than the file has
+    pub timepoints: usize,
+    pub total_timepoints: usize,
+    pub sample_type: SampleType,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    pub directions: [[f32; 3]; 3],
+    // Voxel index to LPS world (row-major 3x4), from sform, qform or pixdim in that order;
+    // the file's RAS rows have X and Y negated
+    pub affine: [[f32; 4]; 3],
+    pub qform_code: i32,
+    pub sform_code: i32,
+    pub unit: Option<String>,
+}
+
+struct Header {
+    version: u8,
+    endian: Endian,
+    dims: [i64; 8],
+    datatype: i16,
+    pixdim: [f64; 8],
+    vox_offset: usize,
+    scl_slope: f64,
+    scl_inter: f64,
+    qform_code: i32,
+    sform_code: i32,
+    quatern: [f64; 3],
+    qoffset: [f64; 3],
+    srow: [[f64; 4]; 3],
+    xyzt_units: i32,
+}
+
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl HeaderReader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(&self.data[offset..offset + N]);
+        if self.endian == Endian::Big {
+            buf.reverse();
+        }
+        buf
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.bytes(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.bytes(offset))
+    }
+
+    fn i64(&self, offset: usize) -> i64 {
+        i64::from_le_bytes(self.bytes(offset))
+    }
+
+    fn f32(&self, offset: usize) -> f64 {
+        f32::from_le_bytes(self.bytes(offset)) as f64
+    }
+
+    fn f64(&self, offset: usize) -> f64 {
+        f64::from_le_bytes(self.bytes(offset))
+    }
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+// sizeof_hdr must agree with the magic, in either byte order
+pub fn is_nifti(data: &[u8]) -> bool {
+    let sizeof_hdr = |expected: i32| {
+        data.get(..4).is_some_and(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            i32::from_le_bytes(bytes) == expected || i32::from_be_bytes(bytes) == expected
+        })
+    };
+    (sizeof_hdr(348) && data.get(344..347) == Some(b"n+1")) || (sizeof_hdr(540) && data.get(4..7) == Some(b"n+2"))
+}
+
+// Inflates just enough of a gzip stream to look for a NIfTI header
+pub fn is_gzipped_nifti(data: &[u8]) -> bool {
+    if !is_gzip(data) {
+        return false;
+    }
+    let mut header = Vec::new();
+    // A stream cut short still yields the bytes before the cut
+    let _ = MultiGzDecoder::new(data).take(348).read_to_end(&mut header);
+    is_nifti(&header)
+}
+
+fn parse_header(data: &[u8]) -> Result<Header> {
+    if data.len() < 348 {
+        return Err(anyhow::anyhow!("File too small for a NIfTI header"));
+    }
+
+    let size_le = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let size_be = i32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let (version, endian) = match (size_le, size_be) {
+        (348, _) => (1, Endian::Little),
+        (_, 348) => (1, Endian::Big),
+        (540, _) => (2, Endian::Little),
+        (_, 540) => (2, Endian::Big),
+        _ => return Err(anyhow::anyhow!("Not a NIfTI file")),
+    };
+
+    if version == 2 && data.len() < 540 {
+        return Err(anyhow::anyhow!("File too small for a NIfTI-2 header"));
+    }
+
+    let r = HeaderReader { data, endian };
+    let mut dims = [0i64; 8];
+    let mut pixdim = [0f64; 8];
+    let mut srow = [[0f64; 4]; 3];
+
+    let header = if version == 1 {
+        match &data[344..348] {
+            b"n+1\0" => {},
+            b"ni1\0" => return Err(anyhow::anyhow!("NIfTI header/image pairs (.hdr/.img) are not supported")),
+            _ => return Err(anyhow::anyhow!("Invalid NIfTI-1 magic")),
+        }
+
+        for (i, d) in dims.iter_mut().enumerate() {
+            *d = r.i16(40 + i * 2) as i64;
+        }
+        for (i, p) in pixdim.iter_mut().enumerate() {
+            *p = r.f32(76 + i * 4);
+        }
+        for (row, values) in srow.iter_mut().enumerate() {
+            for (col, v) in values.iter_mut().enumerate() {
+                *v = r.f32(280 + row * 16 + col * 4);
+            }
+        }
+
+        Header {
+            version,
+            endian,
+            dims,
+            datatype: r.i16(70),
+            pixdim,
+            vox_offset: r.f32(108).max(0.0) as usize,
+            scl_slope: r.f32(112),
+            scl_inter: r.f32(116),
+            qform_code: r.i16(252) as i32,
+            sform_code: r.i16(254) as i32,
+            quatern: [r.f32(256), r.f32(260), r.f32(264)],
+            qoffset: [r.f32(268), r.f32(272), r.f32(276)],
+            srow,
+            xyzt_units: data[123] as i32,
+        }
+    } else {
+        if &data[4..8] != b"n+2\0" {
+            return Err(anyhow::anyhow!("Invalid NIfTI-2 magic"));
+        }
+
+        for (i, d) in dims.iter_mut().enumerate() {
+            *d = r.i64(16 + i * 8);
+        }
+        for (i, p) in pixdim.iter_mut().enumerate() {
+            *p = r.f64(104 + i * 8);
+        }
+        for (row, values) in srow.iter_mut().enumerate() {
+            for (col, v) in values.iter_mut().enumerate() {
+                *v = r.f64(400 + row * 32 + col * 8);
+            }
+        }
+
+        Header {
+            version,
+            endian,
+            dims,
+            datatype: r.i16(12),
+            pixdim,
+            vox_offset: r.i64(168).max(0) as usize,
+            scl_slope: r.f64(176),
+            scl_inter: r.f64(184),
+            qform_code: r.i32(344),
+            sform_code: r.i32(348),
+            quatern: [r.f64(352), r.f64(360), r.f64(368)],
+            qoffset: [r.f64(376), r.f64(384), r.f64(392)],
+            srow,
+            xyzt_units: r.i32(500),
+        }
+    };
+
+    Ok(header)
+}
+
+fn parse_datatype(datatype: i16) -> Result<SampleType> {
+    let sample_type = match datatype {
+        2 => SampleType::U8,
+        4 => SampleType::I16,
+        8 => SampleType::I32,
+        16 => SampleType::F32,
+        64 => SampleType::F64,
+        256 => SampleType::I8,
+        512 => SampleType::U16,
+        768 => SampleType::U32,
+        1024 => SampleType::I64,
+        1280 => SampleType::U64,
+        _ => return Err(anyhow::anyhow!("Unsupported NIfTI datatype: {}", datatype)),
+    };
+    Ok(sample_type)
+}
+
+fn quaternion_affine(header: &Header) -> [[f64; 4]; 3] {
+    let [b, c, d] = header.quatern;
+    let a = (1.0 - (b * b + c * c + d * d)).max(0.0).sqrt();
+    let qfac = if header.pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+
+    let rotation = [
+        [a * a + b * b - c * c - d * d, 2.0 * (b * c - a * d), 2.0 * (b * d + a * c)],
+        [2.0 * (b * c + a * d), a * a + c * c - b * b - d * d, 2.0 * (c * d - a * b)],
+        [2.0 * (b * d - a * c), 2.0 * (c * d + a * b), a * a + d * d - c * c - b * b],
+    ];
+    let scale = [header.pixdim[1], header.pixdim[2], header.pixdim[3] * qfac];
+
+    let mut affine = [[0.0; 4]; 3];
+    for row in 0..3 {
+        for col in 0..3 {
+            affine[row][col] = rotation[row][col] * scale[col];
+        }
+        affine[row][3] = header.qoffset[row];
+    }
+    affine
+}
+
+fn spatial_unit(xyzt_units: i32) -> Option<String> {
+    match xyzt_units & 0x07 {
+        1 => Some("m".to_string()),
+        2 => Some("mm".to_string()),
+        3 => Some("µm".to_string()),
+        _ => None,
+    }
+}
+
+// The first keep bytes of each channel's run of timepoints, skipping the rest of the
+// run, so a small stream only inflates as far as the load needs
+fn read_channels(mut payload: impl Read, channels: usize, keep: usize, skip: usize) -> Result<Vec<Vec<u8>>> {
+    let mut blocks = Vec::with_capacity(channels);
+    for c in 0..channels {
+        let mut block = Vec::new();
+        (&mut payload).take(keep as u64).read_to_end(&mut block)?;
+        blocks.push(block);
+        if c + 1 < channels {
+            io::copy(&mut (&mut payload).take(skip as u64), &mut io::sink())?;
+        }
+    }
+    Ok(blocks)
+}
+
+pub fn load_nifti_from_memory(data: &[u8]) -> Result<NiftiVolume> {
+    load_nifti_within(data, crate::MAX_SIZE)
+}
+
+fn load_nifti_within(data: &[u8], max_voxels: usize) -> Result<NiftiVolume> {
+    let gzip = is_gzip(data);
+    let mut decoder = MultiGzDecoder::new(data);
+    let mut head = Vec::new();
+    if gzip {
+        (&mut decoder).take(540).read_to_end(&mut head)?;
+    }
+
+    let header = parse_header(if gzip { &head } else { data })?;
+    let sample_type = parse_datatype(header.datatype)?;
+
+    let ndim = header.dims[0];
+    if !(2..=7).contains(&ndim) {
+        return Err(anyhow::anyhow!("Unsupported NIfTI dimension count: {}", ndim));
+    }
+
+    let axis = |i: usize| -> Result<usize> {
+        if (i as i64) > ndim {
+            return Ok(1);
+        }
+        usize::try_from(header.dims[i].max(1))
+            .map_err(|_| anyhow::anyhow!("Invalid NIfTI dimension"))
+    };
+
+    let width = axis(1)?;
+    let height = axis(2)?;
+    let depth = axis(3)?;
+    let timepoints = axis(4)?;
+    let channels = axis(5)?;
+
+    for i in 6..=7 {
+        if axis(i)? > 1 {
+            return Err(anyhow::anyhow!("NIfTI volumes with more than five dimensions are not supported"));
+        }
+    }
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|wh| wh.checked_mul(depth))
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+
+    // Timepoints past the voxel limit are left out, and so are those past the end of the
+    // file. Each channel stores all of its timepoints together.
+    let per_timepoint = count
+        .checked_mul(channels)
+        .filter(|&n| n <= max_voxels)
+        .ok_or_else(|| anyhow::anyhow!("Image data too large to fit in memory"))?;
+    let wanted = timepoints.min(max_voxels / per_timepoint.max(1)).max(1);
+    let volume_bytes = count * sample_type.size();
+    let keep = wanted * volume_bytes;
+    let skip = (timepoints - wanted).saturating_mul(volume_bytes);
+
+    let inflated;
+    let blocks: Vec<&[u8]> = if gzip {
+        let mut payload = head.as_slice().chain(decoder);
+        let skipped = io::copy(&mut (&mut payload).take(header.vox_offset as u64), &mut io::sink())?;
+        if skipped < header.vox_offset as u64 {
+            return Err(anyhow::anyhow!("NIfTI vox_offset past end of file"));
+        }
+        inflated = read_channels(payload, channels, keep, skip)?;
+        inflated.iter().map(Vec::as_slice).collect()
+    } else {
+        let payload = data.get(header.vox_offset..)
+            .ok_or_else(|| anyhow::anyhow!("NIfTI vox_offset past end of file"))?;
+        let run = timepoints.saturating_mul(volume_bytes);
+        (0..channels)
+            .map(|c| {
+                let block = payload.get(c.saturating_mul(run)..).unwrap_or_default();
+                &block[..keep.min(block.len())]
+            })
+            .collect()
+    };
+
+    let available = blocks.iter().map(|block| block.len() / volume_bytes.max(1)).min().unwrap_or(0);
+    let loaded = wanted.min(available).max(1);
+    if loaded < timepoints {
+        info!("NIfTI has {} timepoints, loading the first {}", timepoints, loaded);
+    }
+
+    let mut values = Vec::with_capacity(loaded * per_timepoint);
+    for t in 0..loaded {
+        for block in &blocks {
+            let bytes = block.get(t * volume_bytes..).unwrap_or_default();
+            values.extend(sample::decode_samples(bytes, sample_type, header.endian, count)?);
+        }
+    }
+
+    let slope = header.scl_slope;
+    let inter = header.scl_inter;
+    let scaled = slope.is_finite() && slope != 0.0 && inter.is_finite() && (slope != 1.0 || inter != 0.0);
+    if scaled {
+        for v in values.iter_mut() {
+            *v = (*v as f64 * slope + inter) as f32;
+        }
+    }
+
+    let mut affine = if header.sform_code > 0 {
+        header.srow
+    } else if header.qform_code > 0 {
+        quaternion_affine(&header)
+    } else {
+        let spacing = |i: usize| if header.pixdim[i] > 0.0 { header.pixdim[i] } else { 1.0 };
+        [
+            [spacing(1), 0.0, 0.0, 0.0],
+            [0.0, spacing(2), 0.0, 0.0],
+            [0.0, 0.0, spacing(3), 0.0],
+        ]
+    };
+    for row in &mut affine[..2] {
+        *row = row.map(|v| -v);
+    }
+
+    let mut spacing = [1.0f32; 3];
+    let mut directions = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for axis in 0..3 {
+        let column = [affine[0][axis], affine[1][axis], affine[2][axis]];
+        let norm = (column[0] * column[0] + column[1] * column[1] + column[2] * column[2]).sqrt();
+        if norm > 0.0 {
+            spacing[axis] = norm as f32;
+            directions[axis] = [
+                (column[0] / norm) as f32,
+                (column[1] / norm) as f32,
+                (column[2] / norm) as f32,
+            ];
+        }
+    }
+    let origin = [affine[0][3] as f32, affine[1][3] as f32, affine[2][3] as f32];
+    let affine = affine.map(|row| row.map(|v| v as f32));
+
+    info!("Loaded NIfTI-{} volume: {}x{}x{} ({:?})", header.version, width, height, depth, sample_type);
+    debug!("NIfTI qform_code: {}, sform_code: {}, spacing: {:?}", header.qform_code, header.sform_code, spacing);
+
+    let sample_type = if scaled { SampleType::F32 } else { sample_type };
+
+    Ok(NiftiVolume {
+        data: values,
+        dimensions: (width, height, depth),
+        channels,
+        timepoints: loaded,
+        total_timepoints: timepoints,
+        sample_type,
+        spacing,
+        origin,
+        directions,
+        affine,
+        qform_code: header.qform_code,
+        sform_code: header.sform_code,
+        unit: spatial_unit(header.xyzt_units),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    // 4x3x2 NIfTI-1 image with 0.5 x 0.5 x 2 mm voxels, rotated 180 degrees about z by its qform
+    fn nifti1(datatype: i16, payload: &[u8]) -> Vec<u8> {
+        let mut header = vec![0u8; 352];
+        header[0..4].copy_from_slice(&348i32.to_le_bytes());
+        for (i, d) in [3i16, 4, 3, 2, 1, 1, 1, 1].iter().enumerate() {
+            header[40 + i * 2..42 + i * 2].copy_from_slice(&d.to_le_bytes());
+        }
+        header[70..72].copy_from_slice(&datatype.to_le_bytes());
+        for (i, p) in [-1.0f32, 0.5, 0.5, 2.0].iter().enumerate() {
+            header[76 + i * 4..80 + i * 4].copy_from_slice(&p.to_le_bytes());
+        }
+        header[108..112].copy_from_slice(&352f32.to_le_bytes());
+        header[123] = 2;
+        header[252..254].copy_from_slice(&1i16.to_le_bytes());
+        header[264..268].copy_from_slice(&1f32.to_le_bytes());
+        header[268..272].copy_from_slice(&5f32.to_le_bytes());
+        header[344..348].copy_from_slice(b"n+1\0");
+        header.extend_from_slice(payload);
+        header
+    }
+
+    fn int16_payload() -> Vec<u8> {
+        (0i16..24).flat_map(|v| (-v).to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn qform_orientation() {
+        let nifti = load_nifti_from_memory(&nifti1(4, &int16_payload())).unwrap();
+        assert_eq!(nifti.dimensions, (4, 3, 2));
+        assert_eq!(nifti.sample_type, SampleType::I16);
+        assert_eq!(nifti.data[3], -3.0);
+        assert_eq!(nifti.spacing, [0.5, 0.5, 2.0]);
+        // A half turn about Z in RAS is LPS's own X and Y, with qfac flipping Z
+        assert_eq!(nifti.origin, [-5.0, 0.0, 0.0]);
+        assert_eq!(nifti.directions, [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]);
+        assert_eq!(nifti.unit.as_deref(), Some("mm"));
+    }
+
+    #[test]
+    fn gzip_and_scaling() {
+        let mut file = nifti1(2, &[10; 24]);
+        file[112..116].copy_from_slice(&2f32.to_le_bytes());
+        file[116..120].copy_from_slice(&(-10f32).to_le_bytes());
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&file).unwrap();
+        let nifti = load_nifti_from_memory(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(nifti.sample_type, SampleType::F32);
+        assert_eq!(nifti.data, vec![10.0; 24]);
+    }
+
+    #[test]
+    fn header_only_pairs_are_rejected() {
+        let mut file = nifti1(4, &int16_payload());
+        file[344..348].copy_from_slice(b"ni1\0");
+        assert!(load_nifti_from_memory(&file).is_err());
+        assert!(load_nifti_from_memory(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn channels_past_the_voxel_limit_keep_their_first_timepoints() {
+        // 4x3x2 uint8 image with 4 timepoints of 2 channels, channel 0's run first
+        let mut file = nifti1(2, &(0..192).map(|v| v as u8).collect::<Vec<u8>>());
+        for (i, d) in [5i16, 4, 3, 2, 4, 2].iter().enumerate() {
+            file[40 + i * 2..42 + i * 2].copy_from_slice(&d.to_le_bytes());
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&file).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        // Room for two timepoints, so channel 1 starts past what channel 0 keeps
+        let expected: Vec<f32> = [0, 96, 24, 120].iter().flat_map(|&start| (start..start + 24).map(|v| v as f32)).collect();
+        for data in [&file, &gzipped] {
+            let nifti = load_nifti_within(data, 96).unwrap();
+            assert_eq!((nifti.channels, nifti.timepoints, nifti.total_timepoints), (2, 2, 4));
+            assert_eq!(nifti.data, expected);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Result;
+use std::io::Write;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::debug;
+use crate::sample::{self, Endian, SampleType};
+use crate::VolumeData;
+
+fn datatype(sample_type: SampleType) -> i16 {
+    match sample_type {
+        SampleType::U8 => 2,
+        SampleType::I16 => 4,
+        SampleType::I32 => 8,
+        SampleType::F32 => 16,
+        SampleType::F64 => 64,
+        SampleType::I8 => 256,
+        SampleType::U16 => 512,
+        SampleType::U32 => 768,
+        SampleType::I64 => 1024,
+        SampleType::U64 => 1280,
+    }
+}
+
+// NIfTI only has codes for metres, millimetres and micrometres
+fn unit_code(unit: Option<&str>) -> u8 {
+    match unit {
+        Some("m") => 1,
+        Some("mm") => 2,
+        Some("µm" | "um" | "micron") => 3,
+        _ => 0,
+    }
+}
+
+// Voxel index to world rows: each column is an axis direction scaled by its spacing.
+// NIfTI's world is RAS, so the X and Y rows of the LPS geometry are negated.
+fn affine(volume: &VolumeData) -> [[f64; 4]; 3] {
+    let mut rows = [[0f64; 4]; 3];
+    for (row, values) in rows.iter_mut().enumerate() {
+        let sign = if row < 2 { -1.0 } else { 1.0 };
+        for (axis, value) in values.iter_mut().take(3).enumerate() {
+            *value = sign * (volume.directions[axis][row] * volume.spacing[axis]) as f64;
+        }
+        values[3] = sign * volume.origin[row] as f64;
+    }
+    rows
+}
+
+fn put<const N: usize>(header: &mut [u8], offset: usize, bytes: [u8; N]) {
+    header[offset..offset + N].copy_from_slice(&bytes);
+}
+
+// Channels of a vector-valued image run along the fifth dimension
+const NIFTI_INTENT_VECTOR: i16 = 1007;
+
+// 348-byte header, then the four-byte extension flag
+fn nifti1_header(dims: &[usize], volume: &VolumeData) -> Vec<u8> {
+    let sample_type = volume.sample_type;
+    let mut header = vec![0u8; 352];
+    put(&mut header, 0, 348i32.to_le_bytes());
+    put(&mut header, 40, (dims.len() as i16).to_le_bytes());
+    for i in 1..8 {
+        let size = dims.get(i - 1).copied().unwrap_or(1);
+        put(&mut header, 40 + i * 2, (size as i16).to_le_bytes());
+    }
+    if dims.len() == 5 {
+        put(&mut header, 68, NIFTI_INTENT_VECTOR.to_le_bytes());
+    }
+    put(&mut header, 70, datatype(sample_type).to_le_bytes());
+    put(&mut header, 72, (sample_type.size() as i16 * 8).to_le_bytes());
+    for i in 0..8 {
+        let pixdim = if (1..4).contains(&i) { volume.spacing[i - 1] } else { 1.0 };
+        put(&mut header, 76 + i * 4, pixdim.to_le_bytes());
+    }
+    put(&mut header, 108, 352f32.to_le_bytes());
+    put(&mut header, 112, 1f32.to_le_bytes());
+    header[123] = unit_code(volume.physical_unit());
+    put(&mut header, 254, 1i16.to_le_bytes());
+    for (row, values) in affine(volume).iter().enumerate() {
+        for (col, &value) in values.iter().enumerate() {
+            put(&mut header, 280 + row * 16 + col * 4, (value as f32).to_le_bytes());
+        }
+    }
+    header[344..348].copy_from_slice(b"n+1\0");
+    header
+}
+
+// 540-byte header, then the four-byte extension flag
+fn nifti2_header(dims: &[usize], volume: &VolumeData) -> Vec<u8> {
+    let sample_type = volume.sample_type;
+    let mut header = vec![0u8; 544];
+    put(&mut header, 0, 540i32.to_le_bytes());
+    header[4..12].copy_from_slice(b"n+2\0\r\n\x1a\n");
+    put(&mut header, 12, datatype(sample_type).to_le_bytes());
+    put(&mut header, 14, (sample_type.size() as i16 * 8).to_le_bytes());
+    put(&mut header, 16, (dims.len() as i64).to_le_bytes());
+    for i in 1..8 {
+        let size = dims.get(i - 1).copied().unwrap_or(1);
+        put(&mut header, 16 + i * 8, (size as i64).to_le_bytes());
+    }
+    for i in 0..8 {
+        let pixdim = if (1..4).contains(&i) { volume.spacing[i - 1] as f64 } else { 1.0 };
+        put(&mut header, 104 + i * 8, pixdim.to_le_bytes());
+    }
+    put(&mut header, 168, 544i64.to_le_bytes());
+    put(&mut header, 176, 1f64.to_le_bytes());
+    put(&mut header, 348, 1i32.to_le_bytes());
+    for (row, values) in affine(volume).iter().enumerate() {
+        for (col, &value) in values.iter().enumerate() {
+            put(&mut header, 400 + row * 32 + col * 8, value.to_le_bytes());
+        }
+    }
+    put(&mut header, 500, (unit_code(volume.physical_unit()) as i32).to_le_bytes());
+    if dims.len() == 5 {
+        put(&mut header, 504, (NIFTI_INTENT_VECTOR as i32).to_le_bytes());
+    }
+    header
+}
+
+// Single-file .nii (or .nii.gz) at the source sample format, with the volume's geometry as the
+// sform. Timepoints run along the fourth dimension and channels along the fifth.
+// NIfTI-1 is used unless a dimension is too large for its 16-bit sizes.
+pub fn write_nifti(volume: &VolumeData, gzip: bool) -> Result<Vec<u8>> {
+    let (width, height, depth) = volume.dimensions;
+    let (channels, timepoints) = (volume.channels, volume.timepoints);
+    let mut dims = vec![width, height, depth, timepoints, channels];
+    while dims.len() > 3 && dims.last() == Some(&1) {
+        dims.pop();
+    }
+
+    let mut out = if dims.iter().all(|&d| d <= i16::MAX as usize) {
+        nifti1_header(&dims, volume)
+    } else {
+        debug!("Writing NIfTI-2 for {:?}", dims);
+        nifti2_header(&dims, volume)
+    };
+
+    // The fifth dimension varies slowest, so each channel holds all of its timepoints
+    for c in 0..channels {
+        for t in 0..timepoints {
+            let samples = volume
+                .frame_channel(t, c)
+                .ok_or_else(|| anyhow::anyhow!("No volume data to export"))?;
+            out.extend_from_slice(&sample::encode_samples(samples.iter(), volume.sample_type, Endian::Little));
+        }
+    }
+
+    if gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&out)?;
+        out = encoder.finish()?;
+    }
+
+    debug!("Wrote {} byte NIfTI", out.len());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::Voxels;
+
+    #[test]
+    fn uint16_nrrd_to_nifti() {
+        let mut file = b"NRRD0004\ntype: uint16\ndimension: 3\nsizes: 3 2 2\nendian: little\nencoding: raw\n\
+space directions: (0.5,0,0) (0,0.5,0) (0,0,2)\nspace origin: (1,2,3)\nspace units: \"mm\" \"mm\" \"mm\"\n\n".to_vec();
+        for v in 0u16..12 {
+            file.extend_from_slice(&(v * 5000).to_le_bytes());
+        }
+        let mut volume = VolumeData::default();
+        volume.load_nrrd_from_memory(&file).unwrap();
+
+        // NRRD's LPS origin and axes come out as RAS rows
+        let file = write_nifti(&volume, false).unwrap();
+        let row = |offset: usize| -> Vec<f32> {
+            (0..4).map(|i| f32::from_le_bytes(file[offset + i * 4..offset + i * 4 + 4].try_into().unwrap())).collect()
+        };
+        assert_eq!(row(280), [-0.5, 0.0, 0.0, -1.0]);
+        assert_eq!(row(296), [0.0, -0.5, 0.0, -2.0]);
+        assert_eq!(row(312), [0.0, 0.0, 2.0, 3.0]);
+
+        for gzip in [false, true] {
+            let mut loaded = VolumeData::default();
+            loaded.load_nifti_from_memory(&write_nifti(&volume, gzip).unwrap()).unwrap();
+            assert_eq!(loaded.sample_type, SampleType::U16);
+            assert_eq!(loaded.dimensions, (3, 2, 2));
+            assert_eq!(loaded.raw_data, volume.raw_data);
+            assert_eq!(loaded.spacing, volume.spacing);
+            assert_eq!(loaded.origin, volume.origin);
+            assert_eq!(loaded.directions, volume.directions);
+            assert_eq!(loaded.unit.as_deref(), Some("mm"));
+        }
+    }
+
+    #[test]
+    fn timepoints_and_channels_fill_the_fourth_and_fifth_dimensions() {
+        let data: Vec<f32> = (0..2 * 3 * 12).map(|i| i as f32 * 0.5).collect();
+        let mut volume = VolumeData::default();
+        volume.set_time_series(Voxels::F32(data), (3, 2, 2), 3, 2, SampleType::F32).unwrap();
+
+        let file = write_nifti(&volume, false).unwrap();
+        assert_eq!(i16::from_le_bytes([file[40], file[41]]), 5);
+        let mut loaded = VolumeData::default();
+        loaded.load_nifti_from_memory(&file).unwrap();
+        assert_eq!((loaded.channels, loaded.timepoints), (3, 2));
+        assert_eq!(loaded.raw_data, volume.raw_data);
+    }
+
+    #[test]
+    fn long_axes_need_nifti2() {
+        let mut volume = VolumeData::default();
+        volume.set_samples(vec![7.0; 40000], (40000, 1, 1), 1, SampleType::U16).unwrap();
+
+        let file = write_nifti(&volume, false).unwrap();
+        assert_eq!(&file[4..7], b"n+2");
+        let mut loaded = VolumeData::default();
+        loaded.load_nifti_from_memory(&file).unwrap();
+        assert_eq!(loaded.dimensions, (40000, 1, 1));
+        assert_eq!(loaded.raw_data, volume.raw_data);
+    }
+}
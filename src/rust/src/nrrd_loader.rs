@@ -0,0 +1,498 @@
+use anyhow::Result;
+use std::io::Read;
+use flate2::read::MultiGzDecoder;
+use log::{debug, info};
+use crate::sample::{self, Endian, SampleType};
+
+pub struct NrrdVolume {
+    // Timepoint-major, then channel-major, like VolumeData
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    pub channels: usize,
+    pub timepoints: usize,
+    pub sample_type: SampleType,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    pub directions: [[f32; 3]; 3],
+    pub unit: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Raw,
+    Gzip,
+    Ascii,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AxisRole {
+    Space,
+    Channel,
+    Time,
+}
+
+// Without kinds, every axis is taken to be spatial
+fn axis_roles(kinds: Option<&[String]>, dimension: usize) -> Result<Vec<AxisRole>> {
+    let Some(kinds) = kinds else {
+        return Ok(vec![AxisRole::Space; dimension]);
+    };
+    if kinds.len() != dimension {
+        return Err(anyhow::anyhow!("NRRD kinds do not match dimension"));
+    }
+    let roles = kinds
+        .iter()
+        .map(|kind| match kind.as_str() {
+            "domain" | "space" | "???" | "none" => AxisRole::Space,
+            "time" => AxisRole::Time,
+            _ => AxisRole::Channel,
+        })
+        .collect();
+    Ok(roles)
+}
+
+// Reorders samples stored with the given per-axis strides into t, c, z, y, x order
+fn reorder(values: &[f32], shape: [usize; 5], strides: [usize; 5]) -> Vec<f32> {
+    let [timepoints, channels, depth, height, width] = shape;
+    let [st, sc, sz, sy, sx] = strides;
+    let mut out = Vec::with_capacity(values.len());
+    for t in 0..timepoints {
+        for c in 0..channels {
+            for z in 0..depth {
+                for y in 0..height {
+                    out.extend((0..width).map(|x| values[t * st + c * sc + z * sz + y * sy + x * sx]));
+                }
+            }
+        }
+    }
+    out
+}
+
+pub fn is_nrrd(data: &[u8]) -> bool {
+    data.starts_with(b"NRRD")
+}
+
+fn parse_sample_type(value: &str) -> Result<SampleType> {
+    let sample_type = match value {
+        "signed char" | "int8" | "int8_t" => SampleType::I8,
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => SampleType::U8,
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => SampleType::I16,
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => SampleType::U16,
+        "int" | "signed int" | "int32" | "int32_t" => SampleType::I32,
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => SampleType::U32,
+        "longlong" | "long long" | "long long int" | "signed long long" | "signed long long int"
+        | "int64" | "int64_t" => SampleType::I64,
+        "ulonglong" | "unsigned long long" | "unsigned long long int" | "uint64" | "uint64_t" => SampleType::U64,
+        "float" => SampleType::F32,
+        "double" => SampleType::F64,
+        _ => return Err(anyhow::anyhow!("Unsupported NRRD type: {}", value)),
+    };
+    Ok(sample_type)
+}
+
+fn parse_vector(value: &str) -> Result<[f32; 3]> {
+    let inner = value
+        .trim()
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .ok_or_else(|| anyhow::anyhow!("Malformed NRRD vector: {}", value))?;
+
+    let components: Vec<f32> = inner
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow::anyhow!("Malformed NRRD vector: {}", value))?;
+
+    if components.len() != 3 {
+        return Err(anyhow::anyhow!("Only 3D NRRD spaces are supported"));
+    }
+    Ok([components[0], components[1], components[2]])
+}
+
+// Splits "(1,0,0) (0,1,0) none" into per-axis entries
+fn split_vectors(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        if rest.starts_with('(') {
+            let end = rest.find(')').map(|i| i + 1).unwrap_or(rest.len());
+            parts.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            parts.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+    }
+
+    parts
+}
+
+// Signs taking a named world space to LPS, which is the volume's frame; spaces
+// without anatomical axes (scanner-xyz, 3D-right-handed, ...) are kept as they are
+fn lps_signs(space: &str) -> [f32; 3] {
+    match space.trim_end_matches("-time") {
+        "right-anterior-superior" | "RAS" => [-1.0, -1.0, 1.0],
+        "left-anterior-superior" | "LAS" => [1.0, -1.0, 1.0],
+        _ => [1.0; 3],
+    }
+}
+
+fn split_header(data: &[u8]) -> Result<(&str, &[u8])> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let line_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| pos + i)
+            .ok_or_else(|| anyhow::anyhow!("NRRD header is not terminated"))?;
+
+        let line = &data[pos..line_end];
+        if line.is_empty() || line == b"\r" {
+            let header = std::str::from_utf8(&data[..pos])
+                .map_err(|_| anyhow::anyhow!("NRRD header is not valid text"))?;
+            return Ok((header, &data[line_end + 1..]));
+        }
+        pos = line_end + 1;
+    }
+
+    // Header-only files end without a blank line
+    let header = std::str::from_utf8(data)
+        .map_err(|_| anyhow::anyhow!("NRRD header is not valid text"))?;
+    Ok((header, &[]))
+}
+
+pub fn load_nrrd_from_memory(data: &[u8]) -> Result<NrrdVolume> {
+    if !is_nrrd(data) {
+        return Err(anyhow::anyhow!("Not an NRRD file"));
+    }
+
+    let (header, payload) = split_header(data)?;
+
+    let mut sample_type = None;
+    let mut dimension = None;
+    let mut sizes: Vec<usize> = Vec::new();
+    let mut kinds: Option<Vec<String>> = None;
+    let mut encoding = Encoding::Raw;
+    let mut endian = Endian::Little;
+    let mut spacings: Option<Vec<f32>> = None;
+    let mut space_directions: Option<Vec<Option<[f32; 3]>>> = None;
+    let mut space_origin = None;
+    let mut world_signs = [1.0f32; 3];
+    let mut space_units: Option<String> = None;
+    let mut byte_skip: i64 = 0;
+    let mut line_skip: usize = 0;
+
+    for line in header.lines().skip(1) {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with('#') || line.contains(":=") {
+            continue;
+        }
+
+        let Some((field, value)) = line.split_once(": ") else {
+            continue;
+        };
+        let value = value.trim();
+
+        match field.trim() {
+            "type" => sample_type = Some(parse_sample_type(value)?),
+            "dimension" => {
+                dimension = Some(value.parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Invalid NRRD dimension: {}", value))?);
+            },
+            "sizes" => {
+                sizes = value
+                    .split_whitespace()
+                    .map(|s| s.parse::<usize>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| anyhow::anyhow!("Invalid NRRD sizes: {}", value))?;
+            },
+            "kinds" => kinds = Some(value.split_whitespace().map(str::to_string).collect()),
+            "encoding" => {
+                encoding = match value {
+                    "raw" => Encoding::Raw,
+                    "gzip" | "gz" => Encoding::Gzip,
+                    "ascii" | "text" | "txt" => Encoding::Ascii,
+                    _ => return Err(anyhow::anyhow!("Unsupported NRRD encoding: {}", value)),
+                };
+            },
+            "endian" => {
+                endian = match value {
+                    "little" => Endian::Little,
+                    "big" => Endian::Big,
+                    _ => return Err(anyhow::anyhow!("Invalid NRRD endian: {}", value)),
+                };
+            },
+            "spacings" => {
+                spacings = Some(value
+                    .split_whitespace()
+                    .map(|s| s.parse::<f32>().unwrap_or(f32::NAN))
+                    .collect());
+            },
+            "space directions" => {
+                let directions = split_vectors(value)
+                    .into_iter()
+                    .map(|v| if v == "none" { Ok(None) } else { parse_vector(v).map(Some) })
+                    .collect::<Result<_>>()?;
+                space_directions = Some(directions);
+            },
+            "space" => world_signs = lps_signs(value),
+            "space origin" => space_origin = Some(parse_vector(value)?),
+            // One quoted unit per world axis, e.g. "mm" "mm" "mm"
+            "space units" => {
+                space_units = value
+                    .split('"')
+                    .map(str::trim)
+                    .find(|unit| !unit.is_empty())
+                    .map(str::to_string);
+            },
+            "byte skip" => {
+                byte_skip = value.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid NRRD byte skip: {}", value))?;
+            },
+            "line skip" => {
+                line_skip = value.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid NRRD line skip: {}", value))?;
+            },
+            "data file" | "datafile" => {
+                return Err(anyhow::anyhow!("Detached NRRD data files are not supported"));
+            },
+            _ => debug!("Ignoring NRRD field: {}", field),
+        }
+    }
+
+    let sample_type = sample_type.ok_or_else(|| anyhow::anyhow!("NRRD header missing type"))?;
+    let dimension = dimension.ok_or_else(|| anyhow::anyhow!("NRRD header missing dimension"))?;
+
+    if sizes.len() != dimension {
+        return Err(anyhow::anyhow!("NRRD sizes do not match dimension"));
+    }
+    if !(2..=5).contains(&dimension) {
+        return Err(anyhow::anyhow!("Unsupported NRRD dimension: {}", dimension));
+    }
+
+    // Besides two or three spatial axes there may be one channel and one time axis
+    let roles = axis_roles(kinds.as_deref(), dimension)?;
+    let find = |role: AxisRole| roles.iter().enumerate().filter(move |(_, &r)| r == role).map(|(axis, _)| axis);
+    let spatial: Vec<usize> = find(AxisRole::Space).collect();
+    let channel_axes: Vec<usize> = find(AxisRole::Channel).collect();
+    let time_axes: Vec<usize> = find(AxisRole::Time).collect();
+    if !(2..=3).contains(&spatial.len()) || channel_axes.len() > 1 || time_axes.len() > 1 {
+        return Err(anyhow::anyhow!("Unsupported NRRD axes: {}", kinds.unwrap_or_default().join(" ")));
+    }
+
+    let width = sizes[spatial[0]];
+    let height = sizes[spatial[1]];
+    let depth = spatial.get(2).map(|&axis| sizes[axis]).unwrap_or(1);
+    let channels = channel_axes.first().map(|&axis| sizes[axis]).unwrap_or(1);
+    let timepoints = time_axes.first().map(|&axis| sizes[axis]).unwrap_or(1);
+
+    let count = sizes
+        .iter()
+        .try_fold(1usize, |acc, &size| acc.checked_mul(size))
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+    if count > crate::MAX_SIZE {
+        return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+    }
+    let needed = count * sample_type.size();
+
+    // Line skip applies to the stored bytes, byte skip to the decoded stream
+    let mut payload = payload;
+    for _ in 0..line_skip {
+        let end = payload
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| anyhow::anyhow!("NRRD line skip past end of data"))?;
+        payload = &payload[end + 1..];
+    }
+
+    let decompressed;
+    let mut bytes = match encoding {
+        Encoding::Gzip => {
+            // Nothing past the samples is read, so a small stream cannot inflate without bound
+            let limit = needed + byte_skip.max(0) as usize;
+            let mut buf = Vec::new();
+            MultiGzDecoder::new(payload).take(limit as u64).read_to_end(&mut buf)?;
+            decompressed = buf;
+            &decompressed[..]
+        },
+        _ => payload,
+    };
+
+    let data = if encoding == Encoding::Ascii {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| anyhow::anyhow!("NRRD ascii data is not valid text"))?;
+        let values: Vec<f32> = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .take(count)
+            .map(|s| s.parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| anyhow::anyhow!("Invalid value in NRRD ascii data"))?;
+
+        if values.len() < count {
+            return Err(anyhow::anyhow!("Not enough samples in NRRD ascii data"));
+        }
+        values
+    } else {
+        if byte_skip == -1 {
+            if bytes.len() < needed {
+                return Err(anyhow::anyhow!("Not enough data in NRRD file"));
+            }
+            bytes = &bytes[bytes.len() - needed..];
+        } else if byte_skip > 0 {
+            let skip = byte_skip as usize;
+            if skip > bytes.len() {
+                return Err(anyhow::anyhow!("NRRD byte skip past end of data"));
+            }
+            bytes = &bytes[skip..];
+        }
+        sample::decode_samples(bytes, sample_type, endian, count)?
+    };
+
+    let mut strides = vec![1; dimension];
+    for axis in 1..dimension {
+        strides[axis] = strides[axis - 1] * sizes[axis - 1];
+    }
+    let stride = |axis: Option<&usize>| axis.map(|&a| strides[a]).unwrap_or(0);
+    let strides = [
+        stride(time_axes.first()),
+        stride(channel_axes.first()),
+        stride(spatial.get(2)),
+        strides[spatial[1]],
+        strides[spatial[0]],
+    ];
+    let in_order = spatial.iter().chain(&channel_axes).chain(&time_axes).copied().eq(0..dimension);
+    let data = if in_order {
+        data
+    } else {
+        reorder(&data, [timepoints, channels, depth, height, width], strides)
+    };
+
+    let mut spacing = [1.0f32; 3];
+    let mut directions = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    if let Some(space_directions) = space_directions {
+        let spatial: Vec<[f32; 3]> = space_directions.into_iter().flatten().collect();
+        for (axis, vector) in spatial.iter().take(3).enumerate() {
+            let norm = (vector[0] * vector[0] + vector[1] * vector[1] + vector[2] * vector[2]).sqrt();
+            if norm > 0.0 {
+                spacing[axis] = norm;
+                directions[axis] = [0, 1, 2].map(|i| vector[i] * world_signs[i] / norm);
+            }
+        }
+    } else if let Some(spacings) = spacings {
+        for (axis, &file_axis) in spatial.iter().enumerate() {
+            if let Some(&value) = spacings.get(file_axis).filter(|v| v.is_finite() && **v > 0.0) {
+                spacing[axis] = value;
+            }
+        }
+    }
+
+    let origin = space_origin.unwrap_or([0.0; 3]);
+    let origin = [0, 1, 2].map(|i| origin[i] * world_signs[i]);
+
+    info!(
+        "Loaded NRRD volume: {}x{}x{} with {} channels and {} timepoints ({:?})",
+        width, height, depth, channels, timepoints, sample_type
+    );
+    debug!("NRRD spacing: {:?}, origin: {:?}", spacing, origin);
+
+    Ok(NrrdVolume {
+        data,
+        dimensions: (width, height, depth),
+        channels,
+        timepoints,
+        sample_type,
+        spacing,
+        origin,
+        directions,
+        unit: space_units,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn raw_big_endian_with_geometry() {
+        let mut file = b"NRRD0004\n# comment\ntype: unsigned short\ndimension: 3\nsizes: 2 2 2\nencoding: raw\nendian: big\n\
+space: left-posterior-superior\nspace directions: (0.5,0,0) (0,0.5,0) (0,0,2)\nspace origin: (1,2,3)\n\n".to_vec();
+        for v in 0u16..8 {
+            file.extend_from_slice(&(v * 1000).to_be_bytes());
+        }
+
+        let nrrd = load_nrrd_from_memory(&file).unwrap();
+        assert_eq!(nrrd.dimensions, (2, 2, 2));
+        assert_eq!(nrrd.sample_type, SampleType::U16);
+        assert_eq!(nrrd.data[5], 5000.0);
+        assert_eq!(nrrd.spacing, [0.5, 0.5, 2.0]);
+        assert_eq!(nrrd.origin, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn gzip_and_ascii_encodings() {
+        let samples: Vec<u8> = (0..8).collect();
+        let mut file = b"NRRD0004\ntype: uint8\ndimension: 3\nsizes: 2 2 2\nencoding: gzip\n\n".to_vec();
+        file.extend_from_slice(&gzip(&samples));
+        assert_eq!(load_nrrd_from_memory(&file).unwrap().data, (0..8).map(|v| v as f32).collect::<Vec<_>>());
+
+        let file = b"NRRD0004\ntype: float\ndimension: 2\nsizes: 2 2\nencoding: ascii\nspacings: 1 3\n\n1.5 2 3\n4\n";
+        let nrrd = load_nrrd_from_memory(file).unwrap();
+        assert_eq!(nrrd.data, vec![1.5, 2.0, 3.0, 4.0]);
+        assert_eq!(nrrd.spacing, [1.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn gzip_stops_after_the_samples() {
+        // Far more data than the header asks for is not inflated
+        let mut file = b"NRRD0004\ntype: uint8\ndimension: 2\nsizes: 2 2\nencoding: gzip\n\n".to_vec();
+        file.extend_from_slice(&gzip(&vec![7u8; 4 * 1024 * 1024]));
+        assert_eq!(load_nrrd_from_memory(&file).unwrap().data, vec![7.0; 4]);
+    }
+
+    #[test]
+    fn short_data_is_an_error() {
+        let file = b"NRRD0004\ntype: uint16\ndimension: 2\nsizes: 2 2\nencoding: raw\n\n\x01\x02";
+        assert!(load_nrrd_from_memory(file).is_err());
+        assert!(load_nrrd_from_memory(b"P5 2 2").is_err());
+    }
+
+    #[test]
+    fn interleaved_components_and_time_axis() {
+        // Two components per voxel, fastest varying, then 2x1x1 voxels and two timepoints
+        let file = b"NRRD0004\ntype: uint8\ndimension: 5\nsizes: 2 2 1 1 2\nkinds: vector domain domain domain time\n\
+space directions: none (2,0,0) (0,1,0) (0,0,1) none\nspacings: nan 2 1 1 nan\nencoding: ascii\n\n\
+1 10 2 20\n3 30 4 40\n";
+        let nrrd = load_nrrd_from_memory(file).unwrap();
+        assert_eq!(nrrd.dimensions, (2, 1, 1));
+        assert_eq!((nrrd.channels, nrrd.timepoints), (2, 2));
+        assert_eq!(nrrd.data, vec![1.0, 2.0, 10.0, 20.0, 3.0, 4.0, 30.0, 40.0]);
+        assert_eq!(nrrd.spacing, [2.0, 1.0, 1.0]);
+
+        let file = b"NRRD0004\ntype: uint8\ndimension: 4\nsizes: 1 2 2 1\nkinds: time domain domain time\nencoding: ascii\n\n1 2 3 4\n";
+        assert!(load_nrrd_from_memory(file).is_err());
+    }
+
+    #[test]
+    fn ras_space_is_turned_into_lps() {
+        let mut file = b"NRRD0004\ntype: uint8\ndimension: 3\nsizes: 1 1 1\nencoding: raw\n\
+space: right-anterior-superior\nspace directions: (0,2,0) (1,0,0) (0,0,3)\nspace origin: (1,2,3)\n\n".to_vec();
+        file.push(9);
+
+        let nrrd = load_nrrd_from_memory(&file).unwrap();
+        assert_eq!(nrrd.spacing, [2.0, 1.0, 3.0]);
+        assert_eq!(nrrd.directions, [[0.0, -1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert_eq!(nrrd.origin, [-1.0, -2.0, 3.0]);
+    }
+}
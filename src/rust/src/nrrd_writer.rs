@@ -0,0 +1,131 @@
+use anyhow::Result;
+use std::io::Write;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::debug;
+use crate::sample::{self, Endian, SampleType};
+use crate::VolumeData;
+
+fn type_name(sample_type: SampleType) -> &'static str {
+    match sample_type {
+        SampleType::U8 => "uint8",
+        SampleType::I8 => "int8",
+        SampleType::U16 => "uint16",
+        SampleType::I16 => "int16",
+        SampleType::U32 => "uint32",
+        SampleType::I32 => "int32",
+        SampleType::U64 => "uint64",
+        SampleType::I64 => "int64",
+        SampleType::F32 => "float",
+        SampleType::F64 => "double",
+    }
+}
+
+// Attached-header .nrrd at the source sample format. Channels and timepoints, when there
+// is more than one, become a list and a time axis after the three spatial ones.
+pub fn write_nrrd(volume: &VolumeData, gzip: bool) -> Result<Vec<u8>> {
+    if volume.raw_data.is_empty() {
+        return Err(anyhow::anyhow!("No volume data to export"));
+    }
+    let (width, height, depth) = volume.dimensions;
+
+    let mut sizes = vec![width, height, depth];
+    let mut kinds = vec!["domain"; 3];
+    if volume.channels > 1 {
+        sizes.push(volume.channels);
+        kinds.push("list");
+    }
+    if volume.timepoints > 1 {
+        sizes.push(volume.timepoints);
+        kinds.push("time");
+    }
+    let sizes: Vec<String> = sizes.iter().map(usize::to_string).collect();
+
+    let mut out = Vec::new();
+    writeln!(out, "NRRD0004")?;
+    writeln!(out, "# Complete NRRD file format specification at:")?;
+    writeln!(out, "# http://teem.sourceforge.net/nrrd/format.html")?;
+    writeln!(out, "type: {}", type_name(volume.sample_type))?;
+    writeln!(out, "dimension: {}", sizes.len())?;
+    writeln!(out, "sizes: {}", sizes.join(" "))?;
+    if kinds.len() > 3 {
+        writeln!(out, "kinds: {}", kinds.join(" "))?;
+    }
+    if volume.sample_type.size() > 1 {
+        writeln!(out, "endian: little")?;
+    }
+    writeln!(out, "space: left-posterior-superior")?;
+    let mut directions: Vec<String> = volume
+        .directions
+        .iter()
+        .zip(volume.spacing)
+        .map(|(d, s)| format!("({},{},{})", d[0] * s, d[1] * s, d[2] * s))
+        .collect();
+    directions.resize(kinds.len(), "none".to_string());
+    writeln!(out, "space directions: {}", directions.join(" "))?;
+    let [x, y, z] = volume.origin;
+    writeln!(out, "space origin: ({},{},{})", x, y, z)?;
+    if let Some(unit) = volume.physical_unit() {
+        writeln!(out, "space units: \"{0}\" \"{0}\" \"{0}\"", unit)?;
+    }
+    writeln!(out, "encoding: {}", if gzip { "gzip" } else { "raw" })?;
+    writeln!(out)?;
+
+    // Stored timepoint by timepoint and channel by channel, which is the axis order above
+    let payload = sample::encode_samples(volume.raw_data.as_slice().iter(), volume.sample_type, Endian::Little);
+    if gzip {
+        let mut encoder = GzEncoder::new(out, Compression::default());
+        encoder.write_all(&payload)?;
+        out = encoder.finish()?;
+    } else {
+        out.extend_from_slice(&payload);
+    }
+
+    debug!("Wrote {} byte NRRD", out.len());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::Voxels;
+
+    #[test]
+    fn uint16_nrrd_round_trip() {
+        let mut file = b"NRRD0004\ntype: uint16\ndimension: 3\nsizes: 3 2 2\nendian: little\nencoding: raw\n\
+space: left-posterior-superior\nspace directions: (0,0.5,0) (-0.5,0,0) (0,0,2)\nspace origin: (1,2,3)\nspace units: \"mm\" \"mm\" \"mm\"\n\n".to_vec();
+        for v in 0u16..12 {
+            file.extend_from_slice(&(v * 5000).to_le_bytes());
+        }
+        let mut volume = VolumeData::default();
+        volume.load_nrrd_from_memory(&file).unwrap();
+
+        for gzip in [false, true] {
+            let mut loaded = VolumeData::default();
+            loaded.load_nrrd_from_memory(&write_nrrd(&volume, gzip).unwrap()).unwrap();
+            assert_eq!(loaded.sample_type, SampleType::U16);
+            assert_eq!(loaded.dimensions, (3, 2, 2));
+            assert_eq!(loaded.raw_data, volume.raw_data);
+            assert_eq!(loaded.spacing, volume.spacing);
+            assert_eq!(loaded.origin, volume.origin);
+            assert_eq!(loaded.directions, volume.directions);
+            assert_eq!(loaded.unit.as_deref(), Some("mm"));
+        }
+    }
+
+    #[test]
+    fn channels_and_timepoints_get_their_own_axes() {
+        let data: Vec<i16> = (0..2 * 3 * 12).map(|i| i as i16 - 30).collect();
+        let mut volume = VolumeData::default();
+        volume.set_time_series(Voxels::I16(data), (3, 2, 2), 3, 2, SampleType::I16).unwrap();
+
+        let file = write_nrrd(&volume, false).unwrap();
+        let text = String::from_utf8_lossy(&file);
+        assert!(text.contains("sizes: 3 2 2 3 2\nkinds: domain domain domain list time\n"), "{}", text);
+
+        let mut loaded = VolumeData::default();
+        loaded.load_nrrd_from_memory(&file).unwrap();
+        assert_eq!((loaded.channels, loaded.timepoints), (3, 2));
+        assert_eq!(loaded.raw_data, volume.raw_data);
+    }
+}
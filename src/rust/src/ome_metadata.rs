@@ -0,0 +1,301 @@
+use anyhow::Result;
+use log::{debug, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaneIndex {
+    pub z: usize,
+    pub c: usize,
+    pub t: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct OmeMetadata {
+    pub size_x: usize,
+    pub size_y: usize,
+    pub size_z: usize,
+    // Number of channel planes, i.e. SizeC divided by samples per pixel
+    pub size_c: usize,
+    pub size_t: usize,
+    pub dimension_order: String,
+    // All in physical_size_unit, which is the X unit
+    pub physical_size: [Option<f32>; 3],
+    pub physical_size_unit: String,
+    pub channel_names: Vec<String>,
+    plane_count: usize,
+    // TiffData blocks, looked up per IFD rather than expanded, since their
+    // IFD and PlaneCount attributes are not bounded by anything in the file
+    blocks: Vec<TiffDataBlock>,
+}
+
+// PlaneCount IFDs from ifd onwards hold the planes from linear index start
+#[derive(Clone, Copy, Debug)]
+struct TiffDataBlock {
+    ifd: usize,
+    start: usize,
+    count: usize,
+}
+
+pub fn is_ome_xml(description: &str) -> bool {
+    description.contains("<OME")
+}
+
+fn attr_usize(node: &roxmltree::Node, name: &str) -> Result<Option<usize>> {
+    node.attribute(name)
+        .map(|v| v.trim().parse::<usize>()
+            .map_err(|_| anyhow::anyhow!("Invalid OME-XML {}: {}", name, v)))
+        .transpose()
+}
+
+fn attr_f32(node: &roxmltree::Node, name: &str) -> Option<f32> {
+    node.attribute(name)
+        .and_then(|v| v.trim().parse::<f32>().ok())
+        .filter(|v| v.is_finite() && *v > 0.0)
+}
+
+// Length units OME-TIFF writers commonly use, in metres
+fn unit_in_metres(unit: &str) -> Option<f32> {
+    let scale = match unit {
+        "m" => 1.0,
+        "cm" => 1e-2,
+        "mm" => 1e-3,
+        "µm" | "um" => 1e-6,
+        "nm" => 1e-9,
+        "Å" => 1e-10,
+        _ => return None,
+    };
+    Some(scale)
+}
+
+impl OmeMetadata {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(xml)
+            .map_err(|e| anyhow::anyhow!("Invalid OME-XML: {}", e))?;
+
+        let pixels = doc
+            .descendants()
+            .find(|n| n.tag_name().name() == "Pixels")
+            .ok_or_else(|| anyhow::anyhow!("OME-XML has no Pixels element"))?;
+
+        let required = |name: &str| -> Result<usize> {
+            attr_usize(&pixels, name)?
+                .filter(|&v| v > 0)
+                .ok_or_else(|| anyhow::anyhow!("OME-XML Pixels missing {}", name))
+        };
+
+        let size_x = required("SizeX")?;
+        let size_y = required("SizeY")?;
+        let size_z = required("SizeZ")?;
+        let total_c = required("SizeC")?;
+        let size_t = required("SizeT")?;
+
+        let dimension_order = pixels.attribute("DimensionOrder").unwrap_or("XYZCT").to_string();
+        let valid_order = dimension_order.len() == 5
+            && dimension_order.starts_with("XY")
+            && ['Z', 'C', 'T'].iter().all(|&d| dimension_order[2..].contains(d));
+        if !valid_order {
+            return Err(anyhow::anyhow!("Invalid OME-XML DimensionOrder: {}", dimension_order));
+        }
+
+        let channels: Vec<roxmltree::Node> = pixels
+            .children()
+            .filter(|n| n.tag_name().name() == "Channel")
+            .collect();
+
+        // RGB channels stored as samples of one plane count once towards the IFD layout
+        let size_c = if channels.is_empty() { total_c } else { channels.len() };
+
+        let channel_names = channels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| c.attribute("Name").map(str::to_string).unwrap_or_else(|| format!("Channel {}", i)))
+            .collect();
+
+        let plane_count = size_z
+            .checked_mul(size_c)
+            .and_then(|zc| zc.checked_mul(size_t))
+            .ok_or_else(|| anyhow::anyhow!("OME-XML plane count is too large"))?;
+
+        // Y and Z sizes are converted to the X unit when they are given in another one
+        let unit = pixels.attribute("PhysicalSizeXUnit").unwrap_or("µm").to_string();
+        let mut physical_size = [
+            attr_f32(&pixels, "PhysicalSizeX"),
+            attr_f32(&pixels, "PhysicalSizeY"),
+            attr_f32(&pixels, "PhysicalSizeZ"),
+        ];
+        for (size, axis) in physical_size.iter_mut().zip(["X", "Y", "Z"]).skip(1) {
+            let axis_unit = pixels.attribute(format!("PhysicalSize{}Unit", axis).as_str()).unwrap_or("µm");
+            if axis_unit == unit {
+                continue;
+            }
+            match (unit_in_metres(axis_unit), unit_in_metres(&unit)) {
+                (Some(from), Some(to)) => *size = size.map(|s| s * (from / to)),
+                _ => warn!("Cannot convert OME-XML PhysicalSize{} from {} to {}", axis, axis_unit, unit),
+            }
+        }
+
+        let mut metadata = Self {
+            size_x,
+            size_y,
+            size_z,
+            size_c,
+            size_t,
+            dimension_order,
+            physical_size,
+            physical_size_unit: unit,
+            channel_names,
+            plane_count,
+            blocks: Vec::new(),
+        };
+
+        for block in pixels.children().filter(|n| n.tag_name().name() == "TiffData") {
+            let ifd = attr_usize(&block, "IFD")?;
+            let first = PlaneIndex {
+                z: attr_usize(&block, "FirstZ")?.unwrap_or(0),
+                c: attr_usize(&block, "FirstC")?.unwrap_or(0),
+                t: attr_usize(&block, "FirstT")?.unwrap_or(0),
+            };
+            if first.z >= size_z || first.c >= size_c || first.t >= size_t {
+                debug!("Skipping OME-XML TiffData outside the image: {:?}", first);
+                continue;
+            }
+
+            let start = metadata.linear_index(first);
+            let count = match (attr_usize(&block, "PlaneCount")?, ifd) {
+                (Some(count), _) => count,
+                (None, Some(_)) => 1,
+                (None, None) => plane_count,
+            };
+            metadata.blocks.push(TiffDataBlock {
+                ifd: ifd.unwrap_or(0),
+                start,
+                count: count.min(plane_count - start),
+            });
+        }
+
+        debug!(
+            "OME-XML: {}x{}x{} C={} T={} order={}",
+            size_x, size_y, size_z, size_c, size_t, metadata.dimension_order
+        );
+
+        Ok(metadata)
+    }
+
+    fn axis_sizes(&self) -> Vec<(char, usize)> {
+        self.dimension_order[2..]
+            .chars()
+            .map(|axis| match axis {
+                'Z' => (axis, self.size_z),
+                'C' => (axis, self.size_c),
+                _ => (axis, self.size_t),
+            })
+            .collect()
+    }
+
+    fn plane_at(&self, index: usize) -> PlaneIndex {
+        let mut plane = PlaneIndex { z: 0, c: 0, t: 0 };
+        let mut rest = index;
+        for (axis, size) in self.axis_sizes() {
+            let value = rest % size;
+            rest /= size;
+            match axis {
+                'Z' => plane.z = value,
+                'C' => plane.c = value,
+                _ => plane.t = value,
+            }
+        }
+        plane
+    }
+
+    fn linear_index(&self, plane: PlaneIndex) -> usize {
+        let mut index = 0;
+        let mut stride = 1;
+        for (axis, size) in self.axis_sizes() {
+            let value = match axis {
+                'Z' => plane.z,
+                'C' => plane.c,
+                _ => plane.t,
+            };
+            index += value * stride;
+            stride *= size;
+        }
+        index
+    }
+
+    // Without TiffData elements the IFDs hold the planes in order; otherwise a later
+    // block takes precedence over an earlier one that names the same IFD
+    pub fn plane(&self, ifd: usize) -> Option<PlaneIndex> {
+        if self.blocks.is_empty() {
+            return (ifd < self.plane_count).then(|| self.plane_at(ifd));
+        }
+
+        self.blocks.iter().rev().find_map(|block| {
+            let offset = ifd.checked_sub(block.ifd).filter(|&k| k < block.count)?;
+            Some(self.plane_at(block.start + offset))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(attributes: &str, children: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><OME xmlns="http://www.openmicroscopy.org/Schemas/OME/2016-06"><Image ID="Image:0"><Pixels ID="Pixels:0" Type="uint16" SizeX="2" SizeY="2" {}>{}</Pixels></Image></OME>"#,
+            attributes, children
+        )
+    }
+
+    #[test]
+    fn dimension_order_and_channels() {
+        let xml = pixels(
+            r#"DimensionOrder="XYCZT" SizeZ="3" SizeC="2" SizeT="2" PhysicalSizeX="0.1" PhysicalSizeZ="1.0""#,
+            r#"<Channel ID="Channel:0:0" Name="DAPI"/><Channel ID="Channel:0:1"/>"#,
+        );
+        let ome = OmeMetadata::parse(&xml).unwrap();
+        assert_eq!((ome.size_z, ome.size_c, ome.size_t), (3, 2, 2));
+        assert_eq!(ome.channel_names, vec!["DAPI".to_string(), "Channel 1".to_string()]);
+        assert_eq!(ome.physical_size, [Some(0.1), None, Some(1.0)]);
+        assert_eq!(ome.plane(1), Some(PlaneIndex { z: 0, c: 1, t: 0 }));
+        assert_eq!(ome.plane(7), Some(PlaneIndex { z: 0, c: 1, t: 1 }));
+        assert_eq!(ome.plane(12), None);
+    }
+
+    #[test]
+    fn tiff_data_blocks() {
+        let xml = pixels(
+            r#"DimensionOrder="XYZCT" SizeZ="2" SizeC="1" SizeT="2""#,
+            r#"<TiffData IFD="1" FirstT="1" PlaneCount="2"/><TiffData IFD="3" FirstZ="1"/>"#,
+        );
+        let ome = OmeMetadata::parse(&xml).unwrap();
+        assert_eq!(ome.plane(0), None);
+        assert_eq!(ome.plane(1), Some(PlaneIndex { z: 0, c: 0, t: 1 }));
+        assert_eq!(ome.plane(2), Some(PlaneIndex { z: 1, c: 0, t: 1 }));
+        assert_eq!(ome.plane(3), Some(PlaneIndex { z: 1, c: 0, t: 0 }));
+    }
+
+    #[test]
+    fn z_unit_is_converted() {
+        let xml = pixels(
+            r#"SizeZ="2" SizeC="1" SizeT="1" PhysicalSizeX="500" PhysicalSizeXUnit="nm" PhysicalSizeZ="2" PhysicalSizeZUnit="µm""#,
+            "",
+        );
+        let ome = OmeMetadata::parse(&xml).unwrap();
+        assert_eq!(ome.physical_size_unit, "nm");
+        assert_eq!(ome.physical_size[2], Some(2000.0));
+    }
+
+    #[test]
+    fn oversized_attributes_do_not_allocate() {
+        let xml = pixels(r#"SizeZ="100000000000" SizeC="1" SizeT="100000000000""#, "");
+        assert!(OmeMetadata::parse(&xml).is_err());
+
+        let xml = pixels(
+            r#"SizeZ="100000" SizeC="1" SizeT="100000""#,
+            r#"<TiffData IFD="1000000000000" PlaneCount="1000000000000"/>"#,
+        );
+        let ome = OmeMetadata::parse(&xml).unwrap();
+        assert_eq!(ome.plane(0), None);
+        assert_eq!(ome.plane(1_000_000_000_001), Some(PlaneIndex { z: 1, c: 0, t: 0 }));
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use wasm_bindgen::prelude::*;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use log::debug;
+use crate::transfer_function::TransferFunction;
+use crate::voxels::VoxelSlice;
+use crate::VolumeData;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceMapping {
+    // Stored values, clamped to the PNG's range
+    Raw,
+    // The volume's display range stretched over the PNG's range
+    Window,
+    // RGBA colors from the transfer function
+    TransferFunction,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct PngExportOptions {
+    pub sixteen_bit: bool,
+    pub mapping: SliceMapping,
+}
+
+impl Default for PngExportOptions {
+    fn default() -> Self {
+        Self {
+            sixteen_bit: false,
+            mapping: SliceMapping::Window,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PngExportOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> PngExportOptions {
+        Self::default()
+    }
+}
+
+// Channel values in 0..=1 for one voxel, before scaling to the bit depth
+fn map_value(volume: &VolumeData, value: f32, options: &PngExportOptions, transfer_func: &TransferFunction) -> ([f32; 4], usize) {
+    let max = if options.sixteen_bit { u16::MAX as f32 } else { u8::MAX as f32 };
+    match options.mapping {
+        SliceMapping::Raw => ([value / max, 0.0, 0.0, 0.0], 1),
+        SliceMapping::Window => ([volume.get_normalized_value(value), 0.0, 0.0, 0.0], 1),
+        SliceMapping::TransferFunction => (transfer_func.get_color_3d(volume.get_normalized_value(value)), 4),
+    }
+}
+
+fn encode_slice(
+    volume: &VolumeData,
+    slice: VoxelSlice<'_>,
+    options: &PngExportOptions,
+    transfer_func: &TransferFunction,
+) -> Result<Vec<u8>> {
+    let (width, height, _) = volume.dimensions;
+    let channels = if options.mapping == SliceMapping::TransferFunction { 4 } else { 1 };
+
+    let mut bytes = Vec::with_capacity(slice.len() * channels * if options.sixteen_bit { 2 } else { 1 });
+    for value in slice.iter() {
+        let (color, count) = map_value(volume, value, options, transfer_func);
+        for &c in &color[..count] {
+            let c = c.clamp(0.0, 1.0);
+            if options.sixteen_bit {
+                // The encoder takes native-endian samples and converts them itself
+                bytes.extend_from_slice(&((c * u16::MAX as f32).round() as u16).to_ne_bytes());
+            } else {
+                bytes.push((c * u8::MAX as f32).round() as u8);
+            }
+        }
+    }
+
+    let color_type = match (channels, options.sixteen_bit) {
+        (1, false) => ColorType::L8,
+        (1, true) => ColorType::L16,
+        (_, false) => ColorType::Rgba8,
+        (_, true) => ColorType::Rgba16,
+    };
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&bytes, u32::try_from(width)?, u32::try_from(height)?, color_type)?;
+    Ok(png)
+}
+
+// One PNG per Z slice of the active channel, named slice_0000.png, slice_0001.png, ...
+pub fn export_png_slices(
+    volume: &VolumeData,
+    options: &PngExportOptions,
+    transfer_func: &TransferFunction,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let data = volume
+        .channel_data(volume.active_channel)
+        .ok_or_else(|| anyhow::anyhow!("No volume data to export"))?;
+    let (width, height, depth) = volume.dimensions;
+
+    let digits = depth.saturating_sub(1).to_string().len().max(4);
+    let mut files = Vec::with_capacity(depth);
+    let plane = width * height;
+    for z in 0..depth {
+        let name = format!("slice_{:0width$}.png", z, width = digits);
+        let slice = data.range(z * plane, (z + 1) * plane);
+        files.push((name, encode_slice(volume, slice, options, transfer_func)?));
+    }
+
+    debug!("Encoded {} PNG slices ({:?})", files.len(), options.mapping);
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::SampleType;
+
+    fn volume() -> VolumeData {
+        // Two channels of 3x2x2; the second channel holds 100..=1200
+        let mut data: Vec<f32> = (0..12).map(|v| v as f32).collect();
+        data.extend((1..=12).map(|v| v as f32 * 100.0));
+        let mut volume = VolumeData::default();
+        volume.set_samples(data, (3, 2, 2), 2, SampleType::U16).unwrap();
+        volume.active_channel = 1;
+        volume
+    }
+
+    fn decode(png: &[u8]) -> image::DynamicImage {
+        image::load_from_memory_with_format(png, image::ImageFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn one_png_per_slice_of_the_active_channel() {
+        let volume = volume();
+        let options = PngExportOptions { sixteen_bit: true, mapping: SliceMapping::Raw };
+        let files = export_png_slices(&volume, &options, &TransferFunction::default()).unwrap();
+
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["slice_0000.png", "slice_0001.png"]);
+        let image = decode(&files[1].1).into_luma16();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.into_raw(), [700, 800, 900, 1000, 1100, 1200]);
+    }
+
+    #[test]
+    fn window_stretches_the_display_range() {
+        let mut volume = volume();
+        volume.value_range = (100.0, 600.0);
+        let files = export_png_slices(&volume, &PngExportOptions::default(), &TransferFunction::default()).unwrap();
+
+        let image = decode(&files[0].1);
+        assert_eq!(image.color(), image::ColorType::L8);
+        assert_eq!(image.into_luma8().into_raw(), [0, 51, 102, 153, 204, 255]);
+    }
+
+    #[test]
+    fn transfer_function_gives_rgba() {
+        let mut volume = volume();
+        volume.value_range = (100.0, 600.0);
+        let options = PngExportOptions { sixteen_bit: false, mapping: SliceMapping::TransferFunction };
+        let files = export_png_slices(&volume, &options, &TransferFunction::default()).unwrap();
+
+        let image = decode(&files[0].1).into_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(2, 1).0, [255, 255, 255, 255]);
+        // Values past the range clamp to the last color
+        assert_eq!(decode(&files[1].1).into_rgba8().get_pixel(0, 0).0, [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use wasm_bindgen::prelude::*;
+use log::info;
+use crate::sample::{self, Endian, SampleType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceOrder {
+    // First slice in the file is z = 0
+    Ascending,
+    // First slice in the file is the last z
+    Descending,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct RawDescriptor {
+    pub(crate) dimensions: (usize, usize, usize),
+    pub(crate) sample_type: SampleType,
+    pub(crate) endian: Endian,
+    pub(crate) header_offset: usize,
+    pub(crate) slice_order: SliceOrder,
+    // Volumes stored back to back in the file
+    pub(crate) timepoints: usize,
+}
+
+pub struct RawVolume {
+    // One width x height x depth block per timepoint
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    pub timepoints: usize,
+    pub sample_type: SampleType,
+}
+
+fn parse_sample_type(value: &str) -> Result<SampleType> {
+    let sample_type = match value.to_ascii_lowercase().as_str() {
+        "u8" | "uint8" => SampleType::U8,
+        "i8" | "int8" => SampleType::I8,
+        "u16" | "uint16" => SampleType::U16,
+        "i16" | "int16" => SampleType::I16,
+        "u32" | "uint32" => SampleType::U32,
+        "i32" | "int32" => SampleType::I32,
+        "f32" | "float32" => SampleType::F32,
+        "f64" | "float64" => SampleType::F64,
+        _ => return Err(anyhow::anyhow!("Unsupported raw sample type: {}", value)),
+    };
+    Ok(sample_type)
+}
+
+impl RawDescriptor {
+    pub fn new(
+        dimensions: (usize, usize, usize),
+        sample_type: SampleType,
+        endian: Endian,
+        header_offset: usize,
+        slice_order: SliceOrder,
+    ) -> Self {
+        Self { dimensions, sample_type, endian, header_offset, slice_order, timepoints: 1 }
+    }
+
+    pub fn with_timepoints(mut self, timepoints: usize) -> Self {
+        self.timepoints = timepoints;
+        self
+    }
+
+    pub fn expected_len(&self) -> Result<usize> {
+        let (width, height, depth) = self.dimensions;
+        width
+            .checked_mul(height)
+            .and_then(|wh| wh.checked_mul(depth))
+            .and_then(|count| count.checked_mul(self.timepoints))
+            .and_then(|count| count.checked_mul(self.sample_type.size()))
+            .and_then(|bytes| bytes.checked_add(self.header_offset))
+            .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))
+    }
+}
+
+#[wasm_bindgen]
+impl RawDescriptor {
+    #[wasm_bindgen(constructor)]
+    pub fn from_js(
+        width: usize,
+        height: usize,
+        depth: usize,
+        sample_type: &str,
+        endian: &str,
+        header_offset: usize,
+        slice_order: &str,
+    ) -> Result<RawDescriptor, JsValue> {
+        let sample_type = parse_sample_type(sample_type)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let endian = match endian {
+            "little" => Endian::Little,
+            "big" => Endian::Big,
+            _ => return Err(JsValue::from_str("Endianness must be \"little\" or \"big\"")),
+        };
+
+        let slice_order = match slice_order {
+            "ascending" => SliceOrder::Ascending,
+            "descending" => SliceOrder::Descending,
+            _ => return Err(JsValue::from_str("Slice order must be \"ascending\" or \"descending\"")),
+        };
+
+        Ok(Self::new((width, height, depth), sample_type, endian, header_offset, slice_order))
+    }
+
+    // For files holding a time series of volumes, each laid out as described
+    pub fn set_timepoints(&mut self, timepoints: usize) {
+        self.timepoints = timepoints;
+    }
+}
+
+pub fn load_raw_from_memory(data: &[u8], descriptor: &RawDescriptor) -> Result<RawVolume> {
+    let (width, height, depth) = descriptor.dimensions;
+    let timepoints = descriptor.timepoints;
+    if width == 0 || height == 0 || depth == 0 || timepoints == 0 {
+        return Err(anyhow::anyhow!("Raw volume dimensions must be non-zero"));
+    }
+
+    let expected = descriptor.expected_len()?;
+    if data.len() != expected {
+        return Err(anyhow::anyhow!(
+            "Raw data size mismatch: {}x{}x{}x{} {:?} samples with a {} byte header need {} bytes, but the file has {}",
+            width,
+            height,
+            depth,
+            timepoints,
+            descriptor.sample_type,
+            descriptor.header_offset,
+            expected,
+            data.len()
+        ));
+    }
+
+    let count = width * height * depth * timepoints;
+    let mut values = sample::decode_samples(
+        &data[descriptor.header_offset..],
+        descriptor.sample_type,
+        descriptor.endian,
+        count,
+    )?;
+
+    // Slices are reversed within each timepoint; the timepoints keep their order
+    if descriptor.slice_order == SliceOrder::Descending {
+        let slice_len = width * height;
+        let reversed: Vec<f32> = values
+            .chunks_exact(slice_len * depth)
+            .flat_map(|volume| volume.chunks_exact(slice_len).rev().flatten())
+            .copied()
+            .collect();
+        values = reversed;
+    }
+
+    info!(
+        "Loaded raw volume: {}x{}x{}, {} timepoints ({:?})",
+        width, height, depth, timepoints, descriptor.sample_type
+    );
+
+    Ok(RawVolume {
+        data: values,
+        dimensions: descriptor.dimensions,
+        timepoints,
+        sample_type: descriptor.sample_type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_and_descending_slices() {
+        let descriptor = RawDescriptor::new((2, 1, 3), SampleType::U16, Endian::Big, 2, SliceOrder::Descending);
+        let data = [0, 0, 0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6];
+        let raw = load_raw_from_memory(&data, &descriptor).unwrap();
+        assert_eq!(raw.data, vec![5.0, 6.0, 3.0, 4.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        let descriptor = RawDescriptor::new((2, 1, 3), SampleType::U16, Endian::Big, 2, SliceOrder::Ascending);
+        let error = load_raw_from_memory(&[0; 13], &descriptor).err().unwrap().to_string();
+        assert!(error.contains("need 14 bytes, but the file has 13"), "{}", error);
+    }
+}
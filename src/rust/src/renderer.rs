@@ -44,6 +44,54 @@ impl Ray {
     }
 }
 
+// The volume's box in normalized space, centred on the origin and measured
+// along the volume's own axes
+struct VolumeBounds {
+    min: na::Point3<f32>,
+    max: na::Point3<f32>,
+    // Voxels per normalized unit along each axis
+    voxels_per_unit: na::Vector3<f32>,
+    // Rows are the volume's axis directions, so this takes world vectors onto them
+    to_axes: na::Matrix3<f32>,
+}
+
+impl VolumeBounds {
+    fn new(volume: &VolumeData) -> Self {
+        let (width, height, depth) = volume.dimensions;
+        let dims = na::Vector3::new(width as f32, height as f32, depth as f32);
+        let extent = dims.component_mul(&na::Vector3::from(volume.spacing));
+        let scale = 1.0 / extent.max();
+        let half = extent * scale * 0.5;
+
+        let [x, y, z] = volume.directions.map(na::Vector3::from);
+        // Degenerate directions would collapse the box, so they fall back to the image axes
+        let to_axes = match na::Matrix3::from_rows(&[x.transpose(), y.transpose(), z.transpose()]) {
+            m if m.determinant().abs() > f32::EPSILON => m,
+            _ => na::Matrix3::identity(),
+        };
+
+        Self {
+            min: na::Point3::from(-half),
+            max: na::Point3::from(half),
+            voxels_per_unit: dims.component_div(&(extent * scale)),
+            to_axes,
+        }
+    }
+
+    fn to_volume_axes(&self, ray: Ray) -> Ray {
+        Ray {
+            origin: na::Point3::from(self.to_axes * ray.origin.coords),
+            direction: (self.to_axes * ray.direction).normalize(),
+        }
+    }
+
+    fn voxel(&self, pos: &na::Point3<f32>, dimensions: (usize, usize, usize)) -> (usize, usize, usize) {
+        let index = (pos - self.min).component_mul(&self.voxels_per_unit);
+        let clamp = |v: f32, size: usize| v.clamp(0.0, size as f32 - 1.0).floor() as usize;
+        (clamp(index.x, dimensions.0), clamp(index.y, dimensions.1), clamp(index.z, dimensions.2))
+    }
+}
+
 impl VolumeRenderer {
     pub fn new(width: usize, height: usize) -> Self {
         info!("Creating VolumeRenderer with dimensions: {}x{}", width, height);
@@ -64,34 +112,22 @@ impl VolumeRenderer {
         let view_proj = proj * view;
         let inv_view_proj = view_proj.try_inverse().unwrap();
 
-        // Scale volume bounds based on dimensions
-        let max_dim = volume.dimensions.0.max(volume.dimensions.1).max(volume.dimensions.2) as f32;
-        let scale = 1.0 / max_dim;
-        let volume_min = na::Point3::new(
-            -0.5 * volume.dimensions.0 as f32 * scale,
-            -0.5 * volume.dimensions.1 as f32 * scale,
-            -0.5 * volume.dimensions.2 as f32 * scale
-        );
-        let volume_max = na::Point3::new(
-            0.5 * volume.dimensions.0 as f32 * scale,
-            0.5 * volume.dimensions.1 as f32 * scale,
-            0.5 * volume.dimensions.2 as f32 * scale
-        );
-
-        debug!("Volume bounds: min={:?}, max={:?}", volume_min, volume_max);
+        // Scale volume bounds so the longest physical side is 1
+        let bounds = VolumeBounds::new(volume);
+        debug!("Volume bounds: min={:?}, max={:?}", bounds.min, bounds.max);
 
         let mut hit_count = 0;
         let sample_count = 0;
 
         for y in 0..self.height {
             for x in 0..self.width {
-                let ray = self.generate_ray(x, y, &inv_view_proj);
+                let ray = bounds.to_volume_axes(self.generate_ray(x, y, &inv_view_proj));
                 let color = if volume.dimensions.2 == 1 {
                     // 2D image mode
-                    self.cast_ray_2d(&ray, volume, transfer_func, volume_min, volume_max)
+                    self.cast_ray_2d(&ray, volume, transfer_func, &bounds)
                 } else {
                     // 3D volume mode
-                    self.cast_ray_3d(&ray, volume, transfer_func, volume_min, volume_max)
+                    self.cast_ray_3d(&ray, volume, transfer_func, &bounds)
                 };
                 
                 if color[3] > 0 {
@@ -124,19 +160,14 @@ impl VolumeRenderer {
         ray: &Ray,
         volume: &VolumeData,
         transfer_func: &TransferFunction,
-        volume_min: na::Point3<f32>,
-        volume_max: na::Point3<f32>,
+        bounds: &VolumeBounds,
     ) -> [u8; 4] {
-        if let Some((t_min, t_max)) = ray.intersect_box(&volume_min, &volume_max) {
+        if let Some((t_min, t_max)) = ray.intersect_box(&bounds.min, &bounds.max) {
             // For 2D, sample at the intersection point
             let pos = ray.at((t_min + t_max) * 0.5);
             
             // Convert from normalized coordinates to image coordinates
-            let x = ((pos.x + 0.5) * volume.dimensions.0 as f32).clamp(0.0, volume.dimensions.0 as f32 - 1.0);
-            let y = ((pos.y + 0.5) * volume.dimensions.1 as f32).clamp(0.0, volume.dimensions.1 as f32 - 1.0);
-            
-            let x_idx = x.floor() as usize;
-            let y_idx = y.floor() as usize;
+            let (x_idx, y_idx, _) = bounds.voxel(&pos, volume.dimensions);
             
             if let Some(value) = volume.sample(x_idx, y_idx, 0) {
                 let normalized = volume.get_normalized_value(value);
@@ -158,10 +189,9 @@ impl VolumeRenderer {
         ray: &Ray,
         volume: &VolumeData,
         transfer_func: &TransferFunction,
-        volume_min: na::Point3<f32>,
-        volume_max: na::Point3<f32>,
+        bounds: &VolumeBounds,
     ) -> [u8; 4] {
-        if let Some((t_min, t_max)) = ray.intersect_box(&volume_min, &volume_max) {
+        if let Some((t_min, t_max)) = ray.intersect_box(&bounds.min, &bounds.max) {
             let mut color = [0.0f32; 4];
             let mut alpha = 0.0f32;
             let mut t = t_min;
@@ -170,17 +200,7 @@ impl VolumeRenderer {
                 let pos = ray.at(t);
                 
                 // Convert from normalized space to volume space
-                let max_dim = volume.dimensions.0.max(volume.dimensions.1).max(volume.dimensions.2) as f32;
-                let scale = max_dim;
-                let sample_pos = na::Point3::new(
-                    (pos.x * scale + 0.5 * volume.dimensions.0 as f32).clamp(0.0, volume.dimensions.0 as f32 - 1.0),
-                    (pos.y * scale + 0.5 * volume.dimensions.1 as f32).clamp(0.0, volume.dimensions.1 as f32 - 1.0),
-                    (pos.z * scale + 0.5 * volume.dimensions.2 as f32).clamp(0.0, volume.dimensions.2 as f32 - 1.0),
-                );
-                
-                let x = sample_pos.x.floor() as usize;
-                let y = sample_pos.y.floor() as usize;
-                let z = sample_pos.z.floor() as usize;
+                let (x, y, z) = bounds.voxel(&pos, volume.dimensions);
                 
                 if let Some(value) = volume.sample(x, y, z) {
                     let normalized = volume.get_normalized_value(value);
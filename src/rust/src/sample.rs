@@ -0,0 +1,116 @@
+use anyhow::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl SampleType {
+    pub fn size(self) -> usize {
+        match self {
+            SampleType::U8 | SampleType::I8 => 1,
+            SampleType::U16 | SampleType::I16 => 2,
+            SampleType::U32 | SampleType::I32 | SampleType::F32 => 4,
+            SampleType::U64 | SampleType::I64 | SampleType::F64 => 8,
+        }
+    }
+
+    // Full range representable by the type; floats have no meaningful one
+    pub fn nominal_range(self) -> Option<(f32, f32)> {
+        match self {
+            SampleType::U8 => Some((0.0, u8::MAX as f32)),
+            SampleType::I8 => Some((i8::MIN as f32, i8::MAX as f32)),
+            SampleType::U16 => Some((0.0, u16::MAX as f32)),
+            SampleType::I16 => Some((i16::MIN as f32, i16::MAX as f32)),
+            SampleType::U32 => Some((0.0, u32::MAX as f32)),
+            SampleType::I32 => Some((i32::MIN as f32, i32::MAX as f32)),
+            SampleType::U64 => Some((0.0, u64::MAX as f32)),
+            SampleType::I64 => Some((i64::MIN as f32, i64::MAX as f32)),
+            SampleType::F32 | SampleType::F64 => None,
+        }
+    }
+}
+
+pub fn decode_samples(bytes: &[u8], sample_type: SampleType, endian: Endian, count: usize) -> Result<Vec<f32>> {
+    let size = sample_type.size();
+    let needed = count
+        .checked_mul(size)
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+
+    if bytes.len() < needed {
+        return Err(anyhow::anyhow!(
+            "Not enough sample data: expected {} bytes, found {}",
+            needed,
+            bytes.len()
+        ));
+    }
+
+    let mut samples = Vec::with_capacity(count);
+
+    for chunk in bytes[..needed].chunks_exact(size) {
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(chunk);
+        if endian == Endian::Big {
+            buf[..size].reverse();
+        }
+
+        let value = match sample_type {
+            SampleType::U8 => buf[0] as f32,
+            SampleType::I8 => buf[0] as i8 as f32,
+            SampleType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f32,
+            SampleType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f32,
+            SampleType::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f32,
+            SampleType::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f32,
+            SampleType::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            SampleType::U64 => u64::from_le_bytes(buf) as f32,
+            SampleType::I64 => i64::from_le_bytes(buf) as f32,
+            SampleType::F64 => f64::from_le_bytes(buf) as f32,
+        };
+        samples.push(value);
+    }
+
+    Ok(samples)
+}
+
+// Inverse of decode_samples; integer samples are rounded and saturate at the type's limits
+pub fn encode_samples(values: impl ExactSizeIterator<Item = f32>, sample_type: SampleType, endian: Endian) -> Vec<u8> {
+    let size = sample_type.size();
+    let mut bytes = Vec::with_capacity(values.len() * size);
+
+    for v in values {
+        let mut buf = [0u8; 8];
+        match sample_type {
+            SampleType::U8 => buf[0] = v.round() as u8,
+            SampleType::I8 => buf[0] = v.round() as i8 as u8,
+            SampleType::U16 => buf[..2].copy_from_slice(&(v.round() as u16).to_le_bytes()),
+            SampleType::I16 => buf[..2].copy_from_slice(&(v.round() as i16).to_le_bytes()),
+            SampleType::U32 => buf[..4].copy_from_slice(&(v.round() as u32).to_le_bytes()),
+            SampleType::I32 => buf[..4].copy_from_slice(&(v.round() as i32).to_le_bytes()),
+            SampleType::F32 => buf[..4].copy_from_slice(&v.to_le_bytes()),
+            SampleType::U64 => buf = (v.round() as u64).to_le_bytes(),
+            SampleType::I64 => buf = (v.round() as i64).to_le_bytes(),
+            SampleType::F64 => buf = (v as f64).to_le_bytes(),
+        }
+        if endian == Endian::Big {
+            buf[..size].reverse();
+        }
+        bytes.extend_from_slice(&buf[..size]);
+    }
+
+    bytes
+}
@@ -0,0 +1,241 @@
+use anyhow::Result;
+use std::io::{Cursor, Read, Seek};
+use tiff::decoder::ifd::Value;
+use tiff::decoder::{Decoder, Limits};
+use tiff::tags::Tag;
+use log::debug;
+use crate::ome_metadata::OmeMetadata;
+use crate::sample::SampleType;
+use crate::tiff_loader::{self, PageCollector, PageStatus, TiffLoadOptions};
+
+#[derive(Clone, Debug)]
+pub struct Resolution {
+    pub x: f64,
+    pub y: f64,
+    // "none", "inch" or "centimeter"
+    pub unit: &'static str,
+}
+
+#[derive(Clone, Debug)]
+pub struct PageSummary {
+    pub index: usize,
+    // As stored, before the orientation is applied
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub bits_per_sample: u16,
+    // None for bit depths and formats the loader cannot decode
+    pub sample_type: Option<SampleType>,
+    pub compression: String,
+    pub orientation: u16,
+    pub description: Option<String>,
+    pub resolution: Option<Resolution>,
+    // What a load with the same options would do with the page
+    pub status: PageStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TiffSummary {
+    pub big_tiff: bool,
+    pub pages: Vec<PageSummary>,
+    pub ome: Option<OmeMetadata>,
+    // Shape of the VolumeData a load with the same options would produce
+    pub dimensions: (usize, usize, usize),
+    pub channels: usize,
+    pub timepoints: usize,
+    pub estimated_bytes: usize,
+    pub truncated: bool,
+    // Set when the load would fail outright
+    pub error: Option<String>,
+}
+
+fn compression_name(code: u16) -> String {
+    match code {
+        1 => "none".to_string(),
+        2 => "CCITT RLE".to_string(),
+        3 | 4 => "CCITT fax".to_string(),
+        5 => "LZW".to_string(),
+        6 | 7 => "JPEG".to_string(),
+        8 | 32946 => "Deflate".to_string(),
+        32773 => "PackBits".to_string(),
+        33003 | 33004 | 33005 | 34712 => "JPEG 2000".to_string(),
+        34925 => "LZMA".to_string(),
+        50000 => "Zstandard".to_string(),
+        50001 => "WebP".to_string(),
+        _ => format!("unknown ({})", code),
+    }
+}
+
+fn sample_type(format: u16, bits: u16) -> Option<SampleType> {
+    match (format, bits) {
+        (1, 8) => Some(SampleType::U8),
+        (1, 16) => Some(SampleType::U16),
+        (1, 32) => Some(SampleType::U32),
+        (2, 8) => Some(SampleType::I8),
+        (2, 16) => Some(SampleType::I16),
+        (2, 32) => Some(SampleType::I32),
+        (3, 32) => Some(SampleType::F32),
+        (3, 64) => Some(SampleType::F64),
+        _ => None,
+    }
+}
+
+fn rational(value: Value) -> Option<f64> {
+    match value {
+        Value::Rational(n, d) if d != 0 => Some(n as f64 / d as f64),
+        Value::RationalBig(n, d) if d != 0 => Some(n as f64 / d as f64),
+        Value::Float(v) => Some(v as f64),
+        Value::Double(v) => Some(v),
+        value => value.into_u32().ok().map(f64::from),
+    }
+}
+
+// Per-sample tags such as BitsPerSample hold one value per sample; the first is reported
+fn first_u16<R: Read + Seek>(decoder: &mut Decoder<R>, tag: Tag) -> Result<Option<u16>> {
+    let value = match decoder.find_tag(tag)? {
+        Some(Value::List(values)) => values.into_iter().next(),
+        value => value,
+    };
+    Ok(value.and_then(|value| value.into_u16().ok()))
+}
+
+pub(crate) fn read_resolution<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<Option<Resolution>> {
+    let x = decoder.find_tag(Tag::XResolution)?.and_then(rational);
+    let y = decoder.find_tag(Tag::YResolution)?.and_then(rational);
+    let unit = match first_u16(decoder, Tag::ResolutionUnit)?.unwrap_or(2) {
+        1 => "none",
+        3 => "centimeter",
+        _ => "inch",
+    };
+
+    Ok(match (x, y) {
+        (Some(x), Some(y)) => Some(Resolution { x, y, unit }),
+        (Some(x), None) => Some(Resolution { x, y: x, unit }),
+        _ => None,
+    })
+}
+
+// Reads the current page's tags without touching its pixel data
+fn read_page<R: Read + Seek>(decoder: &mut Decoder<R>, index: usize) -> Result<PageSummary> {
+    let (width, height) = decoder.dimensions()?;
+    let samples_per_pixel = first_u16(decoder, Tag::SamplesPerPixel)?.unwrap_or(1) as usize;
+    let bits_per_sample = first_u16(decoder, Tag::BitsPerSample)?.unwrap_or(1);
+    let format = first_u16(decoder, Tag::SampleFormat)?.unwrap_or(1);
+    let photometric = first_u16(decoder, Tag::PhotometricInterpretation)?;
+
+    // Palette indices are expanded to 16-bit RGB when loaded
+    let sample_type = match photometric {
+        Some(3) if bits_per_sample == 8 => Some(SampleType::U16),
+        _ => sample_type(format, bits_per_sample),
+    };
+
+    Ok(PageSummary {
+        index,
+        width: width as usize,
+        height: height as usize,
+        samples_per_pixel,
+        bits_per_sample,
+        sample_type,
+        compression: compression_name(first_u16(decoder, Tag::Compression)?.unwrap_or(1)),
+        orientation: first_u16(decoder, Tag::Orientation)?.unwrap_or(1),
+        description: decoder.get_tag_ascii_string(Tag::ImageDescription).ok(),
+        resolution: read_resolution(decoder)?,
+        status: PageStatus::Used,
+        reason: None,
+    })
+}
+
+// Walks every directory and works out what loading with these options would
+// produce, without decoding any pixel data
+pub fn inspect_tiff(data: &[u8], options: &TiffLoadOptions) -> Result<TiffSummary> {
+    if !tiff_loader::is_tiff(data) {
+        return Err(anyhow::anyhow!("Not a TIFF file"));
+    }
+
+    let mut decoder = Decoder::new(Cursor::new(data))?.with_limits(Limits::unlimited());
+    let mut collector = PageCollector::without_decoding(*options);
+    collector.read_metadata(&mut decoder);
+
+    let mut pages = Vec::new();
+    let mut error = None;
+    let mut index = 0;
+    loop {
+        pages.push(read_page(&mut decoder, index)?);
+
+        // Pages after a failure are listed but would never be reached
+        if error.is_none() {
+            if let Err(e) = collector.add_page(&mut decoder, index) {
+                error = Some(e.to_string());
+            }
+        }
+
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image()?;
+        index += 1;
+    }
+
+    let stack = collector.stack();
+    for (i, page) in pages.iter_mut().enumerate() {
+        match stack.report.pages.get(i) {
+            Some(report) => {
+                page.status = report.status;
+                page.reason = report.reason.clone();
+            },
+            None => {
+                page.status = PageStatus::Dropped;
+                page.reason = error.clone();
+            },
+        }
+    }
+
+    let used = stack.ifds.len();
+    if error.is_none() && used == 0 {
+        error = Some("No valid image data found in TIFF".to_string());
+    }
+
+    let (width, height) = collector.slice_size().unwrap_or((0, 0));
+    let samples = match width * height * used {
+        0 => 0,
+        voxels => collector.loaded_voxels() / voxels,
+    };
+
+    // OME-TIFF pages are rearranged into Z slices and channels of each complete timepoint
+    let (depth, channels, timepoints) = match (&stack.ome, stack.ome_layout()) {
+        (Some(ome), Some(layout)) => {
+            if error.is_none() && layout.timepoints == 0 {
+                error = Some(match stack.report.truncated {
+                    true => "OME-TIFF planes for the first timepoint exceed the load limits".to_string(),
+                    false => "OME-TIFF is missing planes for the first timepoint".to_string(),
+                });
+            }
+            (layout.depth, ome.size_c * samples, layout.timepoints)
+        },
+        _ => (used, samples, 1),
+    };
+
+    let (dimensions, channels, timepoints) = match error {
+        Some(_) => ((0, 0, 0), 0, 0),
+        None => ((width, height, depth), channels, timepoints),
+    };
+    let voxels = dimensions.0 * dimensions.1 * dimensions.2 * channels * timepoints;
+    let bytes_per_voxel = match collector.loaded_voxels() {
+        0 => 0,
+        loaded => collector.loaded_bytes() / loaded,
+    };
+    debug!("TIFF inspection: {} pages, {} voxels", pages.len(), voxels);
+
+    Ok(TiffSummary {
+        big_tiff: matches!(&data[2..4], b"+\0" | b"\0+"),
+        pages,
+        ome: stack.ome.clone(),
+        dimensions,
+        channels,
+        timepoints,
+        estimated_bytes: voxels * bytes_per_voxel,
+        truncated: stack.report.truncated,
+        error,
+    })
+}
@@ -1,106 +1,1074 @@
 use anyhow::Result;
-use std::io::Cursor;
-use image::{DynamicImage, imageops};
-use tiff::decoder::{Decoder, DecodingResult};
-use log::{debug, info};
+use std::io::{Cursor, Read, Seek};
+use wasm_bindgen::prelude::*;
+use tiff::ColorType;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use log::{debug, info, warn};
+use crate::ome_metadata::{self, OmeMetadata};
+use crate::sample::SampleType;
+use crate::tiff_inspect;
+use crate::voxels::{map_voxels, Voxel, VoxelSlice, Voxels};
 
 pub struct ImageInfo {
-    pub data: Vec<u16>,
+    // One plane per channel, back to back
+    pub data: Voxels,
     pub width: usize,
     pub height: usize,
+    pub channels: usize,
+    pub sample_type: SampleType,
 }
 
-fn handle_exif_orientation(img: DynamicImage) -> DynamicImage {
-    DynamicImage::ImageRgba8(imageops::rotate180(&img.to_rgba8()))
-}
-
-fn convert_rgb_to_grayscale(rgb_data: &[u8], width: usize, height: usize) -> Vec<u16> {
-    let mut grayscale = Vec::with_capacity(width * height);
-    
-    for y in 0..height {
-        for x in 0..width {
-            let base_idx = (y * width + x) * 3;
-            
-            if base_idx + 2 < rgb_data.len() {
-                let r = rgb_data[base_idx] as f32;
-                let g = rgb_data[base_idx + 1] as f32;
-                let b = rgb_data[base_idx + 2] as f32;
-                
-                let gray = (0.2989 * r + 0.5870 * g + 0.1140 * b) as u16;
-                grayscale.push(gray);
-            } else {
-                grayscale.push(0);
+impl ImageInfo {
+    pub fn channel(&self, channel: usize) -> VoxelSlice<'_> {
+        let plane = self.width * self.height;
+        self.data.as_slice().range(channel * plane, (channel + 1) * plane)
+    }
+}
+
+pub const DEFAULT_MAX_DIMENSION: usize = 8192;
+pub const DEFAULT_MAX_SLICES: usize = 512;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct TiffLoadOptions {
+    // Collapse color and multi-sample pages into a single luminance channel
+    pub grayscale: bool,
+    pub max_dimension: usize,
+    pub max_slices: usize,
+    // Total voxels across all slices and channels
+    pub max_voxels: usize,
+    // Load reduced-resolution and mask pages instead of skipping them
+    pub include_subfiles: bool,
+    // Z slices z_start, z_start + z_step, ... before z_end are loaded; a z_end of 0 means the last slice
+    pub z_start: usize,
+    pub z_end: usize,
+    pub z_step: usize,
+    // XY crop applied after orientation; a width or height of 0 extends to the page edge
+    pub crop_x: usize,
+    pub crop_y: usize,
+    pub crop_width: usize,
+    pub crop_height: usize,
+    // Each binning x binning block of the crop is averaged into one pixel
+    pub binning: usize,
+}
+
+impl Default for TiffLoadOptions {
+    fn default() -> Self {
+        Self {
+            grayscale: false,
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            max_slices: DEFAULT_MAX_SLICES,
+            // Every voxel takes at least a byte, so by default the memory budget is the limit
+            max_voxels: crate::MAX_BYTES,
+            include_subfiles: false,
+            z_start: 0,
+            z_end: 0,
+            z_step: 1,
+            crop_x: 0,
+            crop_y: 0,
+            crop_width: 0,
+            crop_height: 0,
+            binning: 1,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl TiffLoadOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> TiffLoadOptions {
+        Self::default()
+    }
+}
+
+impl TiffLoadOptions {
+    pub fn slice_range(&self) -> SliceRange {
+        SliceRange {
+            start: self.z_start,
+            end: (self.z_end > 0).then_some(self.z_end),
+            step: self.z_step.max(1),
+        }
+    }
+}
+
+// Z slices picked out of a stack, numbered as in the file (or by OME Z index)
+#[derive(Clone, Copy, Debug)]
+pub struct SliceRange {
+    pub start: usize,
+    pub end: Option<usize>,
+    pub step: usize,
+}
+
+impl SliceRange {
+    // Where slice z ends up in the loaded stack, if it is kept at all
+    pub fn position(&self, z: usize) -> Option<usize> {
+        if z < self.start || self.end.is_some_and(|end| z >= end) || !(z - self.start).is_multiple_of(self.step) {
+            return None;
+        }
+        Some((z - self.start) / self.step)
+    }
+
+    // Number of slices kept out of a stack of the given depth
+    pub fn count(&self, depth: usize) -> usize {
+        let end = self.end.map_or(depth, |end| end.min(depth));
+        end.saturating_sub(self.start).div_ceil(self.step)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageStatus {
+    Used,
+    // Left out by design, e.g. a thumbnail or mask
+    Skipped,
+    // Left out because of a limit or a mismatch with the other pages
+    Dropped,
+}
+
+#[derive(Clone, Debug)]
+pub struct PageReport {
+    pub index: usize,
+    pub width: usize,
+    pub height: usize,
+    pub status: PageStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LoadReport {
+    // Set when a limit stopped loading before the end of the file
+    pub truncated: bool,
+    pub warnings: Vec<String>,
+    // Every TIFF page that was looked at, in file order
+    pub pages: Vec<PageReport>,
+}
+
+impl LoadReport {
+    pub(crate) fn truncate(&mut self, message: String) {
+        warn!("{}", message);
+        self.truncated = true;
+        self.warnings.push(message);
+    }
+}
+
+pub struct TiffStack {
+    pub slices: Vec<ImageInfo>,
+    // IFD index each slice was read from
+    pub ifds: Vec<usize>,
+    pub slice_range: SliceRange,
+    // Pixels averaged into each loaded pixel along X and Y
+    pub binning: usize,
+    pub ome: Option<OmeMetadata>,
+    // Pixel size from the first page's resolution tags or ImageJ description
+    pub spacing: Option<PageSpacing>,
+    pub report: LoadReport,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageSpacing {
+    // X and Y after orientation, then Z; None where the file does not say
+    pub spacing: [Option<f32>; 3],
+    pub unit: String,
+}
+
+// Loaded OME-TIFF pages arranged into whole timepoints
+pub struct OmeLayout {
+    pub depth: usize,
+    // Timepoints whose planes were all loaded; any after the first incomplete one are left out
+    pub timepoints: usize,
+    // Index into ifds (and slices) of each plane, ordered by timepoint, then channel, then Z
+    pub pages: Vec<usize>,
+}
+
+impl TiffStack {
+    pub fn ome_layout(&self) -> Option<OmeLayout> {
+        let ome = self.ome.as_ref()?;
+
+        // Only the Z planes picked by the load options were read
+        let depth = self.slice_range.count(ome.size_z);
+        let frame = depth * ome.size_c;
+        // No more timepoints than the loaded pages can fill, whatever SizeT claims
+        let size_t = ome.size_t.min(self.ifds.len() / frame.max(1));
+        let mut planes: Vec<Option<usize>> = vec![None; frame * size_t];
+        for (page, &ifd) in self.ifds.iter().enumerate() {
+            if let Some(plane) = ome.plane(ifd) {
+                let z = self.slice_range.position(plane.z).filter(|&z| z < depth);
+                if let Some(z) = z.filter(|_| plane.t < size_t && plane.c < ome.size_c) {
+                    planes[(plane.t * ome.size_c + plane.c) * depth + z] = Some(page);
+                }
             }
         }
+
+        let timepoints = planes
+            .chunks(frame.max(1))
+            .take_while(|timepoint| timepoint.iter().all(Option::is_some))
+            .count();
+        let pages = planes[..timepoints * frame].iter().flatten().copied().collect();
+        Some(OmeLayout { depth, timepoints, pages })
     }
-    
-    grayscale
 }
 
-pub fn load_tiff_from_memory(data: &[u8]) -> Result<Vec<ImageInfo>> {
-    let mut decoder = Decoder::new(Cursor::new(data))?;
-    let mut slices: Vec<ImageInfo> = Vec::new();
-    
-    let dimensions = decoder.dimensions()?;
-    let width = dimensions.0 as usize;
-    let height = dimensions.1 as usize;
-    
-    if width > 8192 || height > 8192 {
-        return Err(anyhow::anyhow!("Image dimensions too large"));
+// Rearranges a page stored with the given TIFF Orientation (tag 274) into
+// the default top-left layout; 5 to 8 swap the width and height
+fn apply_orientation(info: ImageInfo, orientation: u16) -> ImageInfo {
+    if !(2..=8).contains(&orientation) {
+        return info;
     }
-    
-    const MAX_SLICES: usize = 512;
-    
-    loop {
-        match decoder.read_image()? {
-            DecodingResult::U8(data) => {
-                let slice_data = match decoder.colortype()? {
-                    tiff::ColorType::RGB(8) => {
-                        info!("Converting RGB to grayscale");
-                        convert_rgb_to_grayscale(&data, width, height)
-                    },
-                    tiff::ColorType::Gray(8) => {
-                        info!("Converting 8-bit grayscale");
-                        data.into_iter().map(|v| u16::from(v)).collect()
-                    },
-                    _ => {
-                        return Err(anyhow::anyhow!("Unsupported color format"));
-                    }
-                };
-                
-                slices.push(ImageInfo { data: slice_data, width, height });
-            },
-            DecodingResult::U16(data) => {
-                slices.push(ImageInfo { data, width, height });
+
+    let (w, h) = (info.width, info.height);
+    let (out_width, out_height) = if orientation >= 5 { (h, w) } else { (w, h) };
+    let data = map_voxels!(&info.data, data => orient_planes(data, w, h, orientation));
+
+    ImageInfo { data, width: out_width, height: out_height, channels: info.channels, sample_type: info.sample_type }
+}
+
+fn orient_planes<T: Copy>(data: &[T], w: usize, h: usize, orientation: u16) -> Vec<T> {
+    let (out_width, out_height) = if orientation >= 5 { (h, w) } else { (w, h) };
+
+    // Stored (column, row) shown at output position (x, y)
+    let source = |x: usize, y: usize| match orientation {
+        2 => (w - 1 - x, y),
+        3 => (w - 1 - x, h - 1 - y),
+        4 => (x, h - 1 - y),
+        5 => (y, x),
+        6 => (y, h - 1 - x),
+        7 => (w - 1 - y, h - 1 - x),
+        _ => (w - 1 - y, x),
+    };
+
+    let mut out = Vec::with_capacity(data.len());
+    for plane in data.chunks_exact(w * h) {
+        for y in 0..out_height {
+            for x in 0..out_width {
+                let (sx, sy) = source(x, y);
+                out.push(plane[sy * w + sx]);
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn split_samples(
+    interleaved: Voxels,
+    sample_type: SampleType,
+    samples: usize,
+    width: usize,
+    height: usize,
+) -> Result<ImageInfo> {
+    let plane = width * height;
+    if interleaved.len() < plane * samples {
+        return Err(anyhow::anyhow!("TIFF page has fewer samples than expected"));
+    }
+
+    let data = if samples == 1 {
+        interleaved
+    } else {
+        map_voxels!(&interleaved, data => to_planar(data, samples, plane))
+    };
+
+    Ok(ImageInfo { data, width, height, channels: samples, sample_type })
+}
+
+fn to_planar<T: Voxel>(interleaved: &[T], samples: usize, plane: usize) -> Vec<T> {
+    let mut planar = vec![T::default(); plane * samples];
+    for (i, pixel) in interleaved.chunks_exact(samples).take(plane).enumerate() {
+        for (s, &value) in pixel.iter().enumerate() {
+            planar[s * plane + i] = value;
+        }
+    }
+    planar
+}
+
+// Produces interleaved RGB samples from the 16-bit TIFF color map
+fn expand_palette(indices: &[u8], colormap: &[u16]) -> Result<Vec<u16>> {
+    let entries = colormap.len() / 3;
+    if entries == 0 {
+        return Err(anyhow::anyhow!("Palette TIFF page has no color map"));
+    }
+
+    let mut rgb = Vec::with_capacity(indices.len() * 3);
+    for &index in indices {
+        let index = (index as usize).min(entries - 1);
+        for c in 0..3 {
+            rgb.push(colormap[c * entries + index]);
+        }
+    }
+
+    Ok(rgb)
+}
+
+fn convert_to_grayscale(info: ImageInfo) -> ImageInfo {
+    if info.channels == 1 {
+        return info;
+    }
+
+    // Luminance for color pages, otherwise keep the first sample (e.g. gray + alpha)
+    let plane = info.width * info.height;
+    let float = is_float(info.sample_type);
+    let data = map_voxels!(&info.data, data => if info.channels >= 3 {
+        luminance(data, plane, float)
+    } else {
+        data[..plane].to_vec()
+    });
+
+    ImageInfo { data, width: info.width, height: info.height, channels: 1, sample_type: info.sample_type }
+}
+
+fn luminance<T: Voxel>(data: &[T], plane: usize, float: bool) -> Vec<T> {
+    let (r, g, b) = (&data[..plane], &data[plane..2 * plane], &data[2 * plane..3 * plane]);
+    r.iter()
+        .zip(g)
+        .zip(b)
+        .map(|((&r, &g), &b)| {
+            let gray = 0.2989 * r.to_f32() + 0.5870 * g.to_f32() + 0.1140 * b.to_f32();
+            T::from_f32(if float { gray } else { gray.trunc() })
+        })
+        .collect()
+}
+
+fn is_float(sample_type: SampleType) -> bool {
+    matches!(sample_type, SampleType::F32 | SampleType::F64)
+}
+
+fn samples_per_pixel(colortype: ColorType) -> Option<usize> {
+    match colortype {
+        ColorType::Gray(_) => Some(1),
+        ColorType::GrayA(_) => Some(2),
+        ColorType::RGB(_) | ColorType::YCbCr(_) => Some(3),
+        ColorType::RGBA(_) | ColorType::CMYK(_) => Some(4),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_ome_metadata<R: Read + Seek>(decoder: &mut Decoder<R>) -> Option<OmeMetadata> {
+    let description = decoder.get_tag_ascii_string(Tag::ImageDescription).ok()?;
+    if !ome_metadata::is_ome_xml(&description) {
+        return None;
+    }
+
+    match OmeMetadata::parse(&description) {
+        Ok(ome) => Some(ome),
+        Err(e) => {
+            warn!("Ignoring unreadable OME-XML: {}", e);
+            None
+        }
+    }
+}
+
+// ImageJ writes key=value lines, e.g. "ImageJ=1.54f\nimages=20\nspacing=2.5\nunit=micron\n"
+fn imagej_value<'a>(description: &'a str, key: &str) -> Option<&'a str> {
+    if !description.starts_with("ImageJ=") {
+        return None;
+    }
+    description
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim())
+}
+
+fn positive(value: f64) -> Option<f32> {
+    Some(value as f32).filter(|v| v.is_finite() && *v > 0.0)
+}
+
+fn read_spacing<R: Read + Seek>(decoder: &mut Decoder<R>) -> Option<PageSpacing> {
+    let description = decoder.get_tag_ascii_string(Tag::ImageDescription).ok().unwrap_or_default();
+    let resolution = tiff_inspect::read_resolution(decoder).ok().flatten();
+
+    // ImageJ stores pixels per its own unit and leaves ResolutionUnit at none
+    let imagej_unit = imagej_value(&description, "unit").and_then(|unit| match unit {
+        "micron" | "um" | "\\u00B5m" => Some("µm"),
+        "pixel" | "" => None,
+        unit => Some(unit),
+    });
+    // Dots per inch describe a print size rather than the sample, and writers default to 72
+    let (per_unit, unit) = match (imagej_unit, resolution.as_ref().map(|r| r.unit)) {
+        (Some(unit), _) => (1.0, unit),
+        (None, Some("centimeter")) => (10.0, "mm"),
+        _ => return None,
+    };
+
+    let resolution = resolution?;
+    let (mut x, mut y) = (positive(per_unit / resolution.x)?, positive(per_unit / resolution.y)?);
+    if read_orientation(decoder).ok()? >= 5 {
+        std::mem::swap(&mut x, &mut y);
+    }
+
+    let z = match imagej_unit {
+        Some(_) => imagej_value(&description, "spacing").and_then(|v| v.parse::<f64>().ok()).and_then(positive),
+        None => None,
+    };
+    Some(PageSpacing { spacing: [Some(x), Some(y), z], unit: unit.to_string() })
+}
+
+pub fn is_tiff(data: &[u8]) -> bool {
+    // Classic TIFF (42) or BigTIFF (43) in either byte order
+    matches!(data.get(..4), Some(b"II*\0" | b"MM\0*" | b"II+\0" | b"MM\0+"))
+}
+
+pub fn load_tiff_from_memory(data: &[u8]) -> Result<TiffStack> {
+    load_tiff_with_options(data, &TiffLoadOptions::default())
+}
+
+// Crop rectangle (x, y, width, height) within a page of the given oriented size
+fn crop_rect(options: &TiffLoadOptions, width: usize, height: usize) -> Result<(usize, usize, usize, usize)> {
+    let (x, y) = (options.crop_x, options.crop_y);
+    if x >= width || y >= height {
+        return Err(anyhow::anyhow!("Crop origin ({}, {}) lies outside the {}x{} page", x, y, width, height));
+    }
+
+    let crop_width = match options.crop_width {
+        0 => width - x,
+        w => w.min(width - x),
+    };
+    let crop_height = match options.crop_height {
+        0 => height - y,
+        h => h.min(height - y),
+    };
+    Ok((x, y, crop_width, crop_height))
+}
+
+// Slice size once a page of the given oriented size is cropped and binned
+pub(crate) fn output_size(options: &TiffLoadOptions, width: usize, height: usize) -> Result<(usize, usize)> {
+    let (_, _, crop_width, crop_height) = crop_rect(options, width, height)?;
+    let bin = options.binning.max(1);
+    if crop_width < bin || crop_height < bin {
+        return Err(anyhow::anyhow!(
+            "A {}x{} region is too small for {}x binning",
+            crop_width, crop_height, bin
+        ));
+    }
+    Ok((crop_width / bin, crop_height / bin))
+}
+
+// Crops and bins a decoded page; binned values are block means, rounded
+// back to the nearest integer for integer samples
+fn resample(info: ImageInfo, options: &TiffLoadOptions) -> Result<ImageInfo> {
+    let (x0, y0, crop_width, crop_height) = crop_rect(options, info.width, info.height)?;
+    let bin = options.binning.max(1);
+    if (x0, y0, crop_width, crop_height) == (0, 0, info.width, info.height) && bin == 1 {
+        return Ok(info);
+    }
+
+    let (width, height) = output_size(options, info.width, info.height)?;
+    let source = (info.width, info.height);
+    let data = map_voxels!(&info.data, data => bin_planes(data, source, (x0, y0), (width, height), bin));
+
+    Ok(ImageInfo { data, width, height, channels: info.channels, sample_type: info.sample_type })
+}
+
+fn bin_planes<T: Voxel>(
+    data: &[T],
+    (source_width, source_height): (usize, usize),
+    (x0, y0): (usize, usize),
+    (width, height): (usize, usize),
+    bin: usize,
+) -> Vec<T> {
+    let scale = 1.0 / (bin * bin) as f32;
+
+    let mut out = Vec::with_capacity(width * height * data.len() / (source_width * source_height));
+    for plane in data.chunks_exact(source_width * source_height) {
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                for dy in 0..bin {
+                    let row = (y0 + y * bin + dy) * source_width + x0 + x * bin;
+                    sum += plane[row..row + bin].iter().map(|v| v.to_f32()).sum::<f32>();
+                }
+                out.push(T::from_f32(sum * scale));
+            }
+        }
+    }
+    out
+}
+
+// Validates the current page against the options and returns the voxels it will take up
+pub(crate) fn check_page<R: Read + Seek>(decoder: &mut Decoder<R>, options: &TiffLoadOptions) -> Result<usize> {
+    let (page_width, page_height) = decoder.dimensions()?;
+    let width = page_width as usize;
+    let height = page_height as usize;
+
+    if width > options.max_dimension || height > options.max_dimension {
+        return Err(anyhow::anyhow!(
+            "Image dimensions too large: {}x{} exceeds the {} pixel limit",
+            width,
+            height,
+            options.max_dimension
+        ));
+    }
+
+    let (width, height) = oriented_size(decoder)?;
+    let (width, height) = output_size(options, width, height)?;
+    Ok(width * height * loaded_samples(decoder, options)?)
+}
+
+// Channels the current page contributes once decoded; palettes expand to RGB
+pub(crate) fn loaded_samples<R: Read + Seek>(decoder: &mut Decoder<R>, options: &TiffLoadOptions) -> Result<usize> {
+    let colortype = decoder.colortype()?;
+    let samples = if matches!(colortype, ColorType::Palette(8)) {
+        3
+    } else {
+        samples_per_pixel(colortype)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported color format: {:?}", colortype))?
+    };
+
+    Ok(if options.grayscale { 1 } else { samples })
+}
+
+// Bytes each sample of the current page will take up once loaded; 8-bit
+// signed samples and palette indices are widened to 16 bits
+pub(crate) fn stored_sample_size<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<usize> {
+    let bits = match decoder.colortype()? {
+        ColorType::Palette(_) => 16,
+        ColorType::Gray(bits) | ColorType::GrayA(bits) | ColorType::RGB(bits) | ColorType::RGBA(bits)
+        | ColorType::CMYK(bits) | ColorType::YCbCr(bits) => bits,
+    };
+    let signed = decoder.find_tag(Tag::SampleFormat)?
+        .and_then(|value| match value {
+            tiff::decoder::ifd::Value::List(values) => values.into_iter().next(),
+            value => Some(value),
+        })
+        .and_then(|value| value.into_u16().ok())
+        == Some(2);
+
+    Ok(match bits {
+        0..=8 if signed => 2,
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+    })
+}
+
+pub(crate) fn decode_page<R: Read + Seek>(decoder: &mut Decoder<R>, options: &TiffLoadOptions) -> Result<ImageInfo> {
+    let (page_width, page_height) = decoder.dimensions()?;
+    let width = page_width as usize;
+    let height = page_height as usize;
+
+    let colortype = decoder.colortype()?;
+    let is_palette = matches!(colortype, ColorType::Palette(8));
+    let samples = if is_palette {
+        3
+    } else {
+        samples_per_pixel(colortype)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported color format: {:?}", colortype))?
+    };
+
+    let (values, sample_type) = match decoder.read_image()? {
+        DecodingResult::U8(data) if is_palette => {
+            let colormap = decoder.get_tag_u16_vec(Tag::ColorMap)?;
+            (Voxels::U16(expand_palette(&data, &colormap)?), SampleType::U16)
+        },
+        DecodingResult::U8(data) => (Voxels::U8(data), SampleType::U8),
+        DecodingResult::I8(data) => (Voxels::I16(data.into_iter().map(i16::from).collect()), SampleType::I8),
+        DecodingResult::U16(data) => (Voxels::U16(data), SampleType::U16),
+        DecodingResult::I16(data) => (Voxels::I16(data), SampleType::I16),
+        DecodingResult::U32(data) => (Voxels::F32(data.into_iter().map(|v| v as f32).collect()), SampleType::U32),
+        DecodingResult::I32(data) => (Voxels::F32(data.into_iter().map(|v| v as f32).collect()), SampleType::I32),
+        DecodingResult::F32(data) => (Voxels::F32(data), SampleType::F32),
+        DecodingResult::F64(data) => (Voxels::F32(data.into_iter().map(|v| v as f32).collect()), SampleType::F64),
+        DecodingResult::U64(_) | DecodingResult::I64(_) => {
+            return Err(anyhow::anyhow!("Unsupported TIFF sample format: 64-bit integer"));
+        },
+    };
+
+    let info = split_samples(values, sample_type, samples, width, height)?;
+
+    let orientation = read_orientation(decoder)?;
+    if orientation != 1 {
+        debug!("Applying TIFF orientation {}", orientation);
+    }
+    let info = resample(apply_orientation(info, orientation), options)?;
+
+    if info.channels > 1 {
+        debug!("Page has {} samples per pixel", info.channels);
+    }
+
+    if options.grayscale && info.channels > 1 {
+        info!("Converting {} channels to grayscale", info.channels);
+        Ok(convert_to_grayscale(info))
+    } else {
+        Ok(info)
+    }
+}
+
+fn read_orientation<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<u16> {
+    Ok(decoder.find_tag(Tag::Orientation)?
+        .and_then(|value| value.into_u16().ok())
+        .unwrap_or(1))
+}
+
+// Width and height of the page once its orientation has been applied
+pub(crate) fn oriented_size<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<(usize, usize)> {
+    let (width, height) = decoder.dimensions()?;
+    let (width, height) = (width as usize, height as usize);
+    match read_orientation(decoder)? {
+        5..=8 => Ok((height, width)),
+        _ => Ok((width, height)),
+    }
+}
+
+// Why a page is left out regardless of the size limits, judged from its subfile type
+pub(crate) fn subfile_skip_reason<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<Option<&'static str>> {
+    if let Some(flags) = decoder.find_tag(Tag::NewSubfileType)?.and_then(|v| v.into_u32().ok()) {
+        if flags & 0x1 != 0 {
+            return Ok(Some("reduced-resolution page"));
+        }
+        if flags & 0x4 != 0 {
+            return Ok(Some("transparency mask"));
+        }
+    }
+
+    // The older SubfileType tag marks reduced-resolution pages with 2
+    if decoder.find_tag(Tag::SubfileType)?.and_then(|v| v.into_u32().ok()) == Some(2) {
+        return Ok(Some("reduced-resolution page"));
+    }
+
+    Ok(None)
+}
+
+// Decides page by page what goes into the stack and records why
+pub(crate) struct PageCollector {
+    options: TiffLoadOptions,
+    // False when only the page selection is wanted, as for inspection
+    decode: bool,
+    loaded_voxels: usize,
+    loaded_bytes: usize,
+    // Oriented size of the first used page, which every other page must match
+    first_size: Option<(usize, usize)>,
+    // Full-resolution pages seen so far, which numbers the Z slices without OME-XML
+    candidates: usize,
+    stack: TiffStack,
+    // Reason every later page is dropped once a limit was hit
+    stopped: Option<String>,
+}
+
+impl PageCollector {
+    pub(crate) fn new(options: TiffLoadOptions) -> Self {
+        Self {
+            options,
+            decode: true,
+            loaded_voxels: 0,
+            loaded_bytes: 0,
+            first_size: None,
+            candidates: 0,
+            stack: TiffStack {
+                slices: Vec::new(),
+                ifds: Vec::new(),
+                slice_range: options.slice_range(),
+                binning: options.binning.max(1),
+                ome: None,
+                spacing: None,
+                report: LoadReport::default(),
             },
-            _ => break,
+            stopped: None,
         }
-        
-        if slices.len() >= MAX_SLICES {
-            debug!("Maximum number of slices reached");
-            break;
+    }
+
+    pub(crate) fn without_decoding(options: TiffLoadOptions) -> Self {
+        Self { decode: false, ..Self::new(options) }
+    }
+
+    pub(crate) fn loaded_voxels(&self) -> usize {
+        self.loaded_voxels
+    }
+
+    // Memory the used pages take up once stored at their native width
+    pub(crate) fn loaded_bytes(&self) -> usize {
+        self.loaded_bytes
+    }
+
+    // Size of every loaded slice after cropping and binning
+    pub(crate) fn slice_size(&self) -> Option<(usize, usize)> {
+        self.first_size.and_then(|(width, height)| output_size(&self.options, width, height).ok())
+    }
+
+    pub(crate) fn stack(&self) -> &TiffStack {
+        &self.stack
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.is_some()
+    }
+
+    // Stack-wide metadata, which writers keep on the first page
+    pub(crate) fn read_metadata<R: Read + Seek>(&mut self, decoder: &mut Decoder<R>) {
+        self.stack.ome = read_ome_metadata(decoder);
+        self.stack.spacing = read_spacing(decoder);
+    }
+
+    fn record(&mut self, index: usize, size: (usize, usize), status: PageStatus, reason: Option<String>) {
+        if let Some(reason) = &reason {
+            debug!("Page {} {:?}: {}", index, status, reason);
+        }
+        self.stack.report.pages.push(PageReport { index, width: size.0, height: size.1, status, reason });
+    }
+
+    fn stop(&mut self, index: usize, size: (usize, usize), reason: String) {
+        let message = format!("Stopped after {} slices: {}", self.stack.ifds.len(), reason);
+        self.stack.report.truncate(message);
+        self.record(index, size, PageStatus::Dropped, Some(reason.clone()));
+        self.stopped = Some(reason);
+    }
+
+    // Handles the decoder's current page, which is IFD number `index`
+    pub(crate) fn add_page<R: Read + Seek>(&mut self, decoder: &mut Decoder<R>, index: usize) -> Result<()> {
+        let size = oriented_size(decoder)?;
+
+        if let Some(reason) = self.stopped.clone() {
+            self.record(index, size, PageStatus::Dropped, Some(reason));
+            return Ok(());
+        }
+
+        if !self.options.include_subfiles {
+            if let Some(reason) = subfile_skip_reason(decoder)? {
+                self.record(index, size, PageStatus::Skipped, Some(reason.to_string()));
+                return Ok(());
+            }
+        }
+
+        if let Some(sub_ifds) = decoder.find_tag(Tag::Unknown(330))?.and_then(|v| v.into_u64_vec().ok()) {
+            debug!("Page {} has {} sub-resolution images, which are not loaded", index, sub_ifds.len());
+        }
+
+        let z = match &self.stack.ome {
+            Some(ome) => ome.plane(index).map(|plane| plane.z),
+            None => Some(self.candidates),
+        };
+        self.candidates += 1;
+        if z.is_some_and(|z| self.stack.slice_range.position(z).is_none()) {
+            self.record(index, size, PageStatus::Skipped, Some("outside the selected Z slices".to_string()));
+            return Ok(());
+        }
+
+        if let Some(expected) = self.first_size {
+            if size != expected {
+                let reason = format!(
+                    "{}x{} does not match the {}x{} of the first page",
+                    size.0, size.1, expected.0, expected.1
+                );
+                warn!("Dropping page {}: {}", index, reason);
+                self.stack.report.warnings.push(format!("Dropped page {}: {}", index, reason));
+                self.record(index, size, PageStatus::Dropped, Some(reason));
+                return Ok(());
+            }
         }
-        
+
+        if self.stack.ifds.len() >= self.options.max_slices {
+            self.stop(index, size, "the slice limit was reached".to_string());
+            return Ok(());
+        }
+
+        if let (None, Some(ome)) = (self.first_size, &self.stack.ome) {
+            if (ome.size_x, ome.size_y) != size {
+                return Err(anyhow::anyhow!("OME-XML dimensions do not match TIFF pages"));
+            }
+        }
+
+        let page_voxels = check_page(decoder, &self.options)?;
+        let page_bytes = page_voxels * stored_sample_size(decoder)?;
+        let limit = if self.loaded_voxels + page_voxels > self.options.max_voxels {
+            Some(format!("the {} voxel limit was reached", self.options.max_voxels))
+        } else if self.loaded_bytes + page_bytes > crate::MAX_BYTES {
+            Some(format!("the {} MB memory limit was reached", crate::MAX_BYTES / (1024 * 1024)))
+        } else {
+            None
+        };
+        if let Some(reason) = limit {
+            if self.stack.ifds.is_empty() {
+                return Err(anyhow::anyhow!("Image data too large to fit in memory"));
+            }
+            self.stop(index, size, reason);
+            return Ok(());
+        }
+        self.loaded_voxels += page_voxels;
+        self.loaded_bytes += page_bytes;
+
+        if self.decode {
+            self.stack.slices.push(decode_page(decoder, &self.options)?);
+        }
+        self.stack.ifds.push(index);
+        self.first_size.get_or_insert(size);
+        self.record(index, size, PageStatus::Used, None);
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<TiffStack> {
+        if self.stack.slices.is_empty() {
+            return Err(anyhow::anyhow!("No valid image data found in TIFF"));
+        }
+
+        let skipped = self.stack.report.pages.iter().filter(|p| p.status == PageStatus::Skipped).count();
+        if skipped > 0 {
+            info!("Skipped {} reduced-resolution or mask pages", skipped);
+        }
+
+        debug!("Successfully loaded {} slices", self.stack.slices.len());
+        Ok(self.stack)
+    }
+
+    // Marks the load as incomplete when the data ran out before the last page
+    pub(crate) fn truncate(&mut self, message: String) {
+        self.stack.report.truncate(message);
+    }
+}
+
+pub fn load_tiff_with_options(data: &[u8], options: &TiffLoadOptions) -> Result<TiffStack> {
+    // Page sizes are bounded by the options below, so the decoder's own caps are lifted
+    let mut decoder = Decoder::new(Cursor::new(data))?.with_limits(Limits::unlimited());
+    let mut pages = PageCollector::new(*options);
+    pages.read_metadata(&mut decoder);
+
+    let mut index = 0;
+    loop {
+        pages.add_page(&mut decoder, index)?;
+
         if !decoder.more_images() {
             debug!("No more images in TIFF");
             break;
         }
-        
+
         decoder.next_image()?;
+        index += 1;
     }
-    
-    if slices.is_empty() {
-        return Err(anyhow::anyhow!("No valid image data found in TIFF"));
-    }
-    
-    debug!("Successfully loaded {} slices", slices.len());
-    Ok(slices)
+
+    pages.finish()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2 page with two channels:
+    //   a b c
+    //   d e f
+    fn page() -> ImageInfo {
+        let data = Voxels::U8(vec![1, 2, 3, 4, 5, 6, 10, 20, 30, 40, 50, 60]);
+        ImageInfo { data, width: 3, height: 2, channels: 2, sample_type: SampleType::U8 }
+    }
+
+    fn oriented(orientation: u16) -> (usize, usize, Vec<f32>) {
+        let info = apply_orientation(page(), orientation);
+        let second: Vec<f32> = info.channel(0).iter().map(|v| v * 10.0).collect();
+        assert_eq!(info.channel(1).to_f32(), second);
+        (info.width, info.height, info.channel(0).to_f32())
+    }
+
+    #[test]
+    fn orientation_top_left() {
+        assert_eq!(oriented(1), (3, 2, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+    }
+
+    #[test]
+    fn orientation_top_right() {
+        assert_eq!(oriented(2), (3, 2, vec![3.0, 2.0, 1.0, 6.0, 5.0, 4.0]));
+    }
+
+    #[test]
+    fn orientation_bottom_right() {
+        assert_eq!(oriented(3), (3, 2, vec![6.0, 5.0, 4.0, 3.0, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn orientation_bottom_left() {
+        assert_eq!(oriented(4), (3, 2, vec![4.0, 5.0, 6.0, 1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn orientation_left_top() {
+        assert_eq!(oriented(5), (2, 3, vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]));
+    }
+
+    #[test]
+    fn orientation_right_top() {
+        assert_eq!(oriented(6), (2, 3, vec![4.0, 1.0, 5.0, 2.0, 6.0, 3.0]));
+    }
+
+    #[test]
+    fn orientation_right_bottom() {
+        assert_eq!(oriented(7), (2, 3, vec![6.0, 3.0, 5.0, 2.0, 4.0, 1.0]));
+    }
+
+    #[test]
+    fn orientation_left_bottom() {
+        assert_eq!(oriented(8), (2, 3, vec![3.0, 6.0, 2.0, 5.0, 1.0, 4.0]));
+    }
+
+    #[test]
+    fn orientation_unknown_is_ignored() {
+        assert_eq!(oriented(0), oriented(1));
+        assert_eq!(oriented(9), oriented(1));
+    }
+
+    #[test]
+    fn orientation_tag_is_read_from_each_page() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            // Both pages are 3x2 once oriented
+            for (orientation, width, height) in [(1u16, 3, 2), (6, 2, 3)] {
+                let mut image = encoder.new_image::<colortype::Gray8>(width, height).unwrap();
+                image.encoder().write_tag(Tag::Orientation, orientation).unwrap();
+                image.write_data(&[1, 2, 3, 4, 5, 6]).unwrap();
+            }
+        }
+
+        let stack = load_tiff_from_memory(&buf.into_inner()).unwrap();
+        assert_eq!(stack.slices.len(), 2);
+        assert_eq!(stack.slices[0].data, Voxels::U8(vec![1, 2, 3, 4, 5, 6]));
+        assert_eq!((stack.slices[1].width, stack.slices[1].height), (3, 2));
+        assert_eq!(stack.slices[1].data, Voxels::U8(vec![5, 3, 1, 6, 4, 2]));
+    }
+
+    #[test]
+    fn reduced_resolution_and_mismatched_pages_are_left_out() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            encoder.write_image::<colortype::Gray8>(4, 2, &[1; 8]).unwrap();
+            let mut thumbnail = encoder.new_image::<colortype::Gray8>(2, 1).unwrap();
+            thumbnail.encoder().write_tag(Tag::NewSubfileType, 1u32).unwrap();
+            thumbnail.write_data(&[9; 2]).unwrap();
+            encoder.write_image::<colortype::Gray8>(3, 3, &[5; 9]).unwrap();
+            encoder.write_image::<colortype::Gray8>(4, 2, &[2; 8]).unwrap();
+        }
+
+        let stack = load_tiff_from_memory(&buf.into_inner()).unwrap();
+        assert_eq!(stack.ifds, vec![0, 3]);
+        assert_eq!(stack.slices[1].data, Voxels::U8(vec![2; 8]));
+
+        let statuses: Vec<PageStatus> = stack.report.pages.iter().map(|page| page.status).collect();
+        assert_eq!(statuses, vec![PageStatus::Used, PageStatus::Skipped, PageStatus::Dropped, PageStatus::Used]);
+        assert!(!stack.report.truncated);
+    }
+
+    #[test]
+    fn crop_and_binning_average_blocks() {
+        let options = TiffLoadOptions { crop_x: 1, crop_width: 4, binning: 2, ..TiffLoadOptions::default() };
+        let data = Voxels::U8((0..10).collect());
+        let info = ImageInfo { data, width: 5, height: 2, channels: 1, sample_type: SampleType::U8 };
 
+        let binned = resample(info, &options).unwrap();
+        assert_eq!((binned.width, binned.height), (2, 1));
+        assert_eq!(binned.data, Voxels::U8(vec![(1 + 2 + 6 + 7) / 4, (3 + 4 + 8 + 9) / 4]));
+    }
 
+    #[test]
+    fn color_samples_become_channels() {
+        use tiff::encoder::{colortype, TiffEncoder};
 
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            encoder.write_image::<colortype::RGBA8>(2, 1, &[10, 20, 30, 255, 40, 50, 60, 128]).unwrap();
+        }
+        let file = buf.into_inner();
 
+        let stack = load_tiff_from_memory(&file).unwrap();
+        let page = &stack.slices[0];
+        assert_eq!(page.channels, 4);
+        assert_eq!(page.channel(0).to_f32(), vec![10.0, 40.0]);
+        assert_eq!(page.channel(3).to_f32(), vec![255.0, 128.0]);
 
+        let options = TiffLoadOptions { grayscale: true, ..TiffLoadOptions::default() };
+        let stack = load_tiff_with_options(&file, &options).unwrap();
+        assert_eq!(stack.slices[0].channels, 1);
+    }
 
+    #[test]
+    fn float_and_signed_samples() {
+        use tiff::encoder::{colortype, TiffEncoder};
 
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            encoder.write_image::<colortype::Gray32Float>(2, 1, &[0.5, -2.5]).unwrap();
+        }
+        let stack = load_tiff_from_memory(&buf.into_inner()).unwrap();
+        assert_eq!(stack.slices[0].sample_type, SampleType::F32);
+        assert_eq!(stack.slices[0].data, Voxels::F32(vec![0.5, -2.5]));
+
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            encoder.write_image::<colortype::GrayI16>(2, 1, &[-1000, 3000]).unwrap();
+        }
+        let stack = load_tiff_from_memory(&buf.into_inner()).unwrap();
+        assert_eq!(stack.slices[0].data, Voxels::I16(vec![-1000, 3000]));
+
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            encoder.write_image::<colortype::Gray64>(2, 1, &[1, 2]).unwrap();
+        }
+        let error = load_tiff_from_memory(&buf.into_inner()).err().unwrap().to_string();
+        assert!(error.contains("64-bit"), "{}", error);
+    }
+
+    #[test]
+    fn bigtiff_with_slice_and_voxel_limits() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new_big(&mut buf).unwrap();
+            for z in 0..20u16 {
+                encoder.write_image::<colortype::Gray16>(2, 2, &[z; 4]).unwrap();
+            }
+        }
+        let file = buf.into_inner();
+
+        let stack = load_tiff_from_memory(&file).unwrap();
+        assert_eq!(stack.slices.len(), 20);
+        assert!(!stack.report.truncated);
+
+        let options = TiffLoadOptions { max_slices: 5, ..TiffLoadOptions::default() };
+        let stack = load_tiff_with_options(&file, &options).unwrap();
+        assert_eq!(stack.slices.len(), 5);
+        assert!(stack.report.truncated);
+        assert_eq!(stack.report.pages[5].status, PageStatus::Dropped);
+
+        let options = TiffLoadOptions { max_voxels: 40, ..TiffLoadOptions::default() };
+        assert_eq!(load_tiff_with_options(&file, &options).unwrap().slices.len(), 10);
+
+        let options = TiffLoadOptions { max_dimension: 1, ..TiffLoadOptions::default() };
+        assert!(load_tiff_with_options(&file, &options).is_err());
+    }
+
+    #[test]
+    fn spacing_from_resolution_tags_and_imagej_description() {
+        use tiff::encoder::{colortype, Rational, TiffEncoder};
+
+        let write = |description: Option<&str>, unit: u16| {
+            let mut buf = Cursor::new(Vec::new());
+            {
+                let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+                let mut image = encoder.new_image::<colortype::Gray8>(2, 1).unwrap();
+                image.x_resolution(Rational { n: 4, d: 1 });
+                image.y_resolution(Rational { n: 2, d: 1 });
+                image.resolution_unit(match unit {
+                    1 => tiff::tags::ResolutionUnit::None,
+                    2 => tiff::tags::ResolutionUnit::Inch,
+                    _ => tiff::tags::ResolutionUnit::Centimeter,
+                });
+                if let Some(description) = description {
+                    image.encoder().write_tag(Tag::ImageDescription, description).unwrap();
+                }
+                image.write_data(&[1, 2]).unwrap();
+            }
+            load_tiff_from_memory(&buf.into_inner()).unwrap().spacing
+        };
+
+        let page = write(None, 3).unwrap();
+        assert_eq!((page.spacing, page.unit.as_str()), ([Some(2.5), Some(5.0), None], "mm"));
+        assert_eq!(write(None, 2), None);
+        assert_eq!(write(None, 1), None);
+
+        let page = write(Some("ImageJ=1.54f\nimages=1\nspacing=3.5\nunit=micron\n"), 1).unwrap();
+        assert_eq!((page.spacing, page.unit.as_str()), ([Some(0.25), Some(0.5), Some(3.5)], "µm"));
+        assert_eq!(write(Some("ImageJ=1.54f\nunit=pixel\n"), 1), None);
+    }
+}
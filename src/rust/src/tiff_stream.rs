@@ -0,0 +1,572 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+use tiff::decoder::{Decoder, Limits};
+use log::debug;
+use crate::tiff_loader::{PageCollector, TiffLoadOptions, TiffStack};
+
+#[derive(Clone, Copy)]
+struct Layout {
+    big: bool,
+    little_endian: bool,
+}
+
+// Largest gap between the data of two pages that is taken to be padding
+const ALIGNMENT_PADDING: u64 = 3;
+
+// Tag values kept from released pages, which later directories may share
+const MAX_RETAINED: usize = 1 << 20;
+
+// A directory whose values and pixel data have all arrived, with the byte ranges it uses
+struct CompletePage {
+    ifd: u64,
+    ranges: Vec<(u64, u64)>,
+    // The ranges holding tag values too large to fit in their directory entries
+    values: Vec<(u64, u64)>,
+}
+
+// The len bytes at offset, if one retained region holds all of them
+fn retained_bytes(retained: &BTreeMap<u64, Vec<u8>>, offset: u64, len: u64) -> Option<&[u8]> {
+    let (&start, bytes) = retained.range(..=offset).next_back()?;
+    let from = usize::try_from(offset - start).ok()?;
+    bytes.get(from..from.checked_add(usize::try_from(len).ok()?)?)
+}
+
+// The buffered part of the file, read at its original offsets. The header is kept
+// apart so it can point the decoder straight at the first page still to decode.
+struct Window<'a> {
+    header: &'a [u8],
+    base: u64,
+    data: &'a [u8],
+    retained: &'a BTreeMap<u64, Vec<u8>>,
+    pos: u64,
+}
+
+impl Read for Window<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = if self.pos < self.header.len() as u64 {
+            &self.header[self.pos as usize..]
+        } else if self.pos >= self.base {
+            self.data.get((self.pos - self.base) as usize..).unwrap_or_default()
+        } else if let Some(bytes) = retained_bytes(self.retained, self.pos, buf.len() as u64) {
+            bytes
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "TIFF data was already released"));
+        };
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for Window<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => (self.base + self.data.len() as u64).checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek in TIFF data"))?;
+        Ok(self.pos)
+    }
+}
+
+// Push-style TIFF loader: bytes are fed in as they arrive and each page
+// is decoded as soon as its directory and pixel data are complete
+pub struct TiffStream {
+    // File bytes from offset base on; those before it were all used by decoded pages
+    data: Vec<u8>,
+    base: u64,
+    header: Vec<u8>,
+    options: TiffLoadOptions,
+    layout: Option<Layout>,
+    // Offset of the next directory to inspect; None once the chain has ended
+    next_ifd: Option<u64>,
+    seen_ifds: HashSet<u64>,
+    // Directories known to be complete but not yet decoded
+    complete: VecDeque<CompletePage>,
+    // Byte ranges of decoded pages that are still buffered
+    consumed: Vec<(u64, u64)>,
+    // Copies of decoded pages' tag values by file offset, up to MAX_RETAINED bytes
+    retained: BTreeMap<u64, Vec<u8>>,
+    retained_len: usize,
+    // Directories already handed to the collector
+    pages_seen: usize,
+    pages: PageCollector,
+}
+
+fn type_size(field_type: u16) -> u64 {
+    match field_type {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 | 16 | 17 | 18 => 8,
+        _ => 1,
+    }
+}
+
+impl TiffStream {
+    pub fn new(options: TiffLoadOptions) -> Self {
+        Self {
+            data: Vec::new(),
+            base: 0,
+            header: Vec::new(),
+            options,
+            layout: None,
+            next_ifd: None,
+            seen_ifds: HashSet::new(),
+            complete: VecDeque::new(),
+            consumed: Vec::new(),
+            retained: BTreeMap::new(),
+            retained_len: 0,
+            pages_seen: 0,
+            pages: PageCollector::new(options),
+        }
+    }
+
+    pub fn options(&self) -> &TiffLoadOptions {
+        &self.options
+    }
+
+    pub fn slices_ready(&self) -> usize {
+        self.pages.stack().slices.len()
+    }
+
+    // Pages decoded so far, in file order
+    pub fn stack(&self) -> &TiffStack {
+        self.pages.stack()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<usize> {
+        // Nothing after a limit was hit will be decoded, so there is no need to keep it
+        if self.pages.is_stopped() {
+            return Ok(self.slices_ready());
+        }
+        self.data.extend_from_slice(chunk);
+
+        if self.layout.is_none() && !self.read_header()? {
+            return Ok(0);
+        }
+
+        while let Some(offset) = self.next_ifd {
+            match self.scan_ifd(offset)? {
+                Some((page, next)) => {
+                    self.complete.push_back(page);
+                    self.next_ifd = next;
+                },
+                None => break,
+            }
+        }
+
+        if !self.complete.is_empty() {
+            self.decode_complete_pages()?;
+        }
+
+        Ok(self.slices_ready())
+    }
+
+    pub fn finish(mut self) -> Result<TiffStack> {
+        if !self.pages.is_stopped() && self.next_ifd.is_some() {
+            let message = format!("The file ended before page {} was complete", self.pages_seen + 1);
+            self.pages.truncate(message);
+        }
+
+        self.pages.finish()
+    }
+
+    fn read_header(&mut self) -> Result<bool> {
+        if self.data.len() < 8 {
+            return Ok(false);
+        }
+
+        let little_endian = match &self.data[..2] {
+            b"II" => true,
+            b"MM" => false,
+            _ => return Err(anyhow::anyhow!("Not a TIFF file")),
+        };
+        let mut layout = Layout { big: false, little_endian };
+
+        let first_ifd = match self.read_uint(2, 2, layout) {
+            42 => self.read_uint(4, 4, layout),
+            43 => {
+                if self.data.len() < 16 {
+                    return Ok(false);
+                }
+                layout.big = true;
+                self.read_uint(8, 8, layout)
+            },
+            _ => return Err(anyhow::anyhow!("Not a TIFF file")),
+        };
+
+        let header_len = if layout.big { 16 } else { 8 };
+        self.header = self.data[..header_len].to_vec();
+        self.consumed.push((0, header_len as u64));
+
+        self.layout = Some(layout);
+        self.next_ifd = Some(first_ifd);
+        self.seen_ifds.insert(first_ifd);
+        Ok(true)
+    }
+
+    // Bytes of the file, from the buffer or from the tag values kept from released pages
+    fn bytes_at(&self, offset: u64, len: u64) -> Option<&[u8]> {
+        if offset < self.base {
+            return retained_bytes(&self.retained, offset, len);
+        }
+        let start = usize::try_from(offset - self.base).ok()?;
+        self.data.get(start..start.checked_add(usize::try_from(len).ok()?)?)
+    }
+
+    // Offsets are positions in the file; callers check they are available first
+    fn read_uint(&self, offset: u64, size: usize, layout: Layout) -> u64 {
+        let bytes = self.bytes_at(offset, size as u64).unwrap_or(&[0; 8][..size]);
+        let mut buf = [0u8; 8];
+        if layout.little_endian {
+            buf[..size].copy_from_slice(bytes);
+            u64::from_le_bytes(buf)
+        } else {
+            buf[8 - size..].copy_from_slice(bytes);
+            u64::from_be_bytes(buf)
+        }
+    }
+
+    fn available(&self, offset: u64, len: u64) -> Result<bool> {
+        if offset < self.base {
+            if self.bytes_at(offset, len).is_some() {
+                return Ok(true);
+            }
+            return Err(anyhow::anyhow!(
+                "TIFF page {} reuses data of an earlier page that was already released; load the file without streaming",
+                self.pages_seen + self.complete.len() + 1
+            ));
+        }
+        Ok(offset.checked_add(len).is_some_and(|end| end <= self.base + self.data.len() as u64))
+    }
+
+    // Returns the page and the offset of the following directory once this one, its
+    // values and its pixel data have all arrived, or None while bytes are still missing
+    fn scan_ifd(&mut self, offset: u64) -> Result<Option<(CompletePage, Option<u64>)>> {
+        let Some(layout) = self.layout else {
+            return Ok(None);
+        };
+        let (count_size, entry_size, inline_size) = if layout.big { (8, 20, 8) } else { (2, 12, 4) };
+
+        if !self.available(offset, count_size)? {
+            return Ok(None);
+        }
+        let entries = self.read_uint(offset, count_size as usize, layout);
+        let entries_start = offset + count_size;
+        let entries_len = entries
+            .checked_mul(entry_size)
+            .ok_or_else(|| anyhow::anyhow!("Invalid TIFF directory"))?;
+        if !self.available(entries_start, entries_len + inline_size)? {
+            return Ok(None);
+        }
+
+        let mut ranges = vec![(offset, count_size + entries_len + inline_size)];
+        let mut values = Vec::new();
+        let mut chunk_offsets = Vec::new();
+        let mut chunk_counts = Vec::new();
+
+        for i in 0..entries {
+            let entry = entries_start + i * entry_size;
+            let tag = self.read_uint(entry, 2, layout) as u16;
+            let field_type = self.read_uint(entry + 2, 2, layout) as u16;
+            let count = self.read_uint(entry + 4, inline_size as usize, layout);
+            let value_pos = entry + 4 + inline_size;
+
+            let size = type_size(field_type);
+            let len = count
+                .checked_mul(size)
+                .ok_or_else(|| anyhow::anyhow!("Invalid TIFF directory"))?;
+            let values_at = if len <= inline_size {
+                value_pos
+            } else {
+                let at = self.read_uint(value_pos, inline_size as usize, layout);
+                if !self.available(at, len)? {
+                    return Ok(None);
+                }
+                ranges.push((at, len));
+                values.push((at, len));
+                at
+            };
+
+            // StripOffsets/TileOffsets and StripByteCounts/TileByteCounts
+            let target = match tag {
+                273 | 324 => &mut chunk_offsets,
+                279 | 325 => &mut chunk_counts,
+                _ => continue,
+            };
+            for k in 0..count {
+                target.push(self.read_uint(values_at + k * size, size as usize, layout));
+            }
+        }
+
+        for (&chunk_offset, &chunk_len) in chunk_offsets.iter().zip(&chunk_counts) {
+            if !self.available(chunk_offset, chunk_len)? {
+                return Ok(None);
+            }
+            ranges.push((chunk_offset, chunk_len));
+        }
+
+        let page = CompletePage { ifd: offset, ranges, values };
+        let next = self.read_uint(entries_start + entries_len, inline_size as usize, layout);
+        if next == 0 {
+            return Ok(Some((page, None)));
+        }
+        if !self.seen_ifds.insert(next) {
+            return Err(anyhow::anyhow!("TIFF directories form a cycle"));
+        }
+        Ok(Some((page, Some(next))))
+    }
+
+    // The file header with its first directory offset replaced
+    fn header_pointing_at(&self, ifd: u64) -> Vec<u8> {
+        let mut header = self.header.clone();
+        let little_endian = self.layout.is_some_and(|layout| layout.little_endian);
+        let (at, bytes) = match (header.len(), little_endian) {
+            (16, true) => (8, ifd.to_le_bytes().to_vec()),
+            (16, false) => (8, ifd.to_be_bytes().to_vec()),
+            (_, true) => (4, (ifd as u32).to_le_bytes().to_vec()),
+            (_, false) => (4, (ifd as u32).to_be_bytes().to_vec()),
+        };
+        header[at..at + bytes.len()].copy_from_slice(&bytes);
+        header
+    }
+
+    // Each call starts the decoder at the first undecoded page, so pages are only read once
+    fn decode_complete_pages(&mut self) -> Result<()> {
+        let Some(first) = self.complete.front() else {
+            return Ok(());
+        };
+
+        let header = self.header_pointing_at(first.ifd);
+        let window = Window { header: &header, base: self.base, data: &self.data, retained: &self.retained, pos: 0 };
+        let mut decoder = Decoder::new(window)?.with_limits(Limits::unlimited());
+        if self.pages_seen == 0 {
+            self.pages.read_metadata(&mut decoder);
+        }
+
+        let mut started = false;
+        let mut values = Vec::new();
+        while let Some(page) = self.complete.pop_front() {
+            if started {
+                decoder.next_image()?;
+            }
+            started = true;
+
+            self.pages.add_page(&mut decoder, self.pages_seen)?;
+            self.pages_seen += 1;
+            self.consumed.extend(page.ranges);
+            values.extend(page.values);
+
+            if self.pages.is_stopped() {
+                break;
+            }
+        }
+
+        if self.pages.is_stopped() {
+            self.stop();
+        } else {
+            for (start, len) in values {
+                self.retain(start, len);
+            }
+            self.release();
+            debug!("{} slices decoded so far", self.slices_ready());
+        }
+        Ok(())
+    }
+
+    // Drops the buffered bytes up to the first one no decoded page used. Pages may share
+    // the file in any order, so anything past a gap is kept, unless the gap is only the
+    // padding writers put in to align offsets.
+    fn release(&mut self) {
+        self.consumed.sort_unstable();
+        let mut end = self.base;
+        let mut kept = Vec::new();
+        for &(start, len) in &self.consumed {
+            if kept.is_empty() && start <= end + ALIGNMENT_PADDING {
+                end = end.max(start + len);
+            } else {
+                kept.push((start, len));
+            }
+        }
+        self.consumed = kept;
+
+        let released = ((end - self.base) as usize).min(self.data.len());
+        self.data.drain(..released);
+        self.base += released as u64;
+    }
+
+    // Keeps a copy of a decoded page's tag values, which a later directory may point at
+    // after the buffer holding them is released
+    fn retain(&mut self, start: u64, len: u64) {
+        if self.retained.contains_key(&start) || self.retained_len as u64 + len > MAX_RETAINED as u64 {
+            return;
+        }
+        if let Some(bytes) = self.bytes_at(start, len).map(<[u8]>::to_vec) {
+            self.retained_len += bytes.len();
+            self.retained.insert(start, bytes);
+        }
+    }
+
+    // Drops the buffered file once the collector has stopped taking pages
+    fn stop(&mut self) {
+        self.complete.clear();
+        self.consumed.clear();
+        self.retained.clear();
+        self.next_ifd = None;
+        self.data = Vec::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    fn stack_file(big: bool) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        let pages = (0..20u16).map(|z| vec![z * 100; 30 * 7]);
+        if big {
+            let mut encoder = TiffEncoder::new_big(&mut buf).unwrap();
+            for page in pages {
+                encoder.write_image::<colortype::Gray16>(30, 7, &page).unwrap();
+            }
+        } else {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            for page in pages {
+                encoder.write_image::<colortype::Gray16>(30, 7, &page).unwrap();
+            }
+        }
+        buf.into_inner()
+    }
+
+    #[test]
+    fn pages_decode_as_they_arrive_and_are_released() {
+        for big in [false, true] {
+            let file = stack_file(big);
+            let mut stream = TiffStream::new(TiffLoadOptions::new());
+            let mut progress = Vec::new();
+            let mut most_buffered = 0;
+            for chunk in file.chunks(97) {
+                progress.push(stream.feed(chunk).unwrap());
+                most_buffered = most_buffered.max(stream.data.len());
+            }
+
+            assert!(progress.windows(2).all(|w| w[0] <= w[1]));
+            assert!(progress.iter().filter(|&&n| n > 0 && n < 20).count() > 5, "{:?}", progress);
+            // Never much more than one page of 420 bytes is held at a time
+            assert!(most_buffered < 1000, "{} bytes buffered", most_buffered);
+
+            let stack = stream.finish().unwrap();
+            assert_eq!(stack.slices.len(), 20);
+            assert!(!stack.report.truncated);
+            assert_eq!(stack.slices[19].data.get(0), Some(1900.0));
+        }
+    }
+
+    #[test]
+    fn early_end_and_slice_limit_truncate() {
+        let file = stack_file(false);
+        let mut stream = TiffStream::new(TiffLoadOptions::new());
+        stream.feed(&file[..file.len() / 2]).unwrap();
+        let stack = stream.finish().unwrap();
+        assert!(stack.report.truncated);
+        assert!(stack.slices.len() < 20);
+
+        let mut options = TiffLoadOptions::new();
+        options.max_slices = 4;
+        let mut stream = TiffStream::new(options);
+        for chunk in file.chunks(1000) {
+            stream.feed(chunk).unwrap();
+        }
+        assert!(stream.data.is_empty());
+        let stack = stream.finish().unwrap();
+        assert_eq!(stack.slices.len(), 4);
+        assert!(stack.report.truncated);
+    }
+
+    #[test]
+    fn shown_volume_grows_with_the_stream() {
+        let mut buf = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+        for z in 0..6u8 {
+            let pixels: Vec<u8> = (0..12).flat_map(|i| [z, i, 200 - z]).collect();
+            encoder.write_image::<colortype::RGB8>(4, 3, &pixels).unwrap();
+        }
+        let file = buf.into_inner();
+
+        let mut stream = TiffStream::new(TiffLoadOptions::new());
+        let mut volume = crate::VolumeData::default();
+        for chunk in file.chunks(150) {
+            if stream.feed(chunk).unwrap() > 0 {
+                volume.extend_tiff_stack(stream.stack(), crate::MAX_SIZE).unwrap();
+            }
+        }
+        let stack = stream.finish().unwrap();
+        volume.extend_tiff_stack(&stack, crate::MAX_SIZE).unwrap();
+
+        let mut expected = crate::VolumeData::default();
+        expected.load_tiff_stack(&stack, crate::MAX_SIZE).unwrap();
+        assert_eq!(volume.dimensions, (4, 3, 6));
+        assert_eq!(volume.channels, 3);
+        assert_eq!(volume.raw_data.as_slice(), expected.raw_data.as_slice());
+        assert_eq!(volume.value_range, expected.value_range);
+    }
+
+    // Offset of each directory entry with the given tag, in a little-endian classic TIFF
+    fn entries(file: &[u8], tag: u16) -> Vec<usize> {
+        let u32_at = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap()) as usize;
+        let mut found = Vec::new();
+        let mut ifd = u32_at(4);
+        while ifd != 0 {
+            let count = u16::from_le_bytes([file[ifd], file[ifd + 1]]) as usize;
+            let entry = (0..count).map(|i| ifd + 2 + i * 12);
+            found.extend(entry.filter(|&at| u16::from_le_bytes([file[at], file[at + 1]]) == tag));
+            ifd = u32_at(ifd + 2 + count * 12);
+        }
+        found
+    }
+
+    #[test]
+    fn values_shared_with_released_pages() {
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            for z in 0..3u8 {
+                let mut image = encoder.new_image::<colortype::RGB8>(4, 2).unwrap();
+                image.encoder().write_tag(tiff::tags::Tag::ImageDescription, "one description for every page").unwrap();
+                image.write_data(&[z; 24]).unwrap();
+            }
+        }
+        let mut file = buf.into_inner();
+
+        // Later pages point at the first page's BitsPerSample and description arrays
+        for tag in [258, 270] {
+            let found = entries(&file, tag);
+            let shared: [u8; 4] = file[found[0] + 8..found[0] + 12].try_into().unwrap();
+            for &at in &found[1..] {
+                file[at + 8..at + 12].copy_from_slice(&shared);
+            }
+        }
+
+        let mut stream = TiffStream::new(TiffLoadOptions::new());
+        for chunk in file.chunks(40) {
+            stream.feed(chunk).unwrap();
+        }
+        assert!(stream.base > 0);
+        let stack = stream.finish().unwrap();
+        assert_eq!(stack.slices.len(), 3);
+        assert_eq!(stack.slices[2].data.get(0), Some(2.0));
+
+        // Pixel data is not kept, so sharing a released strip asks for a full load
+        let strips = entries(&file, 273);
+        let shared: [u8; 4] = file[strips[0] + 8..strips[0] + 12].try_into().unwrap();
+        file[strips[2] + 8..strips[2] + 12].copy_from_slice(&shared);
+        let mut stream = TiffStream::new(TiffLoadOptions::new());
+        let error = file.chunks(40).map(|chunk| stream.feed(chunk)).find_map(Result::err).unwrap();
+        assert!(error.to_string().contains("without streaming"), "{}", error);
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use std::io::{Cursor, Seek, Write};
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::encoder::{TiffEncoder, TiffKind, TiffValue};
+use tiff::tags::Tag;
+use log::debug;
+use crate::sample::SampleType;
+use crate::voxels::VoxelSlice;
+use crate::VolumeData;
+
+// Classic TIFF offsets are 32-bit; past this the file is written as BigTIFF
+const BIGTIFF_THRESHOLD: u64 = u32::MAX as u64 - 64 * 1024 * 1024;
+
+fn write_pages<W, K, C>(
+    encoder: &mut TiffEncoder<W, K>,
+    pages: &[VoxelSlice<'_>],
+    width: u32,
+    height: u32,
+    description: Option<&str>,
+    convert: fn(f32) -> C::Inner,
+) -> Result<()>
+where
+    W: Write + Seek,
+    K: TiffKind,
+    C: ColorType,
+    [C::Inner]: TiffValue,
+{
+    for (i, page) in pages.iter().enumerate() {
+        let mut image = encoder.new_image::<C>(width, height)?;
+        if let (0, Some(description)) = (i, description) {
+            image.encoder().write_tag(Tag::ImageDescription, description)?;
+        }
+
+        let data: Vec<C::Inner> = page.iter().map(convert).collect();
+        image.write_data(&data)?;
+    }
+    Ok(())
+}
+
+// Integer samples are rounded in case a volume was built from fractional values
+fn write_typed<W: Write + Seek, K: TiffKind>(
+    encoder: &mut TiffEncoder<W, K>,
+    sample_type: SampleType,
+    pages: &[VoxelSlice<'_>],
+    width: u32,
+    height: u32,
+    description: Option<&str>,
+) -> Result<()> {
+    match sample_type {
+        SampleType::U8 => write_pages::<_, _, colortype::Gray8>(encoder, pages, width, height, description, |v| v.round() as u8),
+        SampleType::I8 => write_pages::<_, _, colortype::GrayI8>(encoder, pages, width, height, description, |v| v.round() as i8),
+        SampleType::U16 => write_pages::<_, _, colortype::Gray16>(encoder, pages, width, height, description, |v| v.round() as u16),
+        SampleType::I16 => write_pages::<_, _, colortype::GrayI16>(encoder, pages, width, height, description, |v| v.round() as i16),
+        SampleType::U32 => write_pages::<_, _, colortype::Gray32>(encoder, pages, width, height, description, |v| v.round() as u32),
+        SampleType::I32 => write_pages::<_, _, colortype::GrayI32>(encoder, pages, width, height, description, |v| v.round() as i32),
+        SampleType::U64 => write_pages::<_, _, colortype::Gray64>(encoder, pages, width, height, description, |v| v.round() as u64),
+        SampleType::I64 => write_pages::<_, _, colortype::GrayI64>(encoder, pages, width, height, description, |v| v.round() as i64),
+        SampleType::F32 => write_pages::<_, _, colortype::Gray32Float>(encoder, pages, width, height, description, |v| v),
+        SampleType::F64 => write_pages::<_, _, colortype::Gray64Float>(encoder, pages, width, height, description, f64::from),
+    }
+}
+
+// Writes one page per Z slice of each listed (timepoint, channel), in the order given
+fn write_channels(
+    volume: &VolumeData,
+    frames: &[(usize, usize)],
+    sample_type: SampleType,
+    description: Option<&str>,
+) -> Result<Vec<u8>> {
+    let (width, height, depth) = volume.dimensions;
+    let plane = width * height;
+    let mut pages: Vec<VoxelSlice> = Vec::with_capacity(frames.len() * depth);
+    for &(t, c) in frames {
+        let data = volume
+            .frame_channel(t, c)
+            .ok_or_else(|| anyhow::anyhow!("No volume data to export"))?;
+        pages.extend((0..depth).map(|z| data.range(z * plane, (z + 1) * plane)));
+    }
+
+    let bytes = (pages.len() * plane * sample_type.size()) as u64;
+    let (width, height) = (u32::try_from(width)?, u32::try_from(height)?);
+    debug!("Writing {} TIFF pages ({} bytes of pixel data)", pages.len(), bytes);
+
+    let mut buf = Cursor::new(Vec::new());
+    if bytes > BIGTIFF_THRESHOLD {
+        let mut encoder = TiffEncoder::new_big(&mut buf)?;
+        write_typed(&mut encoder, sample_type, &pages, width, height, description)?;
+    } else {
+        let mut encoder = TiffEncoder::new(&mut buf)?;
+        write_typed(&mut encoder, sample_type, &pages, width, height, description)?;
+    }
+
+    Ok(buf.into_inner())
+}
+
+// Multipage grayscale TIFF of the active channel, at the volume's own sample format
+pub fn write_tiff(volume: &VolumeData) -> Result<Vec<u8>> {
+    write_channels(volume, &[(volume.active_timepoint, volume.active_channel)], volume.sample_type, None)
+}
+
+// OME has no 64-bit integer pixel type, so those volumes are written as double
+fn ome_pixel_type(sample_type: SampleType) -> (SampleType, &'static str) {
+    match sample_type {
+        SampleType::U8 => (sample_type, "uint8"),
+        SampleType::I8 => (sample_type, "int8"),
+        SampleType::U16 => (sample_type, "uint16"),
+        SampleType::I16 => (sample_type, "int16"),
+        SampleType::U32 => (sample_type, "uint32"),
+        SampleType::I32 => (sample_type, "int32"),
+        SampleType::F32 => (sample_type, "float"),
+        SampleType::F64 | SampleType::U64 | SampleType::I64 => (SampleType::F64, "double"),
+    }
+}
+
+// TIFF descriptions must be ASCII, so anything else (such as µ) becomes a character reference
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c if c.is_ascii() => escaped.push(c),
+            c => escaped.push_str(&format!("&#{};", c as u32)),
+        }
+    }
+    escaped
+}
+
+fn ome_xml(volume: &VolumeData, pixel_type: &str, physical_size: [Option<f32>; 3], unit: &str) -> String {
+    let (width, height, depth) = volume.dimensions;
+
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<OME xmlns="http://www.openmicroscopy.org/Schemas/OME/2016-06">"#);
+    xml.push_str(r#"<Image ID="Image:0" Name="volume">"#);
+    xml.push_str(&format!(
+        r#"<Pixels ID="Pixels:0" DimensionOrder="XYZCT" Type="{}" SizeX="{}" SizeY="{}" SizeZ="{}" SizeC="{}" SizeT="{}""#,
+        pixel_type, width, height, depth, volume.channels, volume.timepoints
+    ));
+    for (axis, size) in ["X", "Y", "Z"].iter().zip(physical_size) {
+        if let Some(size) = size {
+            xml.push_str(&format!(
+                r#" PhysicalSize{}="{}" PhysicalSize{}Unit="{}""#,
+                axis, size, axis, xml_escape(unit)
+            ));
+        }
+    }
+    xml.push('>');
+
+    for c in 0..volume.channels {
+        let name = volume.channel_names.get(c).cloned().unwrap_or_else(|| format!("Channel {}", c));
+        xml.push_str(&format!(
+            r#"<Channel ID="Channel:0:{}" Name="{}" SamplesPerPixel="1"/>"#,
+            c, xml_escape(&name)
+        ));
+    }
+
+    xml.push_str(&format!(r#"<TiffData IFD="0" PlaneCount="{}"/>"#, depth * volume.channels * volume.timepoints));
+    xml.push_str("</Pixels></Image></OME>");
+    xml
+}
+
+// OME-TIFF holding every channel and timepoint, with the physical voxel size (in unit) where known
+pub fn write_ome_tiff(volume: &VolumeData, physical_size: [Option<f32>; 3], unit: &str) -> Result<Vec<u8>> {
+    let (sample_type, pixel_type) = ome_pixel_type(volume.sample_type);
+    let xml = ome_xml(volume, pixel_type, physical_size, unit);
+    let frames: Vec<(usize, usize)> = (0..volume.timepoints)
+        .flat_map(|t| (0..volume.channels).map(move |c| (t, c)))
+        .collect();
+    write_channels(volume, &frames, sample_type, Some(&xml))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiff_loader;
+    use crate::voxels::Voxels;
+
+    // 3x2x3 uint16 volume with two channels and two timepoints; each value encodes t, c and z
+    fn volume() -> VolumeData {
+        let data: Vec<u16> = (0..2u16)
+            .flat_map(|t| (0..2u16).flat_map(move |c| (0..18u16).map(move |i| t * 1000 + c * 100 + i / 6 * 10 + i % 6)))
+            .collect();
+        let mut volume = VolumeData::default();
+        volume.set_time_series(Voxels::U16(data), (3, 2, 3), 2, 2, SampleType::U16).unwrap();
+        volume.channel_names = vec!["DAPI & co".to_string(), "GFP".to_string()];
+        volume
+    }
+
+    #[test]
+    fn plain_tiff_holds_the_active_channel() {
+        let mut volume = volume();
+        volume.active_channel = 1;
+        volume.set_timepoint(1).unwrap();
+
+        let mut loaded = VolumeData::default();
+        loaded.load_tiff_from_memory(&write_tiff(&volume).unwrap()).unwrap();
+        assert_eq!(loaded.dimensions, (3, 2, 3));
+        assert_eq!(loaded.channels, 1);
+        assert_eq!(loaded.sample_type, SampleType::U16);
+        assert_eq!(loaded.sample(2, 1, 2), Some(1125.0));
+    }
+
+    #[test]
+    fn ome_tiff_keeps_channels_timepoints_and_size() {
+        let volume = volume();
+        let file = write_ome_tiff(&volume, [Some(0.5), Some(0.5), Some(2.0)], "µm").unwrap();
+
+        let stack = tiff_loader::load_tiff_from_memory(&file).unwrap();
+        let ome = stack.ome.as_ref().unwrap();
+        assert_eq!(ome.physical_size, [Some(0.5), Some(0.5), Some(2.0)]);
+        assert_eq!(ome.physical_size_unit, "µm");
+
+        let mut loaded = VolumeData::default();
+        loaded.load_tiff_from_memory(&file).unwrap();
+        assert_eq!(loaded.channels, 2);
+        assert_eq!(loaded.timepoints, 2);
+        assert_eq!(loaded.channel_names, volume.channel_names);
+        assert_eq!(loaded.raw_data, volume.raw_data);
+        assert_eq!(loaded.spacing, [0.5, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn ome_tiff_writes_64_bit_integers_as_double() {
+        let mut volume = VolumeData::default();
+        volume.set_samples(vec![0.0, -3.0, 7.0, 1e6], (2, 1, 2), 1, SampleType::I64).unwrap();
+
+        let mut loaded = VolumeData::default();
+        loaded.load_tiff_from_memory(&write_ome_tiff(&volume, [None; 3], "µm").unwrap()).unwrap();
+        assert_eq!(loaded.sample_type, SampleType::F64);
+        assert_eq!(loaded.raw_data.as_slice().to_f32(), vec![0.0, -3.0, 7.0, 1e6]);
+    }
+}
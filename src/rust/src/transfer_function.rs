@@ -85,8 +85,8 @@ impl TransferFunction {
                 let t = (value - p1.value) / (p2.value - p1.value);
                 let mut color = [0.0; 4];
                 
-                for j in 0..4 {
-                    color[j] = p1.color[j] * (1.0 - t) + p2.color[j] * t;
+                for (j, c) in color.iter_mut().enumerate() {
+                    *c = p1.color[j] * (1.0 - t) + p2.color[j] * t;
                 }
                 
                 self.cached_colors[i] = color;
@@ -0,0 +1,208 @@
+use crate::sample::SampleType;
+
+// Voxel storage at the source width; formats without a matching variant
+// (8-bit signed goes to I16, 32/64-bit to F32) are widened
+#[derive(Clone, Debug, PartialEq)]
+pub enum Voxels {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    I16(Vec<i16>),
+    F32(Vec<f32>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelSlice<'a> {
+    U8(&'a [u8]),
+    U16(&'a [u16]),
+    I16(&'a [i16]),
+    F32(&'a [f32]),
+}
+
+pub trait Voxel: Copy + Default + PartialOrd + std::fmt::Debug {
+    fn to_f32(self) -> f32;
+    // Integer types round and saturate at their limits
+    fn from_f32(value: f32) -> Self;
+    fn typed<'a>(slice: VoxelSlice<'a>) -> Option<&'a [Self]>;
+}
+
+macro_rules! impl_voxel {
+    ($type:ty, $variant:ident, $from:expr) => {
+        impl Voxel for $type {
+            fn to_f32(self) -> f32 {
+                self as f32
+            }
+
+            fn from_f32(value: f32) -> Self {
+                $from(value)
+            }
+
+            fn typed<'a>(slice: VoxelSlice<'a>) -> Option<&'a [Self]> {
+                match slice {
+                    VoxelSlice::$variant(data) => Some(data),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_voxel!(u8, U8, |v: f32| v.round() as u8);
+impl_voxel!(u16, U16, |v: f32| v.round() as u16);
+impl_voxel!(i16, I16, |v: f32| v.round() as i16);
+impl_voxel!(f32, F32, |v: f32| v);
+
+// Runs $body once per variant with $data bound to the inner vector (or slice)
+// and wraps the result back up in the same variant
+macro_rules! map_voxels {
+    ($voxels:expr, $data:ident => $body:expr) => {
+        match $voxels {
+            $crate::voxels::Voxels::U8($data) => $crate::voxels::Voxels::U8($body),
+            $crate::voxels::Voxels::U16($data) => $crate::voxels::Voxels::U16($body),
+            $crate::voxels::Voxels::I16($data) => $crate::voxels::Voxels::I16($body),
+            $crate::voxels::Voxels::F32($data) => $crate::voxels::Voxels::F32($body),
+        }
+    };
+}
+pub(crate) use map_voxels;
+
+// Bytes each voxel of the given source format takes up once loaded
+pub fn stored_size(sample_type: SampleType) -> usize {
+    match sample_type {
+        SampleType::U8 => 1,
+        SampleType::I8 | SampleType::U16 | SampleType::I16 => 2,
+        _ => 4,
+    }
+}
+
+impl Default for Voxels {
+    fn default() -> Self {
+        Voxels::F32(Vec::new())
+    }
+}
+
+impl Voxels {
+    pub fn with_capacity(sample_type: SampleType, capacity: usize) -> Voxels {
+        match sample_type {
+            SampleType::U8 => Voxels::U8(Vec::with_capacity(capacity)),
+            SampleType::I8 | SampleType::I16 => Voxels::I16(Vec::with_capacity(capacity)),
+            SampleType::U16 => Voxels::U16(Vec::with_capacity(capacity)),
+            _ => Voxels::F32(Vec::with_capacity(capacity)),
+        }
+    }
+
+    // Narrows f32 samples to the storage for their source format
+    pub fn from_f32(values: Vec<f32>, sample_type: SampleType) -> Voxels {
+        match Voxels::with_capacity(sample_type, 0) {
+            Voxels::F32(_) => Voxels::F32(values),
+            voxels => map_voxels!(voxels, _data => values.iter().map(|&v| Voxel::from_f32(v)).collect()),
+        }
+    }
+
+    pub fn as_slice(&self) -> VoxelSlice<'_> {
+        match self {
+            Voxels::U8(data) => VoxelSlice::U8(data),
+            Voxels::U16(data) => VoxelSlice::U16(data),
+            Voxels::I16(data) => VoxelSlice::I16(data),
+            Voxels::F32(data) => VoxelSlice::F32(data),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<f32> {
+        self.as_slice().get(index)
+    }
+
+    pub fn bytes_per_voxel(&self) -> usize {
+        match self {
+            Voxels::U8(_) => 1,
+            Voxels::U16(_) | Voxels::I16(_) => 2,
+            Voxels::F32(_) => 4,
+        }
+    }
+
+    // Appends samples, converting them if they are stored at another width
+    pub fn extend_from(&mut self, other: VoxelSlice<'_>) {
+        match (self, other) {
+            (Voxels::U8(data), VoxelSlice::U8(other)) => data.extend_from_slice(other),
+            (Voxels::U16(data), VoxelSlice::U16(other)) => data.extend_from_slice(other),
+            (Voxels::I16(data), VoxelSlice::I16(other)) => data.extend_from_slice(other),
+            (Voxels::F32(data), VoxelSlice::F32(other)) => data.extend_from_slice(other),
+            (voxels, other) => {
+                let values = other.iter();
+                match voxels {
+                    Voxels::U8(data) => data.extend(values.map(u8::from_f32)),
+                    Voxels::U16(data) => data.extend(values.map(u16::from_f32)),
+                    Voxels::I16(data) => data.extend(values.map(i16::from_f32)),
+                    Voxels::F32(data) => data.extend(values),
+                }
+            },
+        }
+    }
+
+    // As extend_from, placing the values before index instead of at the end
+    pub fn insert_from(&mut self, index: usize, other: VoxelSlice<'_>) {
+        let values = other.iter();
+        match self {
+            Voxels::U8(data) => drop(data.splice(index..index, values.map(u8::from_f32))),
+            Voxels::U16(data) => drop(data.splice(index..index, values.map(u16::from_f32))),
+            Voxels::I16(data) => drop(data.splice(index..index, values.map(i16::from_f32))),
+            Voxels::F32(data) => drop(data.splice(index..index, values)),
+        }
+    }
+}
+
+impl<'a> VoxelSlice<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            VoxelSlice::U8(data) => data.len(),
+            VoxelSlice::U16(data) => data.len(),
+            VoxelSlice::I16(data) => data.len(),
+            VoxelSlice::F32(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<f32> {
+        match self {
+            VoxelSlice::U8(data) => data.get(index).map(|&v| v.to_f32()),
+            VoxelSlice::U16(data) => data.get(index).map(|&v| v.to_f32()),
+            VoxelSlice::I16(data) => data.get(index).map(|&v| v.to_f32()),
+            VoxelSlice::F32(data) => data.get(index).copied(),
+        }
+    }
+
+    // Samples start..end; panics when out of bounds, like slice indexing
+    pub fn range(&self, start: usize, end: usize) -> VoxelSlice<'a> {
+        match *self {
+            VoxelSlice::U8(data) => VoxelSlice::U8(&data[start..end]),
+            VoxelSlice::U16(data) => VoxelSlice::U16(&data[start..end]),
+            VoxelSlice::I16(data) => VoxelSlice::I16(&data[start..end]),
+            VoxelSlice::F32(data) => VoxelSlice::F32(&data[start..end]),
+        }
+    }
+
+    // Every sample widened to f32
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = f32> + 'a {
+        let slice = *self;
+        (0..slice.len()).map(move |i| slice.get(i).unwrap_or_default())
+    }
+
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.iter().collect()
+    }
+
+    // The stored samples, if they are held as T
+    pub fn typed<T: Voxel>(self) -> Option<&'a [T]> {
+        T::typed(self)
+    }
+}
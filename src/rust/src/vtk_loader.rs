@@ -0,0 +1,706 @@
+use anyhow::Result;
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use log::{debug, info};
+use crate::sample::{self, Endian, SampleType};
+
+pub struct VtkVolume {
+    // Channel-major: one block per scalar component
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    pub channels: usize,
+    pub sample_type: SampleType,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    pub directions: [[f32; 3]; 3],
+}
+
+const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+pub fn is_vtk_legacy(data: &[u8]) -> bool {
+    data.starts_with(b"# vtk DataFile")
+}
+
+pub fn is_vti(data: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&data[..data.len().min(512)]);
+    start.contains("<VTKFile") && start.contains("ImageData")
+}
+
+fn parse_vector(key: &str, values: &[&str]) -> Result<[f32; 3]> {
+    if values.len() != 3 {
+        return Err(anyhow::anyhow!("VTK {} needs 3 values", key));
+    }
+    let mut vector = [0.0f32; 3];
+    for (v, text) in vector.iter_mut().zip(values) {
+        *v = text.parse().map_err(|_| anyhow::anyhow!("Invalid VTK {}: {}", key, values.join(" ")))?;
+    }
+    Ok(vector)
+}
+
+// Splits interleaved components into one block per component
+fn split_components(values: Vec<f32>, components: usize) -> Vec<f32> {
+    if components == 1 {
+        return values;
+    }
+    let points = values.len() / components;
+    let mut planar = Vec::with_capacity(values.len());
+    for c in 0..components {
+        planar.extend((0..points).map(|i| values[i * components + c]));
+    }
+    planar
+}
+
+fn volume_size(dimensions: (usize, usize, usize)) -> Result<usize> {
+    let (width, height, depth) = dimensions;
+    width
+        .checked_mul(height)
+        .and_then(|wh| wh.checked_mul(depth))
+        .filter(|&count| count <= crate::MAX_SIZE)
+        .ok_or_else(|| anyhow::anyhow!("Image data too large to fit in memory"))
+}
+
+fn parse_legacy_type(value: &str) -> Result<SampleType> {
+    let sample_type = match value {
+        "unsigned_char" | "vtktypeuint8" => SampleType::U8,
+        "char" | "signed_char" | "vtktypeint8" => SampleType::I8,
+        "unsigned_short" | "vtktypeuint16" => SampleType::U16,
+        "short" | "vtktypeint16" => SampleType::I16,
+        "unsigned_int" | "vtktypeuint32" => SampleType::U32,
+        "int" | "vtktypeint32" => SampleType::I32,
+        "unsigned_long" | "vtktypeuint64" => SampleType::U64,
+        "long" | "vtktypeint64" | "vtkIdType" => SampleType::I64,
+        "float" | "vtktypefloat32" => SampleType::F32,
+        "double" | "vtktypefloat64" => SampleType::F64,
+        _ => return Err(anyhow::anyhow!("Unsupported VTK data type: {}", value)),
+    };
+    Ok(sample_type)
+}
+
+fn value_count(tuples: usize, components: usize) -> Result<usize> {
+    tuples
+        .checked_mul(components)
+        .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))
+}
+
+struct LegacyReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    binary: bool,
+}
+
+impl<'a> LegacyReader<'a> {
+    fn line(&mut self) -> Result<Option<&'a str>> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+        let end = self.data[self.pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| self.pos + i)
+            .unwrap_or(self.data.len());
+        let line = std::str::from_utf8(&self.data[self.pos..end])
+            .map_err(|_| anyhow::anyhow!("VTK header is not valid text"))?;
+        self.pos = (end + 1).min(self.data.len());
+        Ok(Some(line.trim_end_matches('\r')))
+    }
+
+    // Next line with content, split into whitespace separated words
+    fn words(&mut self) -> Result<Option<Vec<&'a str>>> {
+        while let Some(line) = self.line()? {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.is_empty() {
+                return Ok(Some(words));
+            }
+        }
+        Ok(None)
+    }
+
+    fn values(&mut self, sample_type: SampleType, count: usize) -> Result<Vec<f32>> {
+        if self.binary {
+            let bytes = count
+                .checked_mul(sample_type.size())
+                .ok_or_else(|| anyhow::anyhow!("Integer overflow in size calculation"))?;
+            // Legacy binary data is always big-endian
+            let values = sample::decode_samples(&self.data[self.pos..], sample_type, Endian::Big, count)?;
+            self.pos += bytes;
+            return Ok(values);
+        }
+
+        // Every ascii value takes at least one byte, which bounds the reservation
+        let mut values = Vec::with_capacity(count.min(self.data.len() - self.pos));
+        while values.len() < count {
+            let line = self.line()?
+                .ok_or_else(|| anyhow::anyhow!("Not enough samples in VTK ascii data"))?;
+            for word in line.split_whitespace() {
+                let value = word.parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("Invalid value in VTK ascii data: {}", word))?;
+                values.push(value as f32);
+            }
+        }
+        values.truncate(count);
+        Ok(values)
+    }
+}
+
+pub fn load_vtk_legacy_from_memory(data: &[u8]) -> Result<VtkVolume> {
+    let mut reader = LegacyReader { data, pos: 0, binary: false };
+
+    let version = reader.line()?.unwrap_or_default();
+    if !version.starts_with("# vtk DataFile") {
+        return Err(anyhow::anyhow!("Not a VTK legacy file"));
+    }
+    let title = reader.line()?.unwrap_or_default();
+    debug!("VTK title: {}", title);
+
+    reader.binary = match reader.line()?.map(str::trim) {
+        Some(format) if format.eq_ignore_ascii_case("ascii") => false,
+        Some(format) if format.eq_ignore_ascii_case("binary") => true,
+        _ => return Err(anyhow::anyhow!("VTK file must be ASCII or BINARY")),
+    };
+
+    let mut dimensions = None;
+    let mut spacing = [1.0f32; 3];
+    let mut origin = [0.0f32; 3];
+    let mut point_data = false;
+    let mut tuples = 0;
+
+    while let Some(words) = reader.words()? {
+        let keyword = words[0].to_ascii_uppercase();
+        match keyword.as_str() {
+            "DATASET" => {
+                if words.get(1).map(|d| d.to_ascii_uppercase()).as_deref() != Some("STRUCTURED_POINTS") {
+                    return Err(anyhow::anyhow!("Unsupported VTK dataset: {}", words[1..].join(" ")));
+                }
+            },
+            "DIMENSIONS" => {
+                let dims = parse_vector("DIMENSIONS", &words[1..])?;
+                if dims.iter().any(|&d| d < 1.0 || d.fract() != 0.0) {
+                    return Err(anyhow::anyhow!("Invalid VTK DIMENSIONS: {}", words[1..].join(" ")));
+                }
+                let dims = (dims[0] as usize, dims[1] as usize, dims[2] as usize);
+                volume_size(dims)?;
+                dimensions = Some(dims);
+            },
+            "SPACING" | "ASPECT_RATIO" => spacing = parse_vector("SPACING", &words[1..])?,
+            "ORIGIN" => origin = parse_vector("ORIGIN", &words[1..])?,
+            "POINT_DATA" | "CELL_DATA" => {
+                point_data = keyword == "POINT_DATA";
+                tuples = words.get(1).and_then(|n| n.parse::<usize>().ok())
+                    .ok_or_else(|| anyhow::anyhow!("Invalid VTK {}", keyword))?;
+            },
+            "SCALARS" => {
+                let sample_type = parse_legacy_type(words.get(2).copied().unwrap_or_default())?;
+                let components = words.get(3).and_then(|n| n.parse::<usize>().ok()).unwrap_or(1);
+
+                // LOOKUP_TABLE normally follows, but some writers leave it out
+                let mark = reader.pos;
+                match reader.words() {
+                    Ok(Some(next)) if next[0].eq_ignore_ascii_case("LOOKUP_TABLE") => {},
+                    _ => reader.pos = mark,
+                }
+
+                let count = value_count(tuples, components)?;
+                let values = reader.values(sample_type, count)?;
+
+                if point_data {
+                    let dims = dimensions.ok_or_else(|| anyhow::anyhow!("VTK file missing DIMENSIONS"))?;
+                    if tuples != volume_size(dims)? {
+                        return Err(anyhow::anyhow!("VTK POINT_DATA count does not match DIMENSIONS"));
+                    }
+
+                    info!(
+                        "Loaded VTK volume: {}x{}x{} ({:?}, {} components) from {}",
+                        dims.0, dims.1, dims.2, sample_type, components, words.get(1).copied().unwrap_or_default()
+                    );
+                    debug!("VTK spacing: {:?}, origin: {:?}", spacing, origin);
+
+                    return Ok(VtkVolume {
+                        data: split_components(values, components),
+                        dimensions: dims,
+                        channels: components,
+                        sample_type,
+                        spacing,
+                        origin,
+                        directions: IDENTITY,
+                    });
+                }
+                debug!("Skipping VTK cell scalars {}", words.get(1).copied().unwrap_or_default());
+            },
+            "VECTORS" | "NORMALS" | "TENSORS" => {
+                let sample_type = parse_legacy_type(words.get(2).copied().unwrap_or_default())?;
+                let components = if keyword == "TENSORS" { 9 } else { 3 };
+                reader.values(sample_type, value_count(tuples, components)?)?;
+            },
+            "FIELD" => {
+                let arrays = words.get(2).and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+                for _ in 0..arrays {
+                    let array = reader.words()?
+                        .filter(|array| array.len() >= 4)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid VTK FIELD array"))?;
+                    let components: usize = array[1].parse().map_err(|_| anyhow::anyhow!("Invalid VTK FIELD array"))?;
+                    let tuples: usize = array[2].parse().map_err(|_| anyhow::anyhow!("Invalid VTK FIELD array"))?;
+                    reader.values(parse_legacy_type(array[3])?, value_count(tuples, components)?)?;
+                }
+            },
+            "METADATA" => {
+                // Metadata blocks end at the next blank line
+                while let Some(line) = reader.line()? {
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+            },
+            _ => return Err(anyhow::anyhow!("Unsupported VTK section: {}", words[0])),
+        }
+    }
+
+    Err(anyhow::anyhow!("VTK file has no point data scalars"))
+}
+
+// Decodes base64 one padded quartet at a time, so separately encoded blocks can be concatenated
+fn decode_base64(text: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut quartet = [0u8; 4];
+    let mut filled = 0;
+    let mut padding = 0;
+
+    for &c in text {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                0
+            },
+            b'<' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return Err(anyhow::anyhow!("Invalid base64 data in VTK file")),
+        };
+
+        quartet[filled] = value;
+        filled += 1;
+        if filled == 4 {
+            let bytes = [
+                (quartet[0] << 2) | (quartet[1] >> 4),
+                (quartet[1] << 4) | (quartet[2] >> 2),
+                (quartet[2] << 6) | quartet[3],
+            ];
+            out.extend_from_slice(&bytes[..3 - padding.min(2)]);
+            filled = 0;
+            padding = 0;
+        }
+    }
+
+    Ok(out)
+}
+
+#[derive(Clone, Copy)]
+struct BlockFormat {
+    header_size: usize,
+    endian: Endian,
+    compressor: Option<&'static str>,
+}
+
+impl BlockFormat {
+    // Size headers are integers and may exceed what an f32 holds exactly
+    fn header(&self, bytes: &[u8], index: usize) -> Result<usize> {
+        let field = index
+            .checked_mul(self.header_size)
+            .and_then(|start| bytes.get(start..)?.get(..self.header_size))
+            .ok_or_else(|| anyhow::anyhow!("Truncated VTK data block"))?;
+        let value = match (self.header_size, self.endian) {
+            (8, Endian::Little) => u64::from_le_bytes(field.try_into()?),
+            (8, Endian::Big) => u64::from_be_bytes(field.try_into()?),
+            (_, Endian::Little) => u32::from_le_bytes(field.try_into()?) as u64,
+            (_, Endian::Big) => u32::from_be_bytes(field.try_into()?) as u64,
+        };
+        usize::try_from(value).map_err(|_| anyhow::anyhow!("VTK data block size {} is too large", value))
+    }
+
+    // Strips the size header and undoes the block compression, stopping after limit bytes
+    fn read(&self, bytes: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let Some(compressor) = self.compressor else {
+            let len = self.header(bytes, 0)?;
+            return bytes
+                .get(self.header_size..)
+                .and_then(|rest| rest.get(..len))
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| anyhow::anyhow!("Truncated VTK data block"));
+        };
+
+        let blocks = self.header(bytes, 0)?;
+        let block_size = self.header(bytes, 1)?;
+        let last_size = self.header(bytes, 2)?;
+
+        let mut out = Vec::new();
+        let mut pos = blocks
+            .checked_add(3)
+            .and_then(|n| n.checked_mul(self.header_size))
+            .ok_or_else(|| anyhow::anyhow!("Truncated VTK data block"))?;
+        for block in 0..blocks {
+            if out.len() >= limit {
+                break;
+            }
+            let compressed_len = self.header(bytes, 3 + block)?;
+            let input = pos
+                .checked_add(compressed_len)
+                .and_then(|end| bytes.get(pos..end))
+                .ok_or_else(|| anyhow::anyhow!("Truncated VTK data block"))?;
+            pos += compressed_len;
+
+            let size = if block + 1 == blocks && last_size != 0 { last_size } else { block_size };
+            if size > limit - out.len() {
+                return Err(anyhow::anyhow!("VTK data blocks hold more than the {} bytes expected", limit));
+            }
+            match compressor {
+                "vtkZLibDataCompressor" => {
+                    ZlibDecoder::new(input).take(size as u64).read_to_end(&mut out)?;
+                },
+                _ => {
+                    let decoded = lz4_flex::block::decompress(input, size)
+                        .map_err(|e| anyhow::anyhow!("Invalid VTK LZ4 block: {}", e))?;
+                    out.extend_from_slice(&decoded);
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn parse_xml_type(value: &str) -> Result<SampleType> {
+    let sample_type = match value {
+        "Int8" | "Char" => SampleType::I8,
+        "UInt8" | "UChar" => SampleType::U8,
+        "Int16" | "Short" => SampleType::I16,
+        "UInt16" | "UShort" => SampleType::U16,
+        "Int32" | "Int" => SampleType::I32,
+        "UInt32" | "UInt" => SampleType::U32,
+        "Int64" | "Long" => SampleType::I64,
+        "UInt64" | "ULong" => SampleType::U64,
+        "Float32" | "Float" => SampleType::F32,
+        "Float64" | "Double" => SampleType::F64,
+        _ => return Err(anyhow::anyhow!("Unsupported VTK DataArray type: {}", value)),
+    };
+    Ok(sample_type)
+}
+
+fn parse_numbers(key: &str, value: &str) -> Result<Vec<f32>> {
+    value
+        .split_whitespace()
+        .map(|v| v.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow::anyhow!("Invalid VTK {}: {}", key, value))
+}
+
+fn parse_extent(value: &str) -> Result<[i64; 6]> {
+    let values: Vec<i64> = value
+        .split_whitespace()
+        .map(|v| v.parse::<i64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow::anyhow!("Invalid VTK extent: {}", value))?;
+    let extent: [i64; 6] = values
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid VTK extent: {}", value))?;
+    if extent[1] < extent[0] || extent[3] < extent[2] || extent[5] < extent[4] {
+        return Err(anyhow::anyhow!("Invalid VTK extent: {}", value));
+    }
+    Ok(extent)
+}
+
+fn extent_dimensions(extent: &[i64; 6]) -> (usize, usize, usize) {
+    (
+        (extent[1] - extent[0] + 1) as usize,
+        (extent[3] - extent[2] + 1) as usize,
+        (extent[5] - extent[4] + 1) as usize,
+    )
+}
+
+// The appended section holds raw bytes, so it is cut off before the XML is parsed
+fn split_appended(data: &[u8]) -> (&[u8], Option<(&[u8], bool)>) {
+    let Some(tag) = data.windows(13).position(|w| w == b"<AppendedData") else {
+        return (data, None);
+    };
+    let Some(tag_end) = data[tag..].iter().position(|&b| b == b'>').map(|i| tag + i + 1) else {
+        return (data, None);
+    };
+    let Some(marker) = data[tag_end..].iter().position(|&b| b == b'_').map(|i| tag_end + i + 1) else {
+        return (data, None);
+    };
+
+    let base64 = String::from_utf8_lossy(&data[tag..tag_end]).contains("base64");
+    (&data[..tag], Some((&data[marker..], base64)))
+}
+
+pub fn load_vti_from_memory(data: &[u8]) -> Result<VtkVolume> {
+    let (xml_bytes, appended) = split_appended(data);
+    let mut xml = std::str::from_utf8(xml_bytes)
+        .map_err(|_| anyhow::anyhow!("VTK XML header is not valid text"))?
+        .to_string();
+    if appended.is_some() {
+        xml.push_str("</VTKFile>");
+    }
+
+    let doc = roxmltree::Document::parse(&xml)
+        .map_err(|e| anyhow::anyhow!("Invalid VTK XML: {}", e))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "VTKFile" || root.attribute("type") != Some("ImageData") {
+        return Err(anyhow::anyhow!("Not a VTK ImageData file"));
+    }
+
+    let compressor = match root.attribute("compressor") {
+        None | Some("") => None,
+        Some("vtkZLibDataCompressor") => Some("vtkZLibDataCompressor"),
+        Some("vtkLZ4DataCompressor") => Some("vtkLZ4DataCompressor"),
+        Some(other) => return Err(anyhow::anyhow!("Unsupported VTK compressor: {}", other)),
+    };
+    let format = BlockFormat {
+        header_size: if root.attribute("header_type") == Some("UInt64") { 8 } else { 4 },
+        endian: if root.attribute("byte_order") == Some("BigEndian") { Endian::Big } else { Endian::Little },
+        compressor,
+    };
+
+    let image = root
+        .children()
+        .find(|n| n.tag_name().name() == "ImageData")
+        .ok_or_else(|| anyhow::anyhow!("VTK file has no ImageData element"))?;
+
+    let whole_extent = parse_extent(image.attribute("WholeExtent").unwrap_or_default())?;
+    let dimensions = extent_dimensions(&whole_extent);
+    let (width, height, depth) = dimensions;
+    let count = volume_size(dimensions)?;
+
+    let mut spacing = [1.0f32; 3];
+    if let Some(values) = image.attribute("Spacing") {
+        spacing.copy_from_slice(&parse_vector("Spacing", &values.split_whitespace().collect::<Vec<_>>())?);
+    }
+    let mut origin = [0.0f32; 3];
+    if let Some(values) = image.attribute("Origin") {
+        origin.copy_from_slice(&parse_vector("Origin", &values.split_whitespace().collect::<Vec<_>>())?);
+    }
+
+    // Direction is a row-major index-to-world matrix; its columns are the axis directions
+    let mut directions = IDENTITY;
+    if let Some(values) = image.attribute("Direction") {
+        let matrix = parse_numbers("Direction", values)?;
+        if matrix.len() == 9 {
+            for (axis, direction) in directions.iter_mut().enumerate() {
+                for (i, component) in direction.iter_mut().enumerate() {
+                    *component = matrix[i * 3 + axis];
+                }
+            }
+        }
+    }
+
+    // Origin is the world position of index (0, 0, 0), which may lie outside the extent
+    for axis in 0..3 {
+        let offset = whole_extent[axis * 2] as f32 * spacing[axis];
+        for i in 0..3 {
+            origin[i] += directions[axis][i] * offset;
+        }
+    }
+
+    let mut scalars_name: Option<String> = None;
+    let mut layout: Option<(SampleType, usize)> = None;
+    let mut volume: Vec<f32> = Vec::new();
+
+    for piece in image.children().filter(|n| n.tag_name().name() == "Piece") {
+        let extent = match piece.attribute("Extent") {
+            Some(extent) => parse_extent(extent)?,
+            None => whole_extent,
+        };
+        let inside = (0..3).all(|a| extent[a * 2] >= whole_extent[a * 2] && extent[a * 2 + 1] <= whole_extent[a * 2 + 1]);
+        if !inside {
+            return Err(anyhow::anyhow!("VTK piece extent lies outside WholeExtent"));
+        }
+
+        let Some(point_data) = piece.children().find(|n| n.tag_name().name() == "PointData") else {
+            continue;
+        };
+
+        let wanted = scalars_name.clone().or_else(|| point_data.attribute("Scalars").map(str::to_string));
+        let arrays: Vec<roxmltree::Node> = point_data
+            .children()
+            .filter(|n| n.tag_name().name() == "DataArray")
+            .collect();
+        let array = match &wanted {
+            Some(name) => arrays.iter().find(|a| a.attribute("Name") == Some(name.as_str())),
+            None => arrays.first(),
+        };
+        let Some(array) = array else {
+            continue;
+        };
+        scalars_name = array.attribute("Name").map(str::to_string);
+
+        let sample_type = parse_xml_type(array.attribute("type").unwrap_or_default())?;
+        let components = array.attribute("NumberOfComponents")
+            .map(|n| n.parse::<usize>().map_err(|_| anyhow::anyhow!("Invalid VTK NumberOfComponents: {}", n)))
+            .transpose()?
+            .unwrap_or(1)
+            .max(1);
+
+        match layout {
+            None => {
+                layout = Some((sample_type, components));
+                let total = count
+                    .checked_mul(components)
+                    .filter(|&n| n <= crate::MAX_SIZE)
+                    .ok_or_else(|| anyhow::anyhow!("Image data too large to fit in memory"))?;
+                volume = vec![0.0; total];
+            },
+            Some(existing) if existing != (sample_type, components) => {
+                return Err(anyhow::anyhow!("VTK pieces disagree on the scalar array type"));
+            },
+            Some(_) => {},
+        }
+
+        let piece_dims = extent_dimensions(&extent);
+        let piece_points = piece_dims.0 * piece_dims.1 * piece_dims.2;
+        let values_needed = piece_points * components;
+        let bytes_needed = values_needed * sample_type.size();
+
+        let values = match array.attribute("format").unwrap_or("ascii") {
+            "ascii" => {
+                let values = parse_numbers("DataArray", array.text().unwrap_or_default())?;
+                if values.len() < values_needed {
+                    return Err(anyhow::anyhow!("Not enough samples in VTK ascii data"));
+                }
+                values
+            },
+            "binary" => {
+                let bytes = decode_base64(array.text().unwrap_or_default().as_bytes())?;
+                sample::decode_samples(&format.read(&bytes, bytes_needed)?, sample_type, format.endian, values_needed)?
+            },
+            "appended" => {
+                let (section, base64) = appended
+                    .ok_or_else(|| anyhow::anyhow!("VTK file has no AppendedData section"))?;
+                let offset = array.attribute("offset")
+                    .and_then(|o| o.trim().parse::<usize>().ok())
+                    .filter(|&o| o <= section.len())
+                    .ok_or_else(|| anyhow::anyhow!("Invalid VTK appended data offset"))?;
+                let block = if base64 {
+                    format.read(&decode_base64(&section[offset..])?, bytes_needed)?
+                } else {
+                    format.read(&section[offset..], bytes_needed)?
+                };
+                sample::decode_samples(&block, sample_type, format.endian, values_needed)?
+            },
+            other => return Err(anyhow::anyhow!("Unsupported VTK DataArray format: {}", other)),
+        };
+
+        // Place the piece into the whole extent, keeping components interleaved for now
+        let x0 = (extent[0] - whole_extent[0]) as usize;
+        let y0 = (extent[2] - whole_extent[2]) as usize;
+        let z0 = (extent[4] - whole_extent[4]) as usize;
+        let row = piece_dims.0 * components;
+        for z in 0..piece_dims.2 {
+            for y in 0..piece_dims.1 {
+                let src = (z * piece_dims.1 + y) * row;
+                let dst = (((z0 + z) * height + y0 + y) * width + x0) * components;
+                volume[dst..dst + row].copy_from_slice(&values[src..src + row]);
+            }
+        }
+    }
+
+    let (sample_type, components) = layout
+        .ok_or_else(|| anyhow::anyhow!("VTK file has no point data scalars"))?;
+
+    info!(
+        "Loaded VTK image data: {}x{}x{} ({:?}, {} components) from {}",
+        width, height, depth, sample_type, components, scalars_name.unwrap_or_default()
+    );
+    debug!("VTK spacing: {:?}, origin: {:?}", spacing, origin);
+
+    Ok(VtkVolume {
+        data: split_components(volume, components),
+        dimensions,
+        channels: components,
+        sample_type,
+        spacing,
+        origin,
+        directions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    fn value(x: usize, y: usize, z: usize) -> i16 {
+        (z * 100 + y * 10 + x) as i16
+    }
+
+    #[test]
+    fn legacy_binary_with_two_components() {
+        let mut data = b"# vtk DataFile Version 3.0\ntest\nBINARY\nDATASET STRUCTURED_POINTS\n\
+            DIMENSIONS 3 2 2\nSPACING 0.5 0.5 2\nORIGIN 1 2 3\nPOINT_DATA 12\n\
+            SCALARS values short 2\nLOOKUP_TABLE default\n".to_vec();
+        for i in 0..12 {
+            let v = value(i % 3, i / 3 % 2, i / 6);
+            data.extend_from_slice(&v.to_be_bytes());
+            data.extend_from_slice(&(-v).to_be_bytes());
+        }
+
+        let vtk = load_vtk_legacy_from_memory(&data).unwrap();
+        assert_eq!(vtk.dimensions, (3, 2, 2));
+        assert_eq!(vtk.channels, 2);
+        assert_eq!(vtk.spacing, [0.5, 0.5, 2.0]);
+        assert_eq!(vtk.origin, [1.0, 2.0, 3.0]);
+        assert_eq!(vtk.data[4], 11.0);
+        assert_eq!(vtk.data[12 + 11], -112.0);
+    }
+
+    #[test]
+    fn vti_appended_zlib_blocks() {
+        let raw: Vec<u8> = (0..12).flat_map(|i| value(i % 3, i / 3 % 2, i / 6).to_le_bytes()).collect();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw[..16]).unwrap();
+        let first = encoder.finish().unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw[16..]).unwrap();
+        let second = encoder.finish().unwrap();
+
+        let mut data = br#"<?xml version="1.0"?>
+<VTKFile type="ImageData" version="1.0" byte_order="LittleEndian" header_type="UInt64" compressor="vtkZLibDataCompressor">
+  <ImageData WholeExtent="1 3 0 1 0 1" Origin="0 0 0" Spacing="1 1 1" Direction="0 -1 0 1 0 0 0 0 1">
+    <Piece Extent="1 3 0 1 0 1">
+      <PointData Scalars="values">
+        <DataArray type="Int16" Name="values" format="appended" offset="0"/>
+      </PointData>
+    </Piece>
+  </ImageData>
+  <AppendedData encoding="raw">
+   _"#.to_vec();
+        for header in [2, 16, 8, first.len(), second.len()] {
+            data.extend_from_slice(&(header as u64).to_le_bytes());
+        }
+        data.extend_from_slice(&first);
+        data.extend_from_slice(&second);
+        data.extend_from_slice(b"\n  </AppendedData>\n</VTKFile>\n");
+
+        assert!(is_vti(&data));
+        let vtk = load_vti_from_memory(&data).unwrap();
+        assert_eq!(vtk.dimensions, (3, 2, 2));
+        assert_eq!(vtk.data[4], 11.0);
+        assert_eq!(vtk.data[11], 112.0);
+        assert_eq!(vtk.directions, [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert_eq!(vtk.origin, [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn block_headers_are_exact_integers() {
+        let format = BlockFormat { header_size: 8, endian: Endian::Big, compressor: None };
+        assert_eq!(format.header(&16_777_217u64.to_be_bytes(), 0).unwrap(), 16_777_217);
+        assert!(format.header(&[0; 8], usize::MAX).is_err());
+
+        // A block count that would overflow the header offsets
+        let format = BlockFormat { compressor: Some("vtkZLibDataCompressor"), ..format };
+        let bytes: Vec<u8> = [u64::MAX, 16, 16].iter().flat_map(|v| v.to_be_bytes()).collect();
+        assert!(format.read(&bytes, 16).is_err());
+    }
+}
@@ -0,0 +1,618 @@
+use anyhow::Result;
+use std::io::Read;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use log::{debug, info};
+use serde_json::Value;
+use crate::blosc;
+use crate::chunk_store::ChunkStore;
+use crate::sample::{self, Endian, SampleType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compressor {
+    None,
+    Zlib,
+    Gzip,
+    Zstd,
+    Blosc,
+}
+
+#[derive(Clone, Debug)]
+pub struct ZarrArray {
+    pub shape: Vec<usize>,
+    pub chunks: Vec<usize>,
+    pub sample_type: SampleType,
+    endian: Endian,
+    compressor: Compressor,
+    fill_value: f32,
+    fortran_order: bool,
+    separator: String,
+}
+
+#[derive(Clone, Debug)]
+struct Axis {
+    name: String,
+    unit: Option<String>,
+}
+
+pub struct ZarrVolume {
+    // Timepoint-major, then channel-major, like VolumeData
+    pub data: Vec<f32>,
+    pub dimensions: (usize, usize, usize),
+    pub channels: usize,
+    // Timepoints held in data, which can be fewer than the image has
+    pub timepoints: usize,
+    pub total_timepoints: usize,
+    pub sample_type: SampleType,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    pub unit: Option<String>,
+    // Number of resolution levels in the multiscale pyramid
+    pub levels: usize,
+}
+
+fn join_key(path: &str, name: &str) -> String {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+fn read_json(store: &dyn ChunkStore, key: &str) -> Result<Option<Value>> {
+    match store.get(key)? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid JSON in {}: {}", key, e)),
+        None => Ok(None),
+    }
+}
+
+fn parse_dtype(dtype: &str) -> Result<(SampleType, Endian)> {
+    let endian = match dtype.chars().next() {
+        Some('>') => Endian::Big,
+        Some('<') | Some('|') => Endian::Little,
+        _ => return Err(anyhow::anyhow!("Unsupported Zarr dtype: {}", dtype)),
+    };
+
+    let sample_type = match &dtype[1..] {
+        "u1" | "b1" => SampleType::U8,
+        "i1" => SampleType::I8,
+        "u2" => SampleType::U16,
+        "i2" => SampleType::I16,
+        "u4" => SampleType::U32,
+        "i4" => SampleType::I32,
+        "u8" => SampleType::U64,
+        "i8" => SampleType::I64,
+        "f4" => SampleType::F32,
+        "f8" => SampleType::F64,
+        _ => return Err(anyhow::anyhow!("Unsupported Zarr dtype: {}", dtype)),
+    };
+
+    Ok((sample_type, endian))
+}
+
+fn parse_usize_list(value: Option<&Value>, name: &str) -> Result<Vec<usize>> {
+    value
+        .and_then(Value::as_array)
+        .and_then(|values| values.iter().map(|v| v.as_u64().map(|v| v as usize)).collect())
+        .ok_or_else(|| anyhow::anyhow!("Invalid or missing Zarr {}", name))
+}
+
+fn parse_f32_list(value: Option<&Value>) -> Option<Vec<f32>> {
+    value
+        .and_then(Value::as_array)
+        .and_then(|values| values.iter().map(|v| v.as_f64().map(|v| v as f32)).collect())
+}
+
+fn parse_fill_value(value: Option<&Value>) -> f32 {
+    match value {
+        Some(Value::Number(n)) => n.as_f64().unwrap_or(0.0) as f32,
+        Some(Value::String(s)) => match s.as_str() {
+            "NaN" => f32::NAN,
+            "Infinity" => f32::INFINITY,
+            "-Infinity" => f32::NEG_INFINITY,
+            _ => 0.0,
+        },
+        Some(Value::Bool(true)) => 1.0,
+        _ => 0.0,
+    }
+}
+
+impl ZarrArray {
+    pub fn open(store: &dyn ChunkStore, path: &str) -> Result<Self> {
+        let key = join_key(path, ".zarray");
+        let meta = read_json(store, &key)?
+            .ok_or_else(|| anyhow::anyhow!("Zarr array metadata {} not found", key))?;
+
+        match meta.get("zarr_format").and_then(Value::as_u64) {
+            Some(2) => {},
+            other => return Err(anyhow::anyhow!("Unsupported Zarr format: {:?}", other)),
+        }
+
+        let shape = parse_usize_list(meta.get("shape"), "shape")?;
+        let chunks = parse_usize_list(meta.get("chunks"), "chunks")?;
+        if shape.len() != chunks.len() || chunks.contains(&0) {
+            return Err(anyhow::anyhow!("Zarr chunks do not match array shape"));
+        }
+        let chunk_len = chunks.iter().try_fold(1usize, |acc, &n| acc.checked_mul(n));
+        if chunk_len.filter(|&n| n <= crate::MAX_SIZE).is_none() {
+            return Err(anyhow::anyhow!("Zarr chunks {:?} are too large", chunks));
+        }
+
+        let dtype = meta.get("dtype").and_then(Value::as_str)
+            .ok_or_else(|| anyhow::anyhow!("Zarr dtype must be a simple type string"))?;
+        let (sample_type, endian) = parse_dtype(dtype)?;
+
+        let compressor = match meta.get("compressor") {
+            None | Some(Value::Null) => Compressor::None,
+            Some(config) => match config.get("id").and_then(Value::as_str) {
+                Some("zlib") => Compressor::Zlib,
+                Some("gzip") => Compressor::Gzip,
+                Some("zstd") => Compressor::Zstd,
+                Some("blosc") => Compressor::Blosc,
+                id => return Err(anyhow::anyhow!("Unsupported Zarr compressor: {:?}", id)),
+            },
+        };
+
+        let has_filters = meta.get("filters")
+            .and_then(Value::as_array)
+            .is_some_and(|filters| !filters.is_empty());
+        if has_filters {
+            return Err(anyhow::anyhow!("Zarr filters are not supported"));
+        }
+
+        let fortran_order = match meta.get("order").and_then(Value::as_str) {
+            Some("F") => true,
+            Some("C") | None => false,
+            Some(order) => return Err(anyhow::anyhow!("Invalid Zarr order: {}", order)),
+        };
+
+        let separator = meta.get("dimension_separator")
+            .and_then(Value::as_str)
+            .unwrap_or(".")
+            .to_string();
+
+        debug!("Zarr array {}: shape {:?}, chunks {:?}, {}", path, shape, chunks, dtype);
+
+        Ok(Self {
+            shape,
+            chunks,
+            sample_type,
+            endian,
+            compressor,
+            fill_value: parse_fill_value(meta.get("fill_value")),
+            fortran_order,
+            separator,
+        })
+    }
+
+    fn decode_chunk(&self, bytes: &[u8]) -> Result<Vec<f32>> {
+        let count: usize = self.chunks.iter().product();
+        // Nothing past a full chunk is ever read, so inflation stops there
+        let chunk_bytes = count * self.sample_type.size();
+
+        let decompressed;
+        let raw = match self.compressor {
+            Compressor::None => bytes,
+            Compressor::Zlib => {
+                let mut buf = Vec::new();
+                ZlibDecoder::new(bytes).take(chunk_bytes as u64).read_to_end(&mut buf)?;
+                decompressed = buf;
+                &decompressed[..]
+            },
+            Compressor::Gzip => {
+                let mut buf = Vec::new();
+                GzDecoder::new(bytes).take(chunk_bytes as u64).read_to_end(&mut buf)?;
+                decompressed = buf;
+                &decompressed[..]
+            },
+            Compressor::Zstd => {
+                decompressed = blosc::decompress_zstd(bytes, chunk_bytes)?;
+                &decompressed[..]
+            },
+            Compressor::Blosc => {
+                decompressed = blosc::decompress(bytes, chunk_bytes)?;
+                &decompressed[..]
+            },
+        };
+
+        sample::decode_samples(raw, self.sample_type, self.endian, count)
+    }
+
+    // Reads the box [start, stop) into a C-ordered buffer
+    pub fn read_region(&self, store: &dyn ChunkStore, path: &str, start: &[usize], stop: &[usize]) -> Result<Vec<f32>> {
+        let ndim = self.shape.len();
+        if start.len() != ndim || stop.len() != ndim {
+            return Err(anyhow::anyhow!("Zarr region does not match array dimensions"));
+        }
+        if (0..ndim).any(|d| start[d] >= stop[d] || stop[d] > self.shape[d]) {
+            return Err(anyhow::anyhow!("Zarr region out of bounds"));
+        }
+
+        let region: Vec<usize> = (0..ndim).map(|d| stop[d] - start[d]).collect();
+        let total = region.iter().try_fold(1usize, |acc, &n| acc.checked_mul(n))
+            .filter(|&n| n <= crate::MAX_SIZE)
+            .ok_or_else(|| anyhow::anyhow!("Image data too large to fit in memory"))?;
+
+        let mut out = vec![self.fill_value; total];
+
+        let region_strides = c_strides(&region);
+        let chunk_strides = if self.fortran_order {
+            f_strides(&self.chunks)
+        } else {
+            c_strides(&self.chunks)
+        };
+
+        let first_chunk: Vec<usize> = (0..ndim).map(|d| start[d] / self.chunks[d]).collect();
+        let last_chunk: Vec<usize> = (0..ndim).map(|d| (stop[d] - 1) / self.chunks[d]).collect();
+
+        let mut chunk_index = first_chunk.clone();
+        loop {
+            let key = chunk_index.iter().map(usize::to_string).collect::<Vec<_>>().join(&self.separator);
+            let key = join_key(path, &key);
+
+            if let Some(bytes) = store.get(&key)? {
+                let chunk = self.decode_chunk(&bytes)
+                    .map_err(|e| anyhow::anyhow!("Zarr chunk {}: {}", key, e))?;
+
+                // Overlap of this chunk with the region, in array coordinates
+                let lo: Vec<usize> = (0..ndim).map(|d| start[d].max(chunk_index[d] * self.chunks[d])).collect();
+                let hi: Vec<usize> = (0..ndim).map(|d| stop[d].min((chunk_index[d] + 1) * self.chunks[d])).collect();
+
+                let mut pos = lo.clone();
+                loop {
+                    let mut src = 0;
+                    let mut dst = 0;
+                    for d in 0..ndim {
+                        src += (pos[d] - chunk_index[d] * self.chunks[d]) * chunk_strides[d];
+                        dst += (pos[d] - start[d]) * region_strides[d];
+                    }
+                    out[dst] = chunk[src];
+
+                    if !advance(&mut pos, &lo, &hi) {
+                        break;
+                    }
+                }
+            }
+
+            let next_chunk: Vec<usize> = last_chunk.iter().map(|&c| c + 1).collect();
+            if !advance(&mut chunk_index, &first_chunk, &next_chunk) {
+                break;
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+fn c_strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for d in (0..shape.len().saturating_sub(1)).rev() {
+        strides[d] = strides[d + 1] * shape[d + 1];
+    }
+    strides
+}
+
+fn f_strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for d in 1..shape.len() {
+        strides[d] = strides[d - 1] * shape[d - 1];
+    }
+    strides
+}
+
+// Steps a C-ordered index through [lo, hi); returns false once it wraps around
+fn advance(pos: &mut [usize], lo: &[usize], hi: &[usize]) -> bool {
+    for d in (0..pos.len()).rev() {
+        pos[d] += 1;
+        if pos[d] < hi[d] {
+            return true;
+        }
+        pos[d] = lo[d];
+    }
+    false
+}
+
+// OME-NGFF spells units out in UDUNITS-2 names; the crate keeps the symbols OME-XML uses
+fn unit_symbol(name: &str) -> String {
+    let symbol = match name {
+        "angstrom" => "Å",
+        "nanometer" => "nm",
+        "micrometer" | "micron" => "µm",
+        "millimeter" => "mm",
+        "centimeter" => "cm",
+        "decimeter" => "dm",
+        "meter" => "m",
+        "kilometer" => "km",
+        "picometer" => "pm",
+        "femtometer" => "fm",
+        "inch" => "in",
+        "foot" => "ft",
+        "yard" => "yd",
+        "mile" => "mi",
+        other => other,
+    };
+    symbol.to_string()
+}
+
+fn parse_axes(multiscale: &Value, ndim: usize) -> Result<Vec<Axis>> {
+    let axes = match multiscale.get("axes").and_then(Value::as_array) {
+        // OME-NGFF 0.3 lists names, 0.4 and later list objects
+        Some(axes) => axes
+            .iter()
+            .map(|axis| match axis {
+                Value::String(name) => Some(Axis { name: name.clone(), unit: None }),
+                Value::Object(_) => Some(Axis {
+                    name: axis.get("name")?.as_str()?.to_string(),
+                    unit: axis.get("unit").and_then(Value::as_str).map(unit_symbol),
+                }),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow::anyhow!("Invalid OME-NGFF axes"))?,
+        // Before 0.3 the axes are always a suffix of t, c, z, y, x
+        None => ["t", "c", "z", "y", "x"][5usize.saturating_sub(ndim)..]
+            .iter()
+            .map(|name| Axis { name: name.to_string(), unit: None })
+            .collect(),
+    };
+
+    if axes.len() != ndim {
+        return Err(anyhow::anyhow!("OME-NGFF axes do not match the array dimensions"));
+    }
+    Ok(axes)
+}
+
+// Combines a list of coordinateTransformations into one scale and translation
+fn apply_transforms(transforms: Option<&Value>, scale: &mut [f32], translation: &mut [f32]) {
+    let Some(transforms) = transforms.and_then(Value::as_array) else {
+        return;
+    };
+
+    for transform in transforms {
+        match transform.get("type").and_then(Value::as_str) {
+            Some("scale") => {
+                if let Some(values) = parse_f32_list(transform.get("scale")).filter(|v| v.len() == scale.len()) {
+                    for d in 0..scale.len() {
+                        scale[d] *= values[d];
+                        translation[d] *= values[d];
+                    }
+                }
+            },
+            Some("translation") => {
+                if let Some(values) = parse_f32_list(transform.get("translation")).filter(|v| v.len() == translation.len()) {
+                    for (t, v) in translation.iter_mut().zip(values) {
+                        *t += v;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+pub fn load_zarr(store: &dyn ChunkStore, level: usize) -> Result<ZarrVolume> {
+    let attrs = read_json(store, ".zattrs")?;
+    let multiscale = attrs
+        .as_ref()
+        .and_then(|attrs| attrs.get("multiscales"))
+        .and_then(Value::as_array)
+        .and_then(|multiscales| multiscales.first());
+
+    // A bare array at the root of the store has a single resolution level
+    let (path, levels, dataset) = match multiscale {
+        Some(multiscale) => {
+            let datasets = multiscale.get("datasets")
+                .and_then(Value::as_array)
+                .filter(|datasets| !datasets.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("OME-NGFF multiscales has no datasets"))?;
+            let dataset = datasets.get(level)
+                .ok_or_else(|| anyhow::anyhow!("Resolution level {} not found, the image has {}", level, datasets.len()))?;
+            let path = dataset.get("path").and_then(Value::as_str)
+                .ok_or_else(|| anyhow::anyhow!("OME-NGFF dataset missing path"))?;
+            (path.to_string(), datasets.len(), Some(dataset))
+        },
+        None if level == 0 => (String::new(), 1, None),
+        None => return Err(anyhow::anyhow!("Resolution level {} not found, the image has 1", level)),
+    };
+
+    let array = ZarrArray::open(store, &path)?;
+    let ndim = array.shape.len();
+
+    let axes = match multiscale {
+        Some(multiscale) => parse_axes(multiscale, ndim)?,
+        None => parse_axes(&Value::Null, ndim)?,
+    };
+    let find = |name: &str| axes.iter().position(|axis| axis.name.eq_ignore_ascii_case(name));
+
+    let x = find("x").ok_or_else(|| anyhow::anyhow!("Zarr image has no x axis"))?;
+    let y = find("y").ok_or_else(|| anyhow::anyhow!("Zarr image has no y axis"))?;
+    let z = find("z");
+    let c = find("c");
+    let t = find("t");
+
+    let extent = |axis: Option<usize>| axis.map(|a| array.shape[a]).unwrap_or(1);
+    let width = array.shape[x];
+    let height = array.shape[y];
+    let depth = extent(z);
+    let channels = extent(c);
+    let total_timepoints = extent(t);
+
+    // Timepoints past the voxel limit are left out
+    let per_timepoint = [width, height, depth, channels]
+        .iter()
+        .try_fold(1usize, |acc, &n| acc.checked_mul(n))
+        .filter(|&n| n <= crate::MAX_SIZE)
+        .ok_or_else(|| anyhow::anyhow!("Image data too large to fit in memory"))?;
+    let timepoints = total_timepoints.min(crate::MAX_SIZE / per_timepoint.max(1)).max(1);
+    if timepoints < total_timepoints {
+        info!("Zarr image has {} timepoints, loading the first {}", total_timepoints, timepoints);
+    }
+
+    // Any axis that is not x, y, z, c or t is read at index 0
+    let start = vec![0; ndim];
+    let stop: Vec<usize> = (0..ndim)
+        .map(|d| match d {
+            d if Some(d) == t => timepoints,
+            d if [Some(x), Some(y), z, c].contains(&Some(d)) => array.shape[d],
+            _ => 1,
+        })
+        .collect();
+
+    let region = array.read_region(store, &path, &start, &stop)?;
+
+    // Reorder into t, c, z, y, x regardless of the stored axis order
+    let strides = c_strides(&stop);
+    let stride = |axis: Option<usize>| axis.map(|a| strides[a]).unwrap_or(0);
+    let (sx, sy, sz, sc, st) = (strides[x], strides[y], stride(z), stride(c), stride(t));
+
+    let mut data = Vec::with_capacity(region.len());
+    for ti in 0..timepoints {
+        for ci in 0..channels {
+            for zi in 0..depth {
+                for yi in 0..height {
+                    for xi in 0..width {
+                        data.push(region[ti * st + ci * sc + zi * sz + yi * sy + xi * sx]);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut scale = vec![1.0f32; ndim];
+    let mut translation = vec![0.0f32; ndim];
+    if let Some(dataset) = dataset {
+        apply_transforms(dataset.get("coordinateTransformations"), &mut scale, &mut translation);
+    }
+    if let Some(multiscale) = multiscale {
+        apply_transforms(multiscale.get("coordinateTransformations"), &mut scale, &mut translation);
+    }
+
+    let spatial = [Some(x), Some(y), z];
+    let mut spacing = [1.0f32; 3];
+    let mut origin = [0.0f32; 3];
+    for (i, axis) in spatial.iter().enumerate() {
+        if let Some(a) = *axis {
+            if scale[a] > 0.0 {
+                spacing[i] = scale[a];
+            }
+            origin[i] = translation[a];
+        }
+    }
+
+    info!(
+        "Loaded Zarr volume: {}x{}x{} with {} channels and {} timepoints (level {} of {}, {:?})",
+        width, height, depth, channels, timepoints, level, levels, array.sample_type
+    );
+    debug!("Zarr spacing: {:?}, origin: {:?}", spacing, origin);
+
+    Ok(ZarrVolume {
+        data,
+        dimensions: (width, height, depth),
+        channels,
+        timepoints,
+        total_timepoints,
+        sample_type: array.sample_type,
+        spacing,
+        origin,
+        unit: axes[x].unit.clone(),
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use crate::chunk_store::MemoryStore;
+
+    const ATTRS: &str = r#"{"multiscales":[{"version":"0.4",
+        "axes":[{"name":"c","type":"channel"},{"name":"z","type":"space","unit":"micrometer"},
+                {"name":"y","type":"space","unit":"micrometer"},{"name":"x","type":"space","unit":"micrometer"}],
+        "datasets":[{"path":"0","coordinateTransformations":[{"type":"scale","scale":[1,2.0,0.5,0.5]},
+                                                              {"type":"translation","translation":[0,1,1,1]}]}]}]}"#;
+
+    fn value(c: usize, z: usize, y: usize, x: usize) -> u16 {
+        (c * 100 + z * 20 + y * 4 + x) as u16
+    }
+
+    // c=2, z=3, y=5, x=4 stored as Fortran-ordered zlib chunks of 1x2x3x3, one chunk left out
+    fn store(chunks: &str) -> MemoryStore {
+        let mut store = MemoryStore::new();
+        store.insert(".zattrs", ATTRS.as_bytes().to_vec());
+        store.insert("0/.zarray", format!(
+            r#"{{"zarr_format":2,"shape":[2,3,5,4],"chunks":{},"dtype":"<u2","compressor":{{"id":"zlib","level":1}},
+                "fill_value":7,"order":"F","filters":null,"dimension_separator":"/"}}"#, chunks).into_bytes());
+
+        for c in 0..2 {
+            for zc in 0..2 {
+                for yc in 0..2 {
+                    for xc in 0..2 {
+                        if (c, zc, yc, xc) == (1, 1, 1, 1) {
+                            continue;
+                        }
+                        let mut raw = Vec::new();
+                        for xx in 0..3 {
+                            for yy in 0..3 {
+                                for zz in 0..2 {
+                                    let (z, y, x) = (zc * 2 + zz, yc * 3 + yy, xc * 3 + xx);
+                                    let v = if z < 3 && y < 5 && x < 4 { value(c, z, y, x) } else { 0 };
+                                    raw.extend_from_slice(&v.to_le_bytes());
+                                }
+                            }
+                        }
+                        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                        encoder.write_all(&raw).unwrap();
+                        store.insert(&format!("0/{}/{}/{}/{}", c, zc, yc, xc), encoder.finish().unwrap());
+                    }
+                }
+            }
+        }
+        store
+    }
+
+    #[test]
+    fn ngff_chunks_and_transforms() {
+        let volume = load_zarr(&store("[1,2,3,3]"), 0).unwrap();
+        assert_eq!(volume.dimensions, (4, 5, 3));
+        assert_eq!(volume.channels, 2);
+        assert_eq!(volume.levels, 1);
+        assert_eq!(volume.spacing, [0.5, 0.5, 2.0]);
+        assert_eq!(volume.origin, [1.0, 1.0, 1.0]);
+        assert_eq!(volume.unit.as_deref(), Some("µm"));
+
+        for (i, &got) in volume.data.iter().enumerate() {
+            let (c, z, y, x) = (i / 60, i / 20 % 3, i / 4 % 5, i % 4);
+            let missing = c == 1 && z >= 2 && y >= 3 && x >= 3;
+            assert_eq!(got, if missing { 7.0 } else { value(c, z, y, x) as f32 }, "voxel {}", i);
+        }
+
+        assert!(load_zarr(&store("[1,2,3,3]"), 1).is_err());
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        let err = load_zarr(&store("[1,100000,100000,100000]"), 0).err().unwrap();
+        assert!(err.to_string().contains("too large"), "{}", err);
+    }
+
+    #[test]
+    fn every_timepoint_is_read() {
+        // Plain v2 array, so the axes are t, c, z, y, x; one uncompressed chunk per timepoint
+        let mut store = MemoryStore::new();
+        store.insert(".zarray", br#"{"zarr_format":2,"shape":[3,2,1,2,2],"chunks":[1,2,1,2,2],"dtype":"|u1",
+            "compressor":null,"fill_value":0,"order":"C","filters":null}"#.to_vec());
+        for t in 0..3u8 {
+            let chunk: Vec<u8> = (0..8).map(|i| t * 100 + i).collect();
+            store.insert(&format!("{}.0.0.0.0", t), chunk);
+        }
+
+        let volume = load_zarr(&store, 0).unwrap();
+        assert_eq!((volume.channels, volume.timepoints, volume.total_timepoints), (2, 3, 3));
+        let expected: Vec<f32> = (0..3).flat_map(|t| (0..8).map(move |i| (t * 100 + i) as f32)).collect();
+        assert_eq!(volume.data, expected);
+    }
+}